@@ -0,0 +1,264 @@
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+use chrono::Utc;
+
+use crate::config::{self, ServerConfig};
+use crate::headers::HttpHeaders;
+use crate::http_error::{HttpError, http_errors};
+use crate::request::HttpRequest;
+
+pub const COMMON_FORMAT: &str = "%h %l %u %t \"%r\" %>s %b";
+pub const COMBINED_FORMAT: &str = "%h %l %u %t \"%r\" %>s %b \"%{Referer}i\" \"%{User-Agent}i\"";
+
+enum LogToken {
+    Literal(String),
+    Directive(char, Option<String>)
+}
+
+pub struct AccessLogFormat {
+    tokens: Vec<LogToken>
+}
+
+struct AccessLog {
+    file: File,
+    format: AccessLogFormat
+}
+
+pub struct AccessLogEntry<'a> {
+    pub request: &'a HttpRequest,
+    pub status: i32,
+    pub body_bytes: u64,
+    pub response_headers: Option<&'a HttpHeaders>
+}
+
+static ACCESS_LOG: Mutex<Option<AccessLog>> = Mutex::new(None);
+
+impl AccessLogFormat {
+    // Accepts "common", "combined" or an Apache style format string
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let format = match value {
+            "common" => COMMON_FORMAT,
+            "combined" => COMBINED_FORMAT,
+            format => format
+        };
+
+        let mut tokens = Vec::new();
+        let mut literal = String::new();
+        let mut chars = format.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                literal.push(c);
+                continue;
+            }
+
+            let mut argument = None;
+            if chars.peek() == Some(&'{') {
+                chars.next();
+                let name: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                argument = Some(name);
+            }
+
+            // %>s is Apache's final status, which is the only status we have
+            if chars.peek() == Some(&'>') {
+                chars.next();
+            }
+
+            let directive = chars.next().ok_or_else(|| format!("Access log format \"{}\" ends in a bare %", format))?;
+            if directive == '%' {
+                literal.push('%');
+                continue;
+            }
+
+            if !"abBDhHilmoqrsStTuU".contains(directive) {
+                return Err(format!("Unknown access log directive %{}", directive));
+            }
+
+            if (directive == 'i' || directive == 'o') && argument.is_none() {
+                return Err(format!("Access log directive %{} needs a header name, e.g. %{{Referer}}{}", directive, directive));
+            }
+
+            if !literal.is_empty() {
+                tokens.push(LogToken::Literal(std::mem::take(&mut literal)));
+            }
+
+            tokens.push(LogToken::Directive(directive, argument));
+        }
+
+        if !literal.is_empty() {
+            tokens.push(LogToken::Literal(literal));
+        }
+
+        Ok(Self { tokens })
+    }
+
+    pub fn format(&self, entry: &AccessLogEntry) -> String {
+        let request = entry.request;
+        let duration = Utc::now().signed_duration_since(request.start);
+        // The same redaction as transcripts, the access log must not leak what they hide
+        let redaction = &request.config.redaction;
+        let mut line = String::new();
+
+        for token in &self.tokens {
+            match token {
+                LogToken::Literal(text) => line.push_str(text),
+                LogToken::Directive(directive, argument) => {
+                    let value = match directive {
+                        'a' | 'h' => get_remote_ip(&request.who).to_string(),
+                        'b' => if entry.body_bytes > 0 { entry.body_bytes.to_string() } else { String::from("-") },
+                        'B' => entry.body_bytes.to_string(),
+                        'D' => duration.num_microseconds().unwrap_or(i64::MAX).to_string(),
+                        'T' => duration.num_seconds().to_string(),
+                        'H' => or_dash(&request.version),
+                        'l' => String::from("-"),
+                        'm' => or_dash(&request.method),
+                        'q' => request.query.as_ref().map(|query| redaction.redact_query(&format!("?{}", query)).to_string()).unwrap_or_default(),
+                        'r' => or_dash(&redaction.redact_query(&request.line)),
+                        's' => entry.status.to_string(),
+                        'S' => request.id.clone(),
+                        't' => request.start.format("[%d/%b/%Y:%H:%M:%S %z]").to_string(),
+                        'u' => get_remote_user(request),
+                        'U' => or_dash(&request.path),
+                        'i' => argument.as_ref().and_then(|name| Some(redaction.redact_header(name, request.headers.get(name)?))).map(|value| escape(&value)).unwrap_or(String::from("-")),
+                        'o' => argument.as_ref().and_then(|name| Some(redaction.redact_header(name, entry.response_headers?.get(name)?))).map(|value| escape(&value)).unwrap_or(String::from("-")),
+                        _ => String::from("-")
+                    };
+
+                    line.push_str(&value);
+                }
+            }
+        }
+
+        line
+    }
+}
+
+pub fn init(config: &ServerConfig) -> Result<(), HttpError> {
+    let access_log = match &config.access_log.path {
+        Some(path) => {
+            let format = AccessLogFormat::parse(&config.access_log.format).map_err(|msg| {
+                http_errors::msg::internal_server_error(msg.as_str()).set_info("Invalid access log format")
+            })?;
+
+            Some(AccessLog {
+                file: open_log_file(path)?,
+                format
+            })
+        },
+        None => None
+    };
+
+    *ACCESS_LOG.lock().unwrap_or_else(|e| e.into_inner()) = access_log;
+    Ok(())
+}
+
+// Reopens the file at its configured path, e.g. after logrotate moved it away
+pub fn reopen() -> Result<(), HttpError> {
+    init(&config::get_current())
+}
+
+// Logging must never fail a request, so write errors are only reported to stderr
+pub fn record(entry: &AccessLogEntry) {
+    let mut guard = ACCESS_LOG.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(access_log) = guard.as_mut() else {
+        return;
+    };
+
+    let line = access_log.format.format(entry);
+    if let Err(e) = writeln!(access_log.file, "{}", line) {
+        eprintln!("Failed to write access log: {}", e);
+    }
+}
+
+fn open_log_file(path: &Path) -> Result<File, HttpError> {
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent).map_err(|e| HttpError::convert_from(e, Some("Failed to create access log directory")))?;
+    }
+
+    OpenOptions::new().create(true).append(true).open(path)
+        .map_err(|e| HttpError::convert_from(e, Some("Failed to open access log")))
+}
+
+fn get_remote_ip(who: &str) -> &str {
+    who.rsplit_once(':').map(|(ip, _)| ip.trim_start_matches('[').trim_end_matches(']')).unwrap_or(who)
+}
+
+fn get_remote_user(request: &HttpRequest) -> String {
+    match request.headers.authorization() {
+        Ok(Some(authorization)) => authorization.basic_credentials().map(|(user, _)| escape(&user)).unwrap_or(String::from("-")),
+        _ => String::from("-")
+    }
+}
+
+fn or_dash(value: &str) -> String {
+    if value.is_empty() {
+        String::from("-")
+    } else {
+        escape(value)
+    }
+}
+
+// Keeps one entry per line and quoted fields parseable, like Apache does
+fn escape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if c.is_control() => result.push_str(&format!("\\x{:02x}", c as u32)),
+            c => result.push(c)
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::transcript::TranscriptLevel;
+
+    fn request(head: &str) -> HttpRequest {
+        let mut config = ServerConfig::new();
+        config.transcript.level = TranscriptLevel::Off;
+
+        let mut request = HttpRequest::new(String::from("[::1]:4242"), Arc::new(config)).unwrap();
+        request.read_head(&mut Cursor::new(head.as_bytes())).unwrap();
+        request
+    }
+
+    fn entry(request: &HttpRequest, status: i32, body_bytes: u64) -> AccessLogEntry<'_> {
+        AccessLogEntry { request, status, body_bytes, response_headers: None }
+    }
+
+    #[test]
+    fn formats_combined_entries() {
+        // alice:secret
+        let request = request("GET /a?b=1 HTTP/1.1\r\nHost: x\r\nAuthorization: Basic YWxpY2U6c2VjcmV0\r\nReferer: http://x/\"q\"\r\n\r\n");
+        let line = AccessLogFormat::parse("combined").unwrap().format(&entry(&request, 200, 12));
+
+        assert!(line.starts_with("::1 - alice ["), "{}", line);
+        assert!(line.ends_with("] \"GET /a?b=1 HTTP/1.1\" 200 12 \"http://x/\\\"q\\\"\" \"-\""), "{}", line);
+    }
+
+    #[test]
+    fn formats_custom_directives() {
+        let request = request("GET /form HTTP/1.1\r\nHost: x\r\n\r\n");
+        let format = AccessLogFormat::parse("%m %U%q %>s %b %B 100%% %{Host}i %{Server}o").unwrap();
+        assert_eq!(format.format(&entry(&request, 204, 0)), "GET /form 204 - 0 100% x -");
+    }
+
+    #[test]
+    fn rejects_invalid_formats() {
+        assert!(AccessLogFormat::parse("%h %").is_err());
+        assert!(AccessLogFormat::parse("%Z").is_err());
+        assert!(AccessLogFormat::parse("%i").is_err());
+    }
+}
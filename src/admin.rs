@@ -0,0 +1,235 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{SecondsFormat, Utc};
+
+use crate::access_log;
+use crate::config::{self, ServerConfig};
+use crate::connections;
+use crate::http_error::HttpError;
+use crate::json::JsonValue;
+use crate::metrics;
+use crate::transcript;
+use crate::transcript_writer;
+
+// Admin requests are tiny, anything bigger is not meant for this listener
+const MAX_HEAD_SIZE: u64 = 8 * 1024;
+
+const ACTIONS: [&str; 2] = ["/reload", "/reopen-logs"];
+
+// Set once the main listener is bound, cleared again when shutting down
+static ACCEPTING: AtomicBool = AtomicBool::new(false);
+
+struct AdminResponse {
+    status: &'static str,
+    content_type: &'static str,
+    body: String
+}
+
+impl AdminResponse {
+    fn text(status: &'static str, body: &str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.to_string()
+        }
+    }
+
+    fn json(status: &'static str, body: JsonValue) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: format!("{}\n", body)
+        }
+    }
+}
+
+pub fn set_accepting(accepting: bool) {
+    ACCEPTING.store(accepting, Ordering::Relaxed);
+}
+
+// Serves operational endpoints on their own listener, so they are never exposed next to the site.
+// Requests here are neither transcribed nor counted in the metrics.
+// Requests are answered from the current config, the listener itself only changes on restart.
+pub fn start(config: &ServerConfig) -> Result<(), HttpError> {
+    let Some(address) = config.admin.listen.as_deref() else {
+        return Ok(());
+    };
+
+    let listener = TcpListener::bind(address).map_err(|e| HttpError::convert_from(e, Some("Failed to bind admin listener")))?;
+    println!("Admin listening on {}", address);
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            thread::spawn(move || {
+                if let Err(e) = handle_admin(stream) {
+                    eprintln!("Failed to handle admin request: {}", e);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+fn handle_admin(mut stream: TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut reader = BufReader::new((&stream).take(MAX_HEAD_SIZE));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // Headers carry nothing the admin endpoints need, only skip past them
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 && !line.trim_end().is_empty() {
+        line.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("").split('?').next().unwrap_or("");
+
+    let response = route(method, path, &config::get_current());
+    let body = if method == "HEAD" { "" } else { response.body.as_str() };
+
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n", response.status, response.content_type, response.body.len())?;
+    if response.status.starts_with("405") {
+        let allow = if ACTIONS.contains(&path) { "POST" } else { "GET, HEAD" };
+        write!(stream, "Allow: {}\r\n", allow)?;
+    }
+
+    write!(stream, "\r\n{}", body)?;
+    stream.flush()
+}
+
+// Views answer GET and HEAD, actions change state and only answer POST
+fn route(method: &str, path: &str, config: &ServerConfig) -> AdminResponse {
+    let is_action = ACTIONS.contains(&path);
+    let allowed = if is_action { method == "POST" } else { method == "GET" || method == "HEAD" };
+    if !allowed {
+        return AdminResponse::text("405 Method Not Allowed", "Method not allowed\n");
+    }
+
+    match path {
+        "/healthz" => AdminResponse::text("200 OK", "ok\n"),
+        "/readyz" => get_readiness(),
+        "/config" => AdminResponse::json("200 OK", config.to_json()),
+        "/connections" => get_connections(),
+        "/reload" => reload(),
+        "/reopen-logs" => reopen_logs(),
+        path if path == config.metrics.path => AdminResponse {
+            status: "200 OK",
+            content_type: "text/plain; version=0.0.4; charset=utf-8",
+            body: metrics::render()
+        },
+        _ => AdminResponse::text("404 Not Found", "Not found\n")
+    }
+}
+
+// Ready while new connections are accepted and transcripts can still be written
+fn get_readiness() -> AdminResponse {
+    let mut problems = Vec::new();
+    if !ACCEPTING.load(Ordering::Relaxed) {
+        problems.push("not accepting connections");
+    }
+
+    if !transcript_writer::is_running() {
+        problems.push("transcript writer stopped");
+    }
+
+    if problems.is_empty() {
+        AdminResponse::text("200 OK", "ready\n")
+    } else {
+        AdminResponse::text("503 Service Unavailable", &format!("not ready: {}\n", problems.join(", ")))
+    }
+}
+
+fn get_connections() -> AdminResponse {
+    let now = Utc::now();
+    let connections: Vec<JsonValue> = connections::list().into_iter().map(|connection| {
+        let age = (now - connection.opened).num_milliseconds().max(0) as f64 / 1000.0;
+
+        JsonValue::object()
+            .with("id", connection.id)
+            .with("peer", connection.peer)
+            .with("opened", connection.opened.to_rfc3339_opts(SecondsFormat::Millis, true))
+            .with("age_seconds", age)
+            .with("request_id", connection.request_id)
+            .with("request_line", connection.request_line)
+    }).collect();
+
+    AdminResponse::json("200 OK", JsonValue::object().with("count", connections.len() as u64).with("connections", connections))
+}
+
+fn reload() -> AdminResponse {
+    match config::reload() {
+        Ok(restart) => {
+            println!("Config reloaded from the admin listener");
+            AdminResponse::json("200 OK", JsonValue::object().with("reloaded", true).with("restart_required", restart))
+        },
+        Err(e) => AdminResponse::json("500 Internal Server Error", JsonValue::object().with("reloaded", false).with("error", e.get_chain_msg()))
+    }
+}
+
+// For logrotate style tools that move files away and expect them to be recreated
+fn reopen_logs() -> AdminResponse {
+    let result = access_log::reopen().and_then(|_| transcript::reopen_shared_file());
+
+    match result {
+        Ok(()) => AdminResponse::json("200 OK", JsonValue::object().with("reopened", true)),
+        Err(e) => AdminResponse::json("500 Internal Server Error", JsonValue::object().with("reopened", false).with("error", e.get_chain_msg()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exchange(request: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        client.write_all(request.as_bytes()).unwrap();
+        handle_admin(server).unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn views_only_answer_get_and_head() {
+        let config = ServerConfig::new();
+        assert_eq!(route("GET", "/healthz", &config).body, "ok\n");
+        assert!(route("POST", "/healthz", &config).status.starts_with("405"));
+        assert!(route("GET", "/reload", &config).status.starts_with("405"));
+        assert!(route("GET", "/missing", &config).status.starts_with("404"));
+
+        let config_view = route("GET", "/config", &config);
+        assert_eq!(config_view.content_type, "application/json");
+        assert!(config_view.body.starts_with('{'));
+
+        let metrics = route("GET", &config.metrics.path, &config);
+        assert!(metrics.content_type.starts_with("text/plain; version=0.0.4"));
+    }
+
+    #[test]
+    fn head_requests_get_no_body() {
+        let response = exchange("HEAD /healthz HTTP/1.1\r\nHost: x\r\n\r\n");
+        assert_eq!(response, "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 3\r\nConnection: close\r\n\r\n");
+    }
+
+    #[test]
+    fn wrong_methods_get_an_allow_header() {
+        let response = exchange("DELETE /reopen-logs?now HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"), "{}", response);
+        assert!(response.contains("\r\nAllow: POST\r\n"));
+
+        let response = exchange("POST /readyz HTTP/1.1\r\n\r\n");
+        assert!(response.contains("\r\nAllow: GET, HEAD\r\n"));
+    }
+}
@@ -0,0 +1,290 @@
+use std::future::Future;
+use std::io;
+use std::net;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::runtime::Builder;
+use tokio::time::timeout;
+
+use crate::config::{self, Limits, ServerConfig};
+use crate::connections::{self, ConnectionHandle};
+use crate::head_parser::{HeadParser, HeadState};
+use crate::http_error::{describe_chain, HttpError, http_errors};
+use crate::io_util::write_error;
+use crate::mapped_file::{MappedFile, CHUNK_SIZE};
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+use crate::util::log_title;
+
+const READ_SIZE: usize = 4096;
+
+// Responses are rendered into memory by the blocking writers, then sent without blocking.
+// Mapped bodies are left out of that and sent straight from the mapping.
+pub type AsyncResponse = HttpResponse<Vec<u8>>;
+
+// Runs the async core on a tokio runtime with `workers` threads, answering with `serve_files`
+pub fn run(listener: net::TcpListener, workers: usize) -> io::Result<()> {
+    listener.set_nonblocking(true)?;
+
+    let runtime = Builder::new_multi_thread().worker_threads(workers).enable_all().build()?;
+    runtime.block_on(async move {
+        let listener = TcpListener::from_std(listener)?;
+        serve(listener, serve_files).await
+    })
+}
+
+// Accepts connections and answers each request with `handler`, which gets the response with its
+// request read and returns it with the answer set, like the static handler fills in HttpResponse
+pub async fn serve<H, F>(listener: TcpListener, handler: H) -> io::Result<()>
+where
+    H: Fn(AsyncResponse) -> F + Send + Sync + 'static,
+    F: Future<Output = AsyncResponse> + Send
+{
+    let handler = Arc::new(handler);
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                eprintln!("Connection failed: {:?}", e);
+                continue;
+            }
+        };
+
+        println!("New connection: {}", peer);
+
+        // Picked up per connection, so reloads apply to the next one
+        let config = config::get_current();
+        let handler = Arc::clone(&handler);
+
+        tokio::spawn(async move {
+            let connection = connections::track(peer.to_string());
+            let (reader, writer) = stream.into_split();
+
+            if let Err(e) = handle_client(reader, writer, peer.to_string(), config, &connection, &*handler).await {
+                eprintln!("{} Failed to handle client: {}", peer, describe_chain(&e));
+            }
+        });
+    }
+}
+
+// The async counterpart of main's handle_client, for any reader and writer pair
+pub async fn handle_client<R, W, H, F>(mut reader: R, mut writer: W, peer: String, config: Arc<ServerConfig>, connection: &ConnectionHandle, handler: &H) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+    H: Fn(AsyncResponse) -> F,
+    F: Future<Output = AsyncResponse>
+{
+    let idle_timeout = config.idle_timeout;
+
+    // Creating and recording to the transcript can wait on a full writer queue, so every step
+    // touching it runs on the blocking pool, up to dropping the request that ends it
+    let (request, title) = run_blocking(move || {
+        let request = HttpRequest::new(peer, config)?;
+        let title = log_title(&request.transcript, "HTTP Request");
+        Ok::<_, HttpError>((request, title))
+    }).await.map_err(|e| e.convert_to(Some("Failed to create HTTP Request")))?;
+
+    if let Err(http_err) = title {
+        return send_client_error(&mut writer, request, http_err).await;
+    }
+
+    connection.set_request(&request);
+    let parser = match read_head(&mut reader, &request.config.limits, idle_timeout).await {
+        Ok(parser) => parser,
+        Err(http_err) => return send_client_error(&mut writer, request, http_err).await
+    };
+
+    let (request, head) = run_blocking(move || {
+        let mut request = request;
+        let mut head = request.read_parsed_head(parser);
+        // Titled along with reading the head, saving a trip to the blocking pool
+        if head.is_ok() && request.is_init {
+            head = log_title(&request.transcript, "HTTP Response");
+        }
+
+        (request, head)
+    }).await;
+    connection.set_request(&request);
+
+    if let Err(http_err) = head {
+        return send_client_error(&mut writer, request, http_err).await;
+    }
+
+    // Closed before sending a request line, there is nobody to answer
+    if !request.is_init {
+        run_blocking(move || drop(request)).await;
+        return finish(&mut writer).await;
+    }
+
+    let mut response = HttpResponse::new(request, Vec::new());
+    response.headers.set("Connection", "close");
+
+    let response = handler(response).await;
+
+    let (mut response, head) = run_blocking(move || {
+        let mut response = response;
+        let head = response.flush_head();
+        (response, head)
+    }).await;
+
+    let sent = match head {
+        Ok((body_bytes, mapped)) => send_response(&mut writer, response.get_stream(), mapped).await.map(|_| body_bytes),
+        Err(e) => Err(e)
+    };
+
+    // The request, and with it the transcript, is kept until the response was sent
+    run_blocking(move || {
+        let body_bytes = sent?;
+        response.record(body_bytes);
+        Ok::<_, io::Error>(())
+    }).await?;
+
+    finish(&mut writer).await
+}
+
+// Routes and serves files like the threads core, on the blocking pool since file reads block
+pub async fn serve_files(response: AsyncResponse) -> AsyncResponse {
+    run_blocking(move || {
+        let mut response = response;
+        if !crate::apply_rules(&mut response) {
+            crate::serve_static(&mut response);
+        }
+
+        response
+    }).await
+}
+
+async fn run_blocking<T, F>(func: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static
+{
+    let result = tokio::task::spawn_blocking(func).await;
+
+    // Passed on, a panic while serving ends the connection like it ends the thread in the threads core
+    result.unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+}
+
+// Reads until the head is complete, invalid or cut short, the request then takes its lines.
+// Nothing past the head is needed, request bodies are not read.
+async fn read_head<R: AsyncRead + Unpin>(reader: &mut R, limits: &Limits, idle_timeout: Option<Duration>) -> Result<HeadParser, HttpError> {
+    let mut parser = HeadParser::new(limits);
+    let mut buffer = [0; READ_SIZE];
+
+    loop {
+        let read = match idle_timeout {
+            Some(duration) => timeout(duration, reader.read(&mut buffer)).await
+                .map_err(|_| http_errors::msg::request_timeout("No request data received in time").set_info("Request timed out"))?,
+            None => reader.read(&mut buffer).await
+        }.map_err(|e| HttpError::convert_from(e, Some("Failed to read from HTTP stream")))?;
+
+        if read == 0 {
+            break;
+        }
+
+        match parser.push(&buffer[..read]) {
+            HeadState::Incomplete => continue,
+            HeadState::Complete | HeadState::Invalid => break
+        }
+    }
+
+    Ok(parser)
+}
+
+// Renders on the blocking pool, where the request and its transcript are dropped as well
+async fn send_client_error<W: AsyncWrite + Unpin>(writer: &mut W, request: HttpRequest, err: HttpError) -> io::Result<()> {
+    let output = run_blocking(move || render_client_error(&request, err)).await?;
+    send(writer, &output).await
+}
+
+// Not async, a borrowed request held across an await would need to be Sync
+fn render_client_error(request: &HttpRequest, err: HttpError) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    write_error(request, &mut output, err).map_err(|e| e.convert_to(Some("Failed to send HTTP Error to client")))?;
+
+    Ok(output)
+}
+
+// A chunk at a time to the writer, without reading the whole file into memory first.
+// Writers may copy the chunk in process, so it is read with pread on the blocking pool instead of taken from the mapping.
+async fn send_mapped<W: AsyncWrite + Unpin>(writer: &mut W, file: &Arc<MappedFile>) -> io::Result<()> {
+    for offset in (0..file.len as usize).step_by(CHUNK_SIZE) {
+        let file = Arc::clone(file);
+        let chunk = run_blocking(move || file.read_chunk(offset)).await.map_err(HttpError::convert_to_direct)?;
+        writer.write_all(&chunk).await?;
+    }
+
+    Ok(())
+}
+
+async fn send_response<W: AsyncWrite + Unpin>(writer: &mut W, head: &[u8], mapped: Option<Arc<MappedFile>>) -> io::Result<()> {
+    writer.write_all(head).await?;
+    if let Some(file) = mapped {
+        send_mapped(writer, &file).await?;
+    }
+
+    Ok(())
+}
+
+async fn send<W: AsyncWrite + Unpin>(writer: &mut W, output: &[u8]) -> io::Result<()> {
+    writer.write_all(output).await?;
+    finish(writer).await
+}
+
+async fn finish<W: AsyncWrite + Unpin>(writer: &mut W) -> io::Result<()> {
+    writer.flush().await?;
+    writer.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_error::HttpCode;
+    use crate::transcript::TranscriptLevel;
+
+    // Runs one exchange through `handle_client` with a handler answering every request with "hello"
+    fn exchange(input: &'static [u8]) -> String {
+        let mut config = ServerConfig::new();
+        config.transcript.level = TranscriptLevel::Off;
+        let config = Arc::new(config);
+
+        let runtime = Builder::new_multi_thread().worker_threads(1).enable_all().build().unwrap();
+        let output = runtime.block_on(async move {
+            let mut output = Vec::new();
+            let connection = connections::track(String::from("127.0.0.1:1"));
+            let handler = |mut response: AsyncResponse| async move {
+                response.set_string_response(HttpCode::E200, String::from("hello")).unwrap();
+                response
+            };
+
+            handle_client(input, &mut output, String::from("127.0.0.1:1"), config, &connection, &handler).await.unwrap();
+            output
+        });
+
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn answers_with_the_handler() {
+        let output = exchange(b"GET /a.html HTTP/1.1\r\nHost: x\r\n\r\n");
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"), "{}", output);
+        assert!(output.contains("Connection: close\r\n"));
+        assert!(output.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn answers_invalid_heads_with_an_error() {
+        assert!(exchange(b"GET /\xff HTTP/1.1\r\nHost: x\r\n\r\n").starts_with("HTTP/1.1 400 "));
+        assert!(exchange(b"GET / HTTP/1.1\r\nHost: x\r\n").starts_with("HTTP/1.1 400 "));
+    }
+
+    #[test]
+    fn closes_connections_without_a_request_line() {
+        assert_eq!(exchange(b"\r\n"), "");
+    }
+}
@@ -0,0 +1,563 @@
+use std::{fs::read_to_string, net::SocketAddr, path::{Path, PathBuf}, sync::{Arc, OnceLock, RwLock}, time::Duration};
+
+use crate::access_log::{self, AccessLogFormat};
+use crate::error_pages::ErrorExposure;
+use crate::event_loop::ServerCore;
+use crate::file_cache;
+use crate::http_error::{HttpError, http_errors};
+use crate::json::JsonValue;
+use crate::metrics::MetricsRoute;
+use crate::redaction::{Redaction, RedactionStyle};
+use crate::rules::Rule;
+use crate::transcript::{self, TranscriptFormat, TranscriptLayout, TranscriptLevel, TranscriptRoute};
+use crate::transcript_sink::{parse_facility, SinkKind};
+use crate::transcript_writer::QueuePolicy;
+
+pub const DEFAULT_CONFIG_PATH: &str = "./myhttp.conf";
+
+pub struct Limits {
+    pub max_request_line: usize,
+    pub max_header_line: usize,
+    pub max_header_size: usize,
+    pub max_header_count: usize
+}
+
+pub struct TranscriptConfig {
+    pub format: TranscriptFormat,
+    pub dir: PathBuf,
+    pub layout: TranscriptLayout,
+    // Only the shared layout rotates, per connection files are already small
+    pub rotate_size: Option<usize>,
+    pub rotate_age: Option<Duration>,
+    pub compress: bool,
+    // Retention limits, enforced by the janitor thread
+    pub max_files: Option<usize>,
+    pub max_age: Option<Duration>,
+    pub max_total_size: Option<usize>,
+    pub janitor_interval: Duration,
+    // Lines waiting for the background writer, and what to do when that fills up
+    pub queue_size: usize,
+    pub queue_policy: QueuePolicy,
+    pub level: TranscriptLevel,
+    // Per path overrides of `level`
+    pub routes: Vec<TranscriptRoute>,
+    // Body bytes recorded at level full, 0 records whole bodies
+    pub max_body: usize,
+    // Where transcript lines go, see transcript_sink
+    pub sinks: Vec<SinkKind>,
+    pub syslog_socket: PathBuf,
+    pub syslog_facility: u8
+}
+
+pub struct CacheConfig {
+    pub enabled: bool,
+    // Total bytes held, gzip variants included
+    pub max_size: usize,
+    // Bigger files are read from disk on every request
+    pub max_file_size: usize,
+    // Keep a gzip variant next to each cached file for clients that accept it
+    pub gzip: bool,
+    // Files at least this big are memory mapped instead, None disables mapping
+    pub mmap_threshold: Option<usize>
+}
+
+pub struct AccessLogConfig {
+    // None disables the access log
+    pub path: Option<PathBuf>,
+    pub format: String
+}
+
+#[derive(Default)]
+pub struct AdminConfig {
+    // None disables the admin listener
+    pub listen: Option<String>
+}
+
+pub struct MetricsConfig {
+    pub path: String,
+    // Groups request paths into route labels
+    pub routes: Vec<MetricsRoute>
+}
+
+pub struct ServerConfig {
+    pub path: Option<PathBuf>,
+    pub root: PathBuf,
+    pub trailing_slash_redirect: bool,
+    pub core: ServerCore,
+    // Threads answering requests for the event loop core, runtime threads for the async core
+    pub workers: usize,
+    // Event loop and async connections that sent nothing for this long are closed, None keeps them open.
+    // Event loop workers also give up on a client that stops reading or sending for this long.
+    pub idle_timeout: Option<Duration>,
+    pub error_pages: PathBuf,
+    pub error_exposure: ErrorExposure,
+    pub limits: Limits,
+    pub cache: CacheConfig,
+    pub transcript: TranscriptConfig,
+    pub access_log: AccessLogConfig,
+    pub redaction: Arc<Redaction>,
+    pub admin: AdminConfig,
+    pub metrics: MetricsConfig,
+    pub rules: Vec<Rule>
+}
+
+struct ConfigEntry {
+    section: String,
+    key: String,
+    value: String,
+    line: usize
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_request_line: 8 * 1024,
+            max_header_line: 8 * 1024,
+            max_header_size: 64 * 1024,
+            max_header_count: 100
+        }
+    }
+}
+
+impl Default for TranscriptConfig {
+    fn default() -> Self {
+        Self {
+            format: TranscriptFormat::Text,
+            dir: PathBuf::from("./logs"),
+            layout: TranscriptLayout::PerConnection,
+            rotate_size: None,
+            rotate_age: None,
+            compress: false,
+            max_files: None,
+            max_age: None,
+            max_total_size: None,
+            janitor_interval: Duration::from_secs(60),
+            queue_size: 4096,
+            queue_policy: QueuePolicy::Block,
+            level: TranscriptLevel::Full,
+            routes: Vec::new(),
+            max_body: 64 * 1024,
+            sinks: vec![SinkKind::File, SinkKind::Stdout],
+            syslog_socket: PathBuf::from("/dev/log"),
+            // local0
+            syslog_facility: 16
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_size: 64 * 1024 * 1024,
+            max_file_size: 1024 * 1024,
+            gzip: true,
+            mmap_threshold: Some(1024 * 1024)
+        }
+    }
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            path: String::from("/metrics"),
+            routes: Vec::new()
+        }
+    }
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            path: Some(PathBuf::from("./logs/access.log")),
+            format: String::from("combined")
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn new() -> Self {
+        Self {
+            path: None,
+            root: PathBuf::from("./public"),
+            trailing_slash_redirect: true,
+            core: ServerCore::Threads,
+            workers: 16,
+            idle_timeout: Some(Duration::from_secs(60)),
+            error_pages: PathBuf::from("errors"),
+            error_exposure: ErrorExposure::Production,
+            limits: Limits::default(),
+            cache: CacheConfig::default(),
+            transcript: TranscriptConfig::default(),
+            access_log: AccessLogConfig::default(),
+            redaction: Arc::new(Redaction::default()),
+            admin: AdminConfig::default(),
+            metrics: MetricsConfig::default(),
+            rules: Vec::new()
+        }
+    }
+
+    // Uses `--config <path>` if given, otherwise ./myhttp.conf if it exists, otherwise defaults
+    pub fn load_from_args(args: &[String]) -> Result<Self, HttpError> {
+        if let Some(index) = args.iter().position(|arg| arg == "--config") {
+            let path = args.get(index + 1).ok_or_else(|| http_errors::msg::internal_server_error("Missing path after --config"))?;
+            return Self::load(Path::new(path));
+        }
+
+        let default_path = Path::new(DEFAULT_CONFIG_PATH);
+        if default_path.exists() {
+            Self::load(default_path)
+        } else {
+            Ok(Self::new())
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, HttpError> {
+        let contents = read_to_string(path).map_err(|e| HttpError::convert_from(e, Some("Failed to read config file")))?;
+
+        let mut config = Self::new();
+        config.path = Some(path.to_path_buf());
+
+        for entry in Self::parse(&contents)? {
+            config.apply(&entry).map_err(|msg| {
+                http_errors::msg::internal_server_error(format!("{}:{}: {}", path.display(), entry.line, msg).as_str())
+                    .set_info("Invalid config")
+            })?;
+        }
+
+        Ok(config)
+    }
+
+    fn parse(contents: &str) -> Result<Vec<ConfigEntry>, HttpError> {
+        let mut entries = Vec::new();
+        let mut section = String::new();
+
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                section = line[1..line.len() - 1].trim().to_string();
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').ok_or_else(|| {
+                http_errors::msg::internal_server_error(format!("Line {} is not a <key> = <value> pair", index + 1).as_str())
+                    .set_info("Invalid config")
+            })?;
+
+            entries.push(ConfigEntry {
+                section: section.clone(),
+                key: key.trim().to_string(),
+                value: value.trim().to_string(),
+                line: index + 1
+            });
+        }
+
+        Ok(entries)
+    }
+
+    fn apply(&mut self, entry: &ConfigEntry) -> Result<(), String> {
+        let value = entry.value.as_str();
+
+        match (entry.section.as_str(), entry.key.as_str()) {
+            ("server", "root") => self.root = PathBuf::from(value),
+            ("server", "trailing_slash_redirect") => self.trailing_slash_redirect = parse_bool(value)?,
+            ("server", "core") => self.core = ServerCore::parse(value)?,
+            ("server", "workers") => self.workers = parse_limit(parse_number(value)?).ok_or("workers must be greater than zero")?,
+            ("server", "idle_timeout") => self.idle_timeout = parse_limit(parse_duration(value)?),
+            ("errors", "dir") => self.error_pages = PathBuf::from(value),
+            ("errors", "mode") => self.error_exposure = ErrorExposure::parse(value)?,
+            ("limits", "max_request_line") => self.limits.max_request_line = parse_size(value)?,
+            ("limits", "max_header_line") => self.limits.max_header_line = parse_size(value)?,
+            ("limits", "max_header_size") => self.limits.max_header_size = parse_size(value)?,
+            ("limits", "max_header_count") => self.limits.max_header_count = parse_number(value)?,
+            ("cache", "enabled") => self.cache.enabled = parse_bool(value)?,
+            ("cache", "max_size") => self.cache.max_size = parse_size(value)?,
+            ("cache", "max_file_size") => self.cache.max_file_size = parse_size(value)?,
+            ("cache", "gzip") => self.cache.gzip = parse_bool(value)?,
+            ("cache", "mmap_threshold") => self.cache.mmap_threshold = parse_limit(parse_size(value)?),
+            ("transcript", "format") => self.transcript.format = TranscriptFormat::parse(value)?,
+            ("transcript", "dir") => self.transcript.dir = PathBuf::from(value),
+            ("transcript", "layout") => self.transcript.layout = TranscriptLayout::parse(value)?,
+            ("transcript", "rotate_size") => self.transcript.rotate_size = parse_limit(parse_size(value)?),
+            ("transcript", "rotate_age") => self.transcript.rotate_age = parse_limit(parse_duration(value)?),
+            ("transcript", "compress") => self.transcript.compress = parse_bool(value)?,
+            ("transcript", "max_files") => self.transcript.max_files = parse_limit(parse_number(value)?),
+            ("transcript", "max_age") => self.transcript.max_age = parse_limit(parse_duration(value)?),
+            ("transcript", "max_total_size") => self.transcript.max_total_size = parse_limit(parse_size(value)?),
+            ("transcript", "janitor_interval") => {
+                self.transcript.janitor_interval = parse_limit(parse_duration(value)?).ok_or("janitor_interval must be greater than zero")?;
+            },
+            ("transcript", "queue_size") => self.transcript.queue_size = parse_limit(parse_number(value)?).ok_or("queue_size must be greater than zero")?,
+            ("transcript", "queue_policy") => self.transcript.queue_policy = QueuePolicy::parse(value)?,
+            ("transcript", "level") => self.transcript.level = TranscriptLevel::parse(value)?,
+            ("transcript", "route") => self.transcript.routes.push(TranscriptRoute::parse(value)?),
+            ("transcript", "max_body") => self.transcript.max_body = parse_size(value)?,
+            ("transcript", "sinks") => self.transcript.sinks = SinkKind::parse_list(value)?,
+            // Shorthand for adding or removing the stdout sink
+            ("transcript", "echo") => {
+                self.transcript.sinks.retain(|sink| *sink != SinkKind::Stdout);
+                if parse_bool(value)? {
+                    self.transcript.sinks.push(SinkKind::Stdout);
+                }
+            },
+            ("transcript", "syslog_socket") => self.transcript.syslog_socket = PathBuf::from(value),
+            ("transcript", "syslog_facility") => self.transcript.syslog_facility = parse_facility(value)?,
+            ("access_log", "path") => self.access_log.path = (!value.is_empty() && value != "off").then(|| PathBuf::from(value)),
+            ("access_log", "format") => {
+                AccessLogFormat::parse(value)?;
+                self.access_log.format = value.to_string();
+            },
+            ("redaction", "headers") => Arc::make_mut(&mut self.redaction).headers = Redaction::parse_list(value),
+            ("redaction", "query") => Arc::make_mut(&mut self.redaction).set_query_params(Redaction::parse_list(value))?,
+            ("redaction", "body") => Arc::make_mut(&mut self.redaction).body_patterns.push(Redaction::parse_body_pattern(value)?),
+            ("redaction", "style") => Arc::make_mut(&mut self.redaction).style = RedactionStyle::parse(value)?,
+            ("redaction", "mask") => Arc::make_mut(&mut self.redaction).mask = value.to_string(),
+            ("redaction", "salt") => Arc::make_mut(&mut self.redaction).salt = value.to_string(),
+            ("admin", "listen") => {
+                self.admin.listen = if value.is_empty() || value == "off" {
+                    None
+                } else {
+                    value.parse::<SocketAddr>().map_err(|_| format!("\"{}\" is not a valid listen address, expected <ip>:<port>", value))?;
+                    Some(value.to_string())
+                };
+            },
+            ("metrics", "path") => {
+                if !value.starts_with('/') {
+                    return Err(format!("Metrics path \"{}\" must start with /", value));
+                }
+
+                self.metrics.path = value.to_string();
+            },
+            ("metrics", "route") => self.metrics.routes.push(MetricsRoute::parse(value)?),
+            ("rules", action) => self.rules.push(Rule::parse(action, value)?),
+            (section, key) => return Err(format!("Unknown config key \"{}\" in section [{}]", key, section))
+        }
+
+        Ok(())
+    }
+
+    // Effective settings for the admin /config view. The redaction salt is left out on purpose.
+    pub fn to_json(&self) -> JsonValue {
+        let transcript = &self.transcript;
+        let redaction = &self.redaction;
+
+        let server = JsonValue::object()
+            .with("root", self.root.display().to_string())
+            .with("trailing_slash_redirect", self.trailing_slash_redirect)
+            .with("core", self.core.get_name())
+            .with("workers", self.workers as u64)
+            .with("idle_timeout", self.idle_timeout.map(|timeout| timeout.as_secs()));
+
+        let errors = JsonValue::object()
+            .with("dir", self.error_pages.display().to_string())
+            .with("mode", self.error_exposure.get_name());
+
+        let limits = JsonValue::object()
+            .with("max_request_line", self.limits.max_request_line as u64)
+            .with("max_header_line", self.limits.max_header_line as u64)
+            .with("max_header_size", self.limits.max_header_size as u64)
+            .with("max_header_count", self.limits.max_header_count as u64);
+
+        let cache = JsonValue::object()
+            .with("enabled", self.cache.enabled)
+            .with("max_size", self.cache.max_size as u64)
+            .with("max_file_size", self.cache.max_file_size as u64)
+            .with("gzip", self.cache.gzip)
+            .with("mmap_threshold", self.cache.mmap_threshold.map(|threshold| threshold as u64));
+
+        let transcript = JsonValue::object()
+            .with("format", transcript.format.get_name())
+            .with("dir", transcript.dir.display().to_string())
+            .with("layout", transcript.layout.get_name())
+            .with("rotate_size", transcript.rotate_size.map(|size| size as u64))
+            .with("rotate_age", transcript.rotate_age.map(|age| age.as_secs()))
+            .with("compress", transcript.compress)
+            .with("max_files", transcript.max_files.map(|count| count as u64))
+            .with("max_age", transcript.max_age.map(|age| age.as_secs()))
+            .with("max_total_size", transcript.max_total_size.map(|size| size as u64))
+            .with("janitor_interval", transcript.janitor_interval.as_secs())
+            .with("queue_size", transcript.queue_size as u64)
+            .with("queue_policy", transcript.queue_policy.get_name())
+            .with("level", transcript.level.get_name())
+            .with("routes", transcript.routes.iter().map(|route| format!("{} {}", route.matcher.describe(), route.level.get_name())).collect::<Vec<String>>())
+            .with("max_body", transcript.max_body as u64)
+            .with("sinks", transcript.sinks.iter().map(SinkKind::get_name).collect::<Vec<&str>>())
+            .with("syslog_socket", transcript.syslog_socket.display().to_string())
+            .with("syslog_facility", transcript.syslog_facility as u64);
+
+        let access_log = JsonValue::object()
+            .with("path", self.access_log.path.as_ref().map(|path| path.display().to_string()))
+            .with("format", self.access_log.format.as_str());
+
+        let redaction = JsonValue::object()
+            .with("headers", redaction.headers.clone())
+            .with("query", redaction.get_query_params().to_vec())
+            .with("body", redaction.body_patterns.iter().map(|pattern| pattern.as_str()).collect::<Vec<&str>>())
+            .with("style", redaction.style.get_name())
+            .with("mask", redaction.mask.as_str());
+
+        let admin = JsonValue::object()
+            .with("listen", self.admin.listen.as_deref());
+
+        let metrics = JsonValue::object()
+            .with("path", self.metrics.path.as_str())
+            .with("routes", self.metrics.routes.iter().map(|route| format!("{} {}", route.matcher.describe(), route.name)).collect::<Vec<String>>());
+
+        JsonValue::object()
+            .with("path", self.path.as_ref().map(|path| path.display().to_string()))
+            .with("server", server)
+            .with("errors", errors)
+            .with("limits", limits)
+            .with("cache", cache)
+            .with("transcript", transcript)
+            .with("access_log", access_log)
+            .with("redaction", redaction)
+            .with("admin", admin)
+            .with("metrics", metrics)
+            .with("rules", self.rules.iter().map(Rule::describe).collect::<Vec<String>>())
+    }
+
+    // Settings only read at startup, listed when a reload changed them
+    fn get_restart_changes(&self, other: &ServerConfig) -> Vec<&'static str> {
+        let (old, new) = (&self.transcript, &other.transcript);
+        let changes = [
+            ("server.core", self.core != other.core),
+            ("server.workers", self.workers != other.workers),
+            ("transcript.format", old.format != new.format),
+            ("transcript.dir", old.dir != new.dir),
+            ("transcript.layout", old.layout != new.layout),
+            ("transcript.compress", old.compress != new.compress),
+            ("transcript.queue_size", old.queue_size != new.queue_size),
+            ("transcript.queue_policy", old.queue_policy != new.queue_policy),
+            ("transcript.sinks", old.sinks != new.sinks),
+            ("transcript.syslog_socket", old.syslog_socket != new.syslog_socket),
+            ("transcript.syslog_facility", old.syslog_facility != new.syslog_facility),
+            ("admin.listen", self.admin.listen != other.admin.listen)
+        ];
+
+        changes.into_iter().filter(|(_, changed)| *changed).map(|(name, _)| name).collect()
+    }
+}
+
+static CURRENT: RwLock<Option<Arc<ServerConfig>>> = RwLock::new(None);
+// What the process started with, settings read only at startup keep these values
+static STARTUP: OnceLock<Arc<ServerConfig>> = OnceLock::new();
+
+// The config new connections start with
+pub fn get_current() -> Arc<ServerConfig> {
+    let current = CURRENT.read().unwrap_or_else(|e| e.into_inner());
+    current.clone().unwrap_or_else(|| Arc::new(ServerConfig::new()))
+}
+
+pub fn set_current(config: Arc<ServerConfig>) {
+    STARTUP.get_or_init(|| Arc::clone(&config));
+    *CURRENT.write().unwrap_or_else(|e| e.into_inner()) = Some(config);
+}
+
+// Reads the config file again and swaps it in, on SIGHUP or from the admin listener.
+// Connections in progress finish with the config they started with.
+// Returns the settings that differ from startup but only take effect after a restart.
+pub fn reload() -> Result<Vec<&'static str>, HttpError> {
+    let current = get_current();
+    let config = match &current.path {
+        Some(path) => ServerConfig::load(path)?,
+        None if Path::new(DEFAULT_CONFIG_PATH).exists() => ServerConfig::load(Path::new(DEFAULT_CONFIG_PATH))?,
+        None => ServerConfig::new()
+    };
+
+    access_log::init(&config)?;
+    transcript::update_shared_file(&config.transcript);
+    // Entries were read under the old settings, and a new root makes most of them unreachable anyway
+    file_cache::clear();
+
+    let restart = STARTUP.get().unwrap_or(&current).get_restart_changes(&config);
+    set_current(Arc::new(config));
+    Ok(restart)
+}
+
+// Accepts plain byte counts or a k/m/g suffix, e.g. "8k"
+fn parse_size(value: &str) -> Result<usize, String> {
+    let lower = value.to_ascii_lowercase();
+    let (number, multiplier) = match lower.chars().last() {
+        Some('k') => (&lower[..lower.len() - 1], 1024),
+        Some('m') => (&lower[..lower.len() - 1], 1024 * 1024),
+        Some('g') => (&lower[..lower.len() - 1], 1024 * 1024 * 1024),
+        _ => (lower.as_str(), 1)
+    };
+
+    parse_number(number.trim())?.checked_mul(multiplier).ok_or_else(|| format!("\"{}\" is too large", value))
+}
+
+// Accepts plain seconds or an s/m/h/d suffix, e.g. "7d"
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let lower = value.to_ascii_lowercase();
+    let (number, multiplier) = match lower.chars().last() {
+        Some('s') => (&lower[..lower.len() - 1], 1),
+        Some('m') => (&lower[..lower.len() - 1], 60),
+        Some('h') => (&lower[..lower.len() - 1], 60 * 60),
+        Some('d') => (&lower[..lower.len() - 1], 24 * 60 * 60),
+        _ => (lower.as_str(), 1)
+    };
+
+    let seconds = parse_number(number.trim())?.checked_mul(multiplier).ok_or_else(|| format!("\"{}\" is too large", value))?;
+    Ok(Duration::from_secs(seconds as u64))
+}
+
+// Zero means no limit
+fn parse_limit<T: Default + PartialEq>(value: T) -> Option<T> {
+    (value != T::default()).then_some(value)
+}
+
+fn parse_number(value: &str) -> Result<usize, String> {
+    value.parse::<usize>().map_err(|_| format!("\"{}\" is not a valid number", value))
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err(format!("\"{}\" is not a valid boolean", value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("8k"), Ok(8 * 1024));
+        assert_eq!(parse_size("2M"), Ok(2 * 1024 * 1024));
+        assert_eq!(parse_size("100"), Ok(100));
+        assert!(parse_size("99999999999999999g").is_err());
+        assert!(parse_size("-1k").is_err());
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("7d"), Ok(Duration::from_secs(7 * 24 * 60 * 60)));
+        assert!(parse_duration("99999999999999999d").is_err());
+        assert!(parse_duration("1w").is_err());
+    }
+
+    #[test]
+    fn reload_swaps_the_current_config() {
+        let path = std::env::temp_dir().join(format!("myhttp-reload-{}.conf", std::process::id()));
+        std::fs::write(&path, "[access_log]\npath = off\n").unwrap();
+        set_current(Arc::new(ServerConfig::load(&path).unwrap()));
+
+        std::fs::write(&path, "[access_log]\npath = off\n[transcript]\nqueue_size = 3\n[rules]\nredirect = exact /a /b\n").unwrap();
+        assert_eq!(reload().unwrap(), ["transcript.queue_size"]);
+        assert_eq!(get_current().rules.len(), 1);
+
+        // A broken file keeps what was loaded before
+        std::fs::write(&path, "[transcript]\nqueue_size = many\n").unwrap();
+        assert!(reload().is_err());
+        assert_eq!(get_current().transcript.queue_size, 3);
+    }
+}
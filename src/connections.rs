@@ -0,0 +1,68 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+
+use crate::metrics::{self, ConnectionGuard};
+use crate::request::HttpRequest;
+
+// Connections currently being handled, for the admin /connections view
+#[derive(Clone)]
+pub struct ActiveConnection {
+    pub id: u64,
+    pub peer: String,
+    pub opened: DateTime<Utc>,
+    // Known once the request head was read
+    pub request_id: Option<String>,
+    pub request_line: Option<String>
+}
+
+// Removes the connection from the registry when it ends
+pub struct ConnectionHandle {
+    id: u64,
+    _metrics: ConnectionGuard
+}
+
+static ACTIVE: Mutex<BTreeMap<u64, ActiveConnection>> = Mutex::new(BTreeMap::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+impl ConnectionHandle {
+    // Request lines are redacted like they are for transcripts
+    pub fn set_request(&self, request: &HttpRequest) {
+        let mut active = ACTIVE.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(connection) = active.get_mut(&self.id) {
+            connection.request_id = Some(request.id.clone());
+            connection.request_line = (!request.line.is_empty()).then(|| request.config.redaction.redact_query(&request.line).to_string());
+        }
+    }
+}
+
+impl Drop for ConnectionHandle {
+    fn drop(&mut self) {
+        ACTIVE.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.id);
+    }
+}
+
+pub fn track(peer: String) -> ConnectionHandle {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let connection = ActiveConnection {
+        id,
+        peer,
+        opened: Utc::now(),
+        request_id: None,
+        request_line: None
+    };
+
+    ACTIVE.lock().unwrap_or_else(|e| e.into_inner()).insert(id, connection);
+
+    ConnectionHandle {
+        id,
+        _metrics: metrics::track_connection()
+    }
+}
+
+// Oldest first
+pub fn list() -> Vec<ActiveConnection> {
+    ACTIVE.lock().unwrap_or_else(|e| e.into_inner()).values().cloned().collect()
+}
@@ -0,0 +1,186 @@
+use std::fs::read_to_string;
+
+use crate::http_error::{HttpCode, HttpError};
+use crate::json::JsonValue;
+use crate::media_type::MediaRange;
+use crate::request::HttpRequest;
+use crate::str_util::html_escape;
+use crate::util::generate_id;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ErrorExposure {
+    // Clients only see the status and a correlation id, details stay in the transcript
+    Production,
+    // Clients also see the error description, for local debugging only
+    Development
+}
+
+pub struct ErrorPage {
+    pub content_type: String,
+    pub body: String,
+    pub headers: Vec<(String, String)>
+}
+
+impl ErrorExposure {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "production" => Ok(ErrorExposure::Production),
+            "development" => Ok(ErrorExposure::Development),
+            _ => Err(format!("Unknown error mode \"{}\", expected production or development", value))
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            ErrorExposure::Production => "production",
+            ErrorExposure::Development => "development"
+        }
+    }
+}
+
+// The single place error bodies are rendered, shared by responses and early connection errors.
+// Full details only go to the transcript, under an id the client also receives.
+pub fn render_error(request: &HttpRequest, error: &HttpError) -> ErrorPage {
+    let code = &error.code;
+    let error_id = generate_id();
+    request.transcript.push(format!("Error {}: {}", error_id, error.get_chain_msg()).as_str()).ok();
+
+    let path = (!request.path.is_empty()).then_some(request.path.as_str());
+    let detail = match request.config.error_exposure {
+        ErrorExposure::Production => None,
+        ErrorExposure::Development => Some(error.to_string())
+    };
+
+    let mut headers = vec![(String::from("X-Error-Id"), error_id.clone())];
+    if let Some(detail) = &detail {
+        headers.push((String::from("X-Error-Info"), detail.clone()));
+    }
+
+    if wants_json(request) {
+        let body = JsonValue::object()
+            .with("status", code.get_code())
+            .with("reason", code.get_desc())
+            .with("path", path)
+            .with("request_id", request.id.as_str())
+            .with("error_id", error_id.as_str())
+            .with("detail", detail);
+
+        return ErrorPage {
+            content_type: String::from("application/json"),
+            body: body.to_string(),
+            headers
+        };
+    }
+
+    let template = load_template(request, code).unwrap_or_else(|| String::from("<html><body><h1>{{reason}}</h1>{{detail}}</body></html>"));
+    let detail = detail.map(|detail| format!("<pre>{}</pre>", html_escape(&detail))).unwrap_or_default();
+
+    let body = template
+        .replace("{{code}}", &code.get_code().to_string())
+        .replace("{{reason}}", &html_escape(code.get_desc()))
+        .replace("{{request_id}}", &html_escape(&request.id))
+        .replace("{{error_id}}", &error_id)
+        .replace("{{detail}}", &detail)
+        .replace("{{path}}", &html_escape(path.unwrap_or("-")));
+
+    ErrorPage {
+        content_type: String::from("text/html"),
+        body,
+        headers
+    }
+}
+
+fn wants_json(request: &HttpRequest) -> bool {
+    // A malformed Accept header is what may have caused the error, so fall back to HTML
+    let Ok(Some(ranges)) = request.headers.accept() else {
+        return false;
+    };
+
+    MediaRange::negotiate(&ranges, &["text/html", "application/json"]) == Some("application/json")
+}
+
+// Looks for <code>.html, then <class>xx.html, in the configured error directory
+fn load_template(request: &HttpRequest, code: &HttpCode) -> Option<String> {
+    let config = &request.config;
+    let dir = config.root.join(&config.error_pages);
+    let code = code.get_code();
+
+    [format!("{}.html", code), format!("{}xx.html", code / 100)]
+        .iter()
+        .find_map(|name| read_to_string(dir.join(name)).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::config::ServerConfig;
+    use crate::http_error::http_errors;
+    use crate::transcript::TranscriptLevel;
+
+    fn request(name: &str, pages: &[(&str, &str)], configure: impl FnOnce(&mut ServerConfig)) -> HttpRequest {
+        let root = std::env::temp_dir().join(format!("myhttp-errors-{}-{}", name, std::process::id()));
+        fs::create_dir_all(root.join("errors")).unwrap();
+        for (file, content) in pages {
+            fs::write(root.join("errors").join(file), content).unwrap();
+        }
+
+        let mut config = ServerConfig::new();
+        config.transcript.level = TranscriptLevel::Off;
+        config.root = root;
+        configure(&mut config);
+
+        let mut request = HttpRequest::new(String::from("127.0.0.1:1"), Arc::new(config)).unwrap();
+        request.path = String::from("/<missing>");
+        request
+    }
+
+    #[test]
+    fn prefers_the_exact_status_page() {
+        let request = request("exact", &[("404.html", "{{code}} {{reason}} {{path}}"), ("4xx.html", "class")], |_| {});
+        let page = render_error(&request, &HttpError::new(HttpCode::E404));
+        assert_eq!(page.content_type, "text/html");
+        assert_eq!(page.body, "404 Not Found /&lt;missing&gt;");
+    }
+
+    #[test]
+    fn falls_back_to_the_class_page_then_the_default() {
+        let request = request("class", &[("4xx.html", "class {{code}}")], |_| {});
+        assert_eq!(render_error(&request, &HttpError::new(HttpCode::E403)).body, "class 403");
+
+        let page = render_error(&request, &http_errors::msg::internal_server_error("boom"));
+        assert_eq!(page.body, "<html><body><h1>Internal Server Error</h1></body></html>");
+    }
+
+    #[test]
+    fn production_hides_error_details() {
+        let request = request("production", &[], |_| {});
+        let page = render_error(&request, &http_errors::msg::internal_server_error("/srv/secret.db is locked"));
+
+        assert!(!page.body.contains("secret"));
+        assert!(page.headers.iter().any(|(name, _)| name == "X-Error-Id"));
+        assert!(!page.headers.iter().any(|(name, _)| name == "X-Error-Info"));
+    }
+
+    #[test]
+    fn development_shows_escaped_details() {
+        let request = request("development", &[], |config| config.error_exposure = ErrorExposure::Development);
+        let page = render_error(&request, &http_errors::msg::bad_request("<script>"));
+
+        assert!(page.body.contains("<pre>ERROR 400 Bad Request: &lt;script&gt;</pre>"));
+        assert!(page.headers.iter().any(|(name, value)| name == "X-Error-Info" && value == "ERROR 400 Bad Request: <script>"));
+    }
+
+    #[test]
+    fn renders_json_when_preferred() {
+        let mut request = request("json", &[("4xx.html", "html")], |_| {});
+        request.headers.add_from_pair("Accept", "text/html;q=0.5, application/json").unwrap();
+
+        let page = render_error(&request, &HttpError::new(HttpCode::E404));
+        assert_eq!(page.content_type, "application/json");
+        assert!(page.body.contains("\"status\":404"));
+        assert!(page.body.contains("\"detail\":null"));
+    }
+}
@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read};
+use std::net::{self, SocketAddr};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mio::net::{TcpListener, TcpStream};
+use mio::{Events, Interest, Poll, Token};
+
+use crate::config::{self, ServerConfig};
+use crate::connections::{self, ConnectionHandle};
+use crate::head_parser::{HeadParser, HeadState};
+use crate::http_error::{describe_chain, http_errors};
+use crate::request::HttpRequest;
+
+const LISTENER: Token = Token(0);
+
+// Also how often idle connections are looked for
+const POLL_TIMEOUT: Duration = Duration::from_secs(1);
+
+const READ_SIZE: usize = 4096;
+
+// Connections waiting for a worker, past that new ones are answered with a 503
+const JOBS_PER_WORKER: usize = 64;
+
+// Connections waiting to be told the queue is full, past that they are closed unanswered
+const QUEUED_REJECTS: usize = 64;
+
+// A full queue is answered by one thread for all rejected clients, which must not wait long on any one of them
+const REJECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Connections with a complete head waiting for a free worker
+static QUEUED: AtomicI64 = AtomicI64::new(0);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ServerCore {
+    // A thread per connection, blocking from accept to the last byte of the response
+    Threads,
+    // One thread waits on every connection until its request head is in, workers then respond
+    EventLoop,
+    // Tokio tasks with async handlers, see async_server
+    #[cfg(feature = "async")]
+    Async
+}
+
+// A connection still sending its request head
+struct PendingConnection {
+    stream: TcpStream,
+    peer: SocketAddr,
+    parser: HeadParser,
+    config: Arc<ServerConfig>,
+    connection: ConnectionHandle,
+    last_read: Instant
+}
+
+// A connection with its head read, handed to a worker
+struct Job {
+    stream: net::TcpStream,
+    peer: SocketAddr,
+    head: HeadParser,
+    config: Arc<ServerConfig>,
+    connection: ConnectionHandle
+}
+
+enum ReadOutcome {
+    Pending,
+    // Head complete, over a limit or cut short, either way a worker answers it
+    Ready,
+    Closed
+}
+
+impl ServerCore {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "threads" => Ok(ServerCore::Threads),
+            "event_loop" => Ok(ServerCore::EventLoop),
+            #[cfg(feature = "async")]
+            "async" => Ok(ServerCore::Async),
+            #[cfg(not(feature = "async"))]
+            "async" => Err(String::from("The async server core needs a build with the async feature")),
+            _ => Err(format!("Unknown server core \"{}\", expected threads, event_loop or async", value))
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            ServerCore::Threads => "threads",
+            ServerCore::EventLoop => "event_loop",
+            #[cfg(feature = "async")]
+            ServerCore::Async => "async"
+        }
+    }
+}
+
+// Accepts and reads request heads on the calling thread. Idle connections cost a buffer and a
+// registration here instead of a thread, only requests being answered occupy one of the workers.
+pub fn run(listener: net::TcpListener, workers: usize) -> io::Result<()> {
+    listener.set_nonblocking(true)?;
+    let mut listener = TcpListener::from_std(listener);
+
+    let mut poll = Poll::new()?;
+    poll.registry().register(&mut listener, LISTENER, Interest::READABLE)?;
+
+    let jobs = start_workers(workers);
+    let rejects = start_rejecter();
+    let mut pending: HashMap<Token, PendingConnection> = HashMap::new();
+    let mut events = Events::with_capacity(1024);
+    let mut next_token = 1;
+
+    loop {
+        if let Err(e) = poll.poll(&mut events, Some(POLL_TIMEOUT)) {
+            if e.kind() == ErrorKind::Interrupted {
+                continue;
+            }
+
+            return Err(e);
+        }
+
+        for event in events.iter() {
+            if event.token() == LISTENER {
+                accept(&listener, &poll, &mut pending, &mut next_token);
+                continue;
+            }
+
+            let Some(connection) = pending.get_mut(&event.token()) else {
+                continue;
+            };
+
+            match read_head(connection) {
+                ReadOutcome::Pending => {},
+                ReadOutcome::Ready => {
+                    let Some(mut connection) = pending.remove(&event.token()) else {
+                        continue;
+                    };
+
+                    poll.registry().deregister(&mut connection.stream).ok();
+                    dispatch(connection, &jobs, &rejects);
+                },
+                ReadOutcome::Closed => {
+                    if let Some(mut connection) = pending.remove(&event.token()) {
+                        poll.registry().deregister(&mut connection.stream).ok();
+                    }
+                }
+            }
+        }
+
+        close_idle(&poll, &mut pending);
+    }
+}
+
+fn accept(listener: &TcpListener, poll: &Poll, pending: &mut HashMap<Token, PendingConnection>, next_token: &mut usize) {
+    loop {
+        let (mut stream, peer) = match listener.accept() {
+            Ok(accepted) => accepted,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => {
+                eprintln!("Connection failed: {:?}", e);
+                return;
+            }
+        };
+
+        println!("New connection: {}", peer);
+
+        let token = Token(*next_token);
+        *next_token += 1;
+
+        if let Err(e) = poll.registry().register(&mut stream, token, Interest::READABLE) {
+            eprintln!("{} Failed to register connection: {}", peer, e);
+            continue;
+        }
+
+        // Picked up per connection, so reloads apply to the next one
+        let config = config::get_current();
+        pending.insert(token, PendingConnection {
+            stream,
+            peer,
+            parser: HeadParser::new(&config.limits),
+            config,
+            connection: connections::track(peer.to_string()),
+            last_read: Instant::now()
+        });
+    }
+}
+
+// Reads until the socket has nothing more, readiness is edge triggered
+fn read_head(connection: &mut PendingConnection) -> ReadOutcome {
+    let mut buffer = [0; READ_SIZE];
+
+    loop {
+        match connection.stream.read(&mut buffer) {
+            // Closed before sending anything, there is nobody to answer
+            Ok(0) if connection.parser.is_empty() => return ReadOutcome::Closed,
+            // Closed mid head, answered like the blocking reader answers an early end
+            Ok(0) => return ReadOutcome::Ready,
+            Ok(read) => {
+                connection.last_read = Instant::now();
+                match connection.parser.push(&buffer[..read]) {
+                    HeadState::Incomplete => continue,
+                    HeadState::Complete | HeadState::Invalid => return ReadOutcome::Ready
+                }
+            },
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return ReadOutcome::Pending,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => return ReadOutcome::Closed
+        }
+    }
+}
+
+fn dispatch(connection: PendingConnection, jobs: &SyncSender<Job>, rejects: &SyncSender<Job>) {
+    let stream = net::TcpStream::from(connection.stream);
+    // Workers answer with the same blocking code the threads core uses, the timeouts keep a
+    // client that stops reading from holding a worker for longer than an idle one is kept
+    let idle_timeout = connection.config.idle_timeout;
+    let blocking = stream.set_nonblocking(false)
+        .and_then(|_| stream.set_read_timeout(idle_timeout))
+        .and_then(|_| stream.set_write_timeout(idle_timeout));
+
+    if let Err(e) = blocking {
+        eprintln!("{} Failed to hand connection to a worker: {}", connection.peer, e);
+        return;
+    }
+
+    let job = Job {
+        stream,
+        peer: connection.peer,
+        head: connection.parser,
+        config: connection.config,
+        connection: connection.connection
+    };
+
+    QUEUED.fetch_add(1, Ordering::Relaxed);
+    match jobs.try_send(job) {
+        Ok(()) => {},
+        Err(TrySendError::Full(job)) => {
+            QUEUED.fetch_sub(1, Ordering::Relaxed);
+            // Answered on the rejecter thread, writing here would stall every connection on a slow client
+            if let Err(TrySendError::Full(job) | TrySendError::Disconnected(job)) = rejects.try_send(job) {
+                println!("Worker queue full, closing connection unanswered: {}", job.peer);
+            }
+        },
+        Err(TrySendError::Disconnected(_)) => {
+            QUEUED.fetch_sub(1, Ordering::Relaxed);
+            eprintln!("{} Failed to hand connection to a worker: all workers stopped", connection.peer);
+        }
+    }
+}
+
+fn start_rejecter() -> SyncSender<Job> {
+    let (sender, receiver) = sync_channel::<Job>(QUEUED_REJECTS);
+    thread::spawn(move || {
+        for job in receiver {
+            reject(job);
+        }
+    });
+
+    sender
+}
+
+// Every worker is busy and the queue is full, tell the client instead of queueing without limit
+fn reject(job: Job) {
+    println!("Worker queue full, rejecting connection: {}", job.peer);
+
+    if let Err(e) = job.stream.set_write_timeout(Some(REJECT_TIMEOUT)) {
+        eprintln!("{} Failed to reject connection: {}", job.peer, e);
+        return;
+    }
+
+    let mut request = match HttpRequest::new(job.peer.to_string(), job.config) {
+        Ok(request) => request,
+        Err(e) => {
+            eprintln!("{} Failed to reject connection: {}", job.peer, e.get_chain_msg());
+            return;
+        }
+    };
+
+    let err = http_errors::msg::service_unavailable("All workers are busy").set_info("Server overloaded");
+    if let Err(e) = crate::respond_client_error(&mut request, &job.stream, err) {
+        eprintln!("{} Failed to reject connection: {}", job.peer, describe_chain(&e));
+    }
+}
+
+// Read from the current config, so a reload changes it for connections already waiting
+fn close_idle(poll: &Poll, pending: &mut HashMap<Token, PendingConnection>) {
+    let Some(timeout) = config::get_current().idle_timeout else {
+        return;
+    };
+
+    pending.retain(|_, connection| {
+        if connection.last_read.elapsed() < timeout {
+            return true;
+        }
+
+        println!("Closing idle connection: {}", connection.peer);
+        poll.registry().deregister(&mut connection.stream).ok();
+        false
+    });
+}
+
+pub fn get_queue_depth() -> i64 {
+    QUEUED.load(Ordering::Relaxed).max(0)
+}
+
+fn start_workers(count: usize) -> SyncSender<Job> {
+    let (sender, receiver) = sync_channel::<Job>(count * JOBS_PER_WORKER);
+    let receiver = Arc::new(Mutex::new(receiver));
+
+    for _ in 0..count {
+        let receiver = Arc::clone(&receiver);
+        thread::spawn(move || work(receiver));
+    }
+
+    sender
+}
+
+fn work(receiver: Arc<Mutex<Receiver<Job>>>) {
+    loop {
+        let job = match receiver.lock().unwrap_or_else(|e| e.into_inner()).recv() {
+            Ok(job) => job,
+            Err(_) => return
+        };
+
+        QUEUED.fetch_sub(1, Ordering::Relaxed);
+
+        if let Err(e) = crate::handle_client(job.stream, job.config, &job.connection, Some(job.head)) {
+            eprintln!("{} Failed to handle client: {}", job.peer, describe_chain(&e));
+        }
+    }
+}
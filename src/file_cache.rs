@@ -0,0 +1,314 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::config::CacheConfig;
+use crate::http_error::HttpError;
+use crate::mapped_file::{self, MappedFile};
+
+pub enum FileContent {
+    // Shared with every response sending it, not copied per request
+    Memory(Arc<[u8]>),
+    // Files above the mmap threshold, never held by the cache itself
+    Mapped(Arc<MappedFile>)
+}
+
+// A static file as it was on disk when it was read, shared with every response serving it
+pub struct CachedFile {
+    pub content: FileContent,
+    // Only kept when it is actually smaller than the content
+    pub gzip: Option<Arc<[u8]>>,
+    pub modified: SystemTime,
+    pub len: u64,
+    pub etag: String
+}
+
+struct CacheEntry {
+    file: Arc<CachedFile>,
+    last_used: u64
+}
+
+// Least recently used entries are evicted first once `max_size` is exceeded
+struct FileCache {
+    // Keyed by canonical path, so different request paths for one file share an entry
+    entries: HashMap<PathBuf, CacheEntry>,
+    // last_used -> path, the first key is the next to evict
+    order: BTreeMap<u64, PathBuf>,
+    size: usize,
+    tick: u64
+}
+
+static CACHE: Mutex<Option<FileCache>> = Mutex::new(None);
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+static MAPPED: AtomicU64 = AtomicU64::new(0);
+
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub mapped: u64,
+    pub entries: usize,
+    pub size: usize
+}
+
+impl CachedFile {
+    fn read(path: &Path, gzip: bool) -> Result<Self, HttpError> {
+        let mut file = File::open(path).map_err(|e| HttpError::convert_from(e, Some("Failed to open file")))?;
+        // Taken from the open file, so a later replacement of the path shows up as a change
+        let metadata = file.metadata().map_err(|e| HttpError::convert_from(e, Some("Failed to read file metadata")))?;
+
+        let mut content = Vec::with_capacity(metadata.len() as usize);
+        file.read_to_end(&mut content).map_err(|e| HttpError::convert_from(e, Some("Failed to read file buffer")))?;
+
+        let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+        let gzip = if gzip { compress(&content).filter(|compressed| compressed.len() < content.len()) } else { None };
+        let gzip = gzip.map(Arc::from);
+
+        Ok(Self {
+            etag: get_etag(metadata.len(), modified),
+            content: FileContent::Memory(Arc::from(content)),
+            gzip,
+            modified,
+            len: metadata.len()
+        })
+    }
+
+    // Compressing per request would cost more than the mapping saves, so these have no gzip variant
+    fn map(path: &Path, len: u64, modified: SystemTime) -> Result<Self, HttpError> {
+        let file = mapped_file::get(path, len, modified)?;
+
+        Ok(Self {
+            etag: get_etag(file.len, file.modified),
+            modified: file.modified,
+            len: file.len,
+            content: FileContent::Mapped(file),
+            gzip: None
+        })
+    }
+
+    // The gzip variant is a different representation, so it gets its own ETag
+    pub fn get_gzip_etag(&self) -> String {
+        format!("{}-gz\"", self.etag.trim_end_matches('"'))
+    }
+
+    fn get_size(&self) -> usize {
+        let content = match &self.content {
+            FileContent::Memory(content) => content.len(),
+            FileContent::Mapped(_) => 0
+        };
+
+        content + self.gzip.as_ref().map(|gzip| gzip.len()).unwrap_or(0)
+    }
+
+    fn is_current(&self, len: u64, modified: SystemTime) -> bool {
+        self.len == len && self.modified == modified
+    }
+}
+
+impl FileCache {
+    fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            size: 0,
+            tick: 0
+        }
+    }
+
+    fn get(&mut self, path: &Path) -> Option<Arc<CachedFile>> {
+        self.tick += 1;
+        let entry = self.entries.get_mut(path)?;
+
+        self.order.remove(&entry.last_used);
+        self.order.insert(self.tick, path.to_path_buf());
+        entry.last_used = self.tick;
+
+        Some(Arc::clone(&entry.file))
+    }
+
+    fn insert(&mut self, path: PathBuf, file: Arc<CachedFile>, max_size: usize) {
+        self.remove(&path);
+        self.tick += 1;
+
+        self.size += file.get_size();
+        self.order.insert(self.tick, path.clone());
+        self.entries.insert(path, CacheEntry { file, last_used: self.tick });
+
+        while self.size > max_size {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+
+            if let Some(entry) = self.entries.remove(&oldest) {
+                self.size -= entry.file.get_size();
+            }
+        }
+    }
+
+    fn remove(&mut self, path: &Path) {
+        if let Some(entry) = self.entries.remove(path) {
+            self.order.remove(&entry.last_used);
+            self.size -= entry.file.get_size();
+        }
+    }
+}
+
+// Returns the file at the canonical `path`, from the cache while its size and mtime on disk are unchanged.
+// Files from `mmap_threshold` up are mapped instead, other files bigger than `max_file_size` are read every time.
+pub fn get(path: &Path, config: &CacheConfig) -> Result<Arc<CachedFile>, HttpError> {
+    let metadata = std::fs::metadata(path).map_err(|e| HttpError::convert_from(e, Some("Failed to open file")))?;
+    let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+
+    if config.mmap_threshold.is_some_and(|threshold| metadata.len() >= threshold as u64) {
+        MAPPED.fetch_add(1, Ordering::Relaxed);
+        return CachedFile::map(path, metadata.len(), modified).map(Arc::new);
+    }
+
+    if !config.enabled {
+        return CachedFile::read(path, false).map(Arc::new);
+    }
+
+    if metadata.len() > config.max_file_size as u64 || metadata.len() > config.max_size as u64 {
+        MISSES.fetch_add(1, Ordering::Relaxed);
+        return CachedFile::read(path, false).map(Arc::new);
+    }
+
+    {
+        let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+        let cache = cache.get_or_insert_with(FileCache::new);
+
+        match cache.get(path) {
+            Some(file) if file.is_current(metadata.len(), modified) => {
+                HITS.fetch_add(1, Ordering::Relaxed);
+                return Ok(file);
+            },
+            Some(_) => cache.remove(path),
+            None => {}
+        }
+    }
+
+    // Read without holding the lock, two requests racing for one file both read it and the last insert wins
+    MISSES.fetch_add(1, Ordering::Relaxed);
+    let file = Arc::new(CachedFile::read(path, config.gzip)?);
+
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    cache.get_or_insert_with(FileCache::new).insert(path.to_path_buf(), Arc::clone(&file), config.max_size);
+
+    Ok(file)
+}
+
+// Drops every entry, responses still holding a file keep it until they are done
+pub fn clear() {
+    *CACHE.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+pub fn get_stats() -> CacheStats {
+    let cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    let (entries, size) = cache.as_ref().map(|cache| (cache.entries.len(), cache.size)).unwrap_or((0, 0));
+
+    CacheStats {
+        hits: HITS.load(Ordering::Relaxed),
+        misses: MISSES.load(Ordering::Relaxed),
+        mapped: MAPPED.load(Ordering::Relaxed),
+        entries,
+        size
+    }
+}
+
+// Size and mtime in hex, like most servers build theirs
+fn get_etag(len: u64, modified: SystemTime) -> String {
+    let nanos = modified.duration_since(UNIX_EPOCH).map(|duration| duration.as_nanos()).unwrap_or(0);
+    format!("\"{:x}-{:x}\"", len, nanos)
+}
+
+fn compress(content: &[u8]) -> Option<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(content).ok()?;
+    encoder.finish().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_file(name: &str, content: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("myhttp-cache-{}-{}", name, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn memory(file: &CachedFile) -> &Arc<[u8]> {
+        match &file.content {
+            FileContent::Memory(content) => content,
+            FileContent::Mapped(_) => panic!("expected an in-memory file")
+        }
+    }
+
+    fn entry(len: usize) -> Arc<CachedFile> {
+        Arc::new(CachedFile {
+            content: FileContent::Memory(Arc::from(vec![0; len])),
+            gzip: None,
+            modified: UNIX_EPOCH,
+            len: len as u64,
+            etag: String::new()
+        })
+    }
+
+    #[test]
+    fn hits_share_the_cached_body() {
+        let path = write_file("hit", b"<p>cached</p>");
+        let config = CacheConfig::default();
+
+        let first = get(&path, &config).unwrap();
+        let second = get(&path, &config).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(Arc::ptr_eq(memory(&first), memory(&second)));
+    }
+
+    #[test]
+    fn changed_files_are_read_again() {
+        let path = write_file("changed", b"old");
+        let config = CacheConfig::default();
+        let old = get(&path, &config).unwrap();
+
+        std::fs::write(&path, b"newer").unwrap();
+        let new = get(&path, &config).unwrap();
+        assert_eq!(&memory(&new)[..], b"newer");
+        assert_ne!(old.etag, new.etag);
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_entry() {
+        let mut cache = FileCache::new();
+        cache.insert(PathBuf::from("/a"), entry(4), 10);
+        cache.insert(PathBuf::from("/b"), entry(4), 10);
+        assert!(cache.get(Path::new("/a")).is_some());
+
+        cache.insert(PathBuf::from("/c"), entry(4), 10);
+        assert!(cache.get(Path::new("/b")).is_none());
+        assert!(cache.get(Path::new("/a")).is_some());
+        assert_eq!(cache.size, 8);
+
+        cache.insert(PathBuf::from("/big"), entry(11), 10);
+        assert!(cache.entries.is_empty());
+        assert_eq!(cache.size, 0);
+    }
+
+    #[test]
+    fn keeps_gzip_only_when_smaller() {
+        let file = CachedFile::read(&write_file("gzip", &b"<p>repeated</p>".repeat(20)), true).unwrap();
+        assert!(file.gzip.as_ref().is_some_and(|gzip| gzip.len() < file.len as usize));
+        assert_eq!(file.get_size(), file.len as usize + file.gzip.as_ref().unwrap().len());
+        assert_eq!(file.get_gzip_etag(), format!("{}-gz\"", &file.etag[..file.etag.len() - 1]));
+
+        let file = CachedFile::read(&write_file("tiny", b"a"), true).unwrap();
+        assert!(file.gzip.is_none());
+    }
+}
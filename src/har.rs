@@ -0,0 +1,216 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::json::JsonValue;
+use crate::str_util::base64_encode;
+use crate::transcript_reader::RecordedExchange;
+
+// Builds a HAR 1.2 log (http://www.softwareishard.com/blog/har-12-spec/) from recorded exchanges.
+// Exchanges without a request line, e.g. connections that sent nothing, are skipped.
+pub fn build_har(exchanges: &[RecordedExchange]) -> JsonValue {
+    let mut exchanges: Vec<&RecordedExchange> = exchanges.iter().filter(|exchange| exchange.request_line.is_some()).collect();
+    exchanges.sort_by_key(|exchange| exchange.request_time.or(exchange.opened));
+
+    let creator = JsonValue::object()
+        .with("name", "myhttp")
+        .with("version", env!("CARGO_PKG_VERSION"));
+
+    let log = JsonValue::object()
+        .with("version", "1.2")
+        .with("creator", creator)
+        .with("entries", exchanges.into_iter().map(build_entry).collect::<Vec<JsonValue>>());
+
+    JsonValue::object().with("log", log)
+}
+
+fn build_entry(exchange: &RecordedExchange) -> JsonValue {
+    let started = exchange.request_time.or(exchange.opened).unwrap_or_default();
+
+    // Missing phases count as zero, the spec does not allow -1 for these
+    let send = get_millis(exchange.request_time, exchange.request_end);
+    let wait = get_millis(exchange.request_end.or(exchange.request_time), exchange.response_time);
+    let receive = get_millis(exchange.response_time, exchange.last_time);
+
+    let timings = JsonValue::object()
+        .with("send", send)
+        .with("wait", wait)
+        .with("receive", receive);
+
+    JsonValue::object()
+        .with("startedDateTime", started.to_rfc3339_opts(SecondsFormat::Millis, true))
+        .with("time", send + wait + receive)
+        .with("request", build_request(exchange))
+        .with("response", build_response(exchange))
+        .with("cache", JsonValue::object())
+        .with("timings", timings)
+        .with("connection", exchange.peer.as_str())
+        .with("_requestId", exchange.request_id.as_str())
+        .with("_connectionId", exchange.connection_id.as_deref())
+}
+
+fn build_request(exchange: &RecordedExchange) -> JsonValue {
+    let (method, target, version) = exchange.get_request_parts().unwrap_or(("", "", ""));
+
+    let url = if target.starts_with('/') {
+        format!("http://{}{}", exchange.get_request_header("Host").unwrap_or("localhost"), target)
+    } else {
+        target.to_string()
+    };
+
+    let query_string: Vec<JsonValue> = target.split_once('?')
+        .map(|(_, query)| query.split('&').filter(|pair| !pair.is_empty()).map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            build_pair(name, value)
+        }).collect())
+        .unwrap_or_default();
+
+    JsonValue::object()
+        .with("method", method)
+        .with("url", url)
+        .with("httpVersion", version)
+        .with("cookies", Vec::<JsonValue>::new())
+        .with("headers", build_headers(&exchange.request_headers))
+        .with("queryString", query_string)
+        .with("headersSize", -1)
+        .with("bodySize", 0)
+}
+
+fn build_response(exchange: &RecordedExchange) -> JsonValue {
+    let status_line = exchange.status_line.as_deref().unwrap_or("");
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next().unwrap_or("");
+    let status_text = parts.nth(1).unwrap_or("");
+
+    let content_length = exchange.get_response_header("Content-Length").and_then(|length| length.parse::<u64>().ok());
+    let mime_type = exchange.get_response_header("Content-Type").unwrap_or("");
+
+    let mut content = JsonValue::object()
+        .with("size", exchange.response_body.as_ref().map(|body| body.size).or(content_length).unwrap_or(0))
+        .with("mimeType", mime_type);
+
+    if let Some(body) = &exchange.response_body {
+        if let Some(text) = &body.text {
+            content = content.with("text", text.as_str());
+        } else if let Some(binary) = &body.binary {
+            content = content.with("text", base64_encode(binary)).with("encoding", "base64");
+        }
+
+        if body.truncated {
+            content = content.with("comment", "Body was truncated in the transcript");
+        }
+    }
+
+    JsonValue::object()
+        .with("status", exchange.status.unwrap_or(0))
+        .with("statusText", status_text)
+        .with("httpVersion", version)
+        .with("cookies", Vec::<JsonValue>::new())
+        .with("headers", build_headers(&exchange.response_headers))
+        .with("content", content)
+        .with("redirectURL", exchange.get_response_header("Location").unwrap_or(""))
+        .with("headersSize", -1)
+        .with("bodySize", content_length.map(|length| length as f64).unwrap_or(-1.0))
+}
+
+fn build_headers(headers: &[(String, String)]) -> Vec<JsonValue> {
+    headers.iter().map(|(name, value)| build_pair(name, value)).collect()
+}
+
+fn build_pair(name: &str, value: &str) -> JsonValue {
+    JsonValue::object().with("name", name).with("value", value)
+}
+
+fn get_millis(from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> f64 {
+    match (from, to) {
+        (Some(from), Some(to)) => (to - from).num_microseconds().unwrap_or(0).max(0) as f64 / 1000.0,
+        _ => 0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::transcript_reader::read_transcript;
+
+    const TEXT: &str = r#"[07:09:34.222] [76ad1b3be2f26add] 127.0.0.1:50818 New transcript for HTTP connection
+[07:09:34.222] [76ad1b3be2f26add] 127.0.0.1:50818  at 2026-10-19 07:09:34.222 UTC
+[07:09:34.222] [76ad1b3be2f26add] 127.0.0.1:50818  by 127.0.0.1:50818
+[07:09:34.223] [76ad1b3be2f26add] 127.0.0.1:50818 HTTP Request
+[07:09:34.223] [76ad1b3be2f26add] 127.0.0.1:50818 --> GET /test.html?a=1&b= HTTP/1.1
+[07:09:34.223] [76ad1b3be2f26add] 127.0.0.1:50818 --> Host: 127.0.0.1:8080
+[07:09:34.223] [76ad1b3be2f26add] 127.0.0.1:50818 --> X-Request-Id: rt-1
+[07:09:34.223] [76ad1b3be2f26add] 127.0.0.1:50818 --> 
+[07:09:34.223] [76ad1b3be2f26add] 127.0.0.1:50818 Request id 76ad1b3be2f26add replaced by X-Request-Id rt-1
+[07:09:34.223] [rt-1] 127.0.0.1:50818 HTTP Response
+[07:09:34.224] [rt-1] 127.0.0.1:50818 <-- HTTP/1.1 200 OK
+[07:09:34.224] [rt-1] 127.0.0.1:50818 <-- Content-Type: text/html
+[07:09:34.224] [rt-1] 127.0.0.1:50818 <-- Content-Length: 179
+[07:09:34.224] [rt-1] 127.0.0.1:50818 <-- 
+[07:09:34.224] [rt-1] 127.0.0.1:50818 <-- <html>
+[07:09:34.224] [rt-1] 127.0.0.1:50818 <--     <head>
+[07:09:34.224] [rt-1] 127.0.0.1:50818 <--       
+[07:09:34.224] [rt-1] 127.0.0.1:50818 <-- <155 more bytes not captured>
+[07:09:34.227] [rt-1] 127.0.0.1:50818 Transcript ended after 4708µs
+"#;
+
+    const JSON: &str = r#"{"timestamp":"2026-10-19T07:09:34.222Z","connection_id":"6ae2dc354b8887e0","request_id":"76ad1b3be2f26add","peer":"127.0.0.1:50818","event":"open","direction":null}
+{"timestamp":"2026-10-19T07:09:34.223Z","connection_id":"6ae2dc354b8887e0","request_id":"76ad1b3be2f26add","peer":"127.0.0.1:50818","event":"request_line","direction":"-->","line":"GET /test.html?a=1&b= HTTP/1.1"}
+{"timestamp":"2026-10-19T07:09:34.223Z","connection_id":"6ae2dc354b8887e0","request_id":"76ad1b3be2f26add","peer":"127.0.0.1:50818","event":"header","direction":"-->","name":"Host","value":"127.0.0.1:8080"}
+{"timestamp":"2026-10-19T07:09:34.223Z","connection_id":"6ae2dc354b8887e0","request_id":"76ad1b3be2f26add","peer":"127.0.0.1:50818","event":"header","direction":"-->","name":"X-Request-Id","value":"rt-1"}
+{"timestamp":"2026-10-19T07:09:34.223Z","connection_id":"6ae2dc354b8887e0","request_id":"76ad1b3be2f26add","peer":"127.0.0.1:50818","event":"header_end","direction":"-->"}
+{"timestamp":"2026-10-19T07:09:34.223Z","connection_id":"6ae2dc354b8887e0","request_id":"76ad1b3be2f26add","peer":"127.0.0.1:50818","event":"message","direction":null,"message":"Request id 76ad1b3be2f26add replaced by X-Request-Id rt-1"}
+{"timestamp":"2026-10-19T07:09:34.224Z","connection_id":"6ae2dc354b8887e0","request_id":"rt-1","peer":"127.0.0.1:50818","event":"status","direction":"<--","status":200,"line":"HTTP/1.1 200 OK"}
+{"timestamp":"2026-10-19T07:09:34.224Z","connection_id":"6ae2dc354b8887e0","request_id":"rt-1","peer":"127.0.0.1:50818","event":"header","direction":"<--","name":"Content-Type","value":"text/html"}
+{"timestamp":"2026-10-19T07:09:34.224Z","connection_id":"6ae2dc354b8887e0","request_id":"rt-1","peer":"127.0.0.1:50818","event":"header","direction":"<--","name":"Content-Length","value":"179"}
+{"timestamp":"2026-10-19T07:09:34.224Z","connection_id":"6ae2dc354b8887e0","request_id":"rt-1","peer":"127.0.0.1:50818","event":"header_end","direction":"<--"}
+{"timestamp":"2026-10-19T07:09:34.224Z","connection_id":"6ae2dc354b8887e0","request_id":"rt-1","peer":"127.0.0.1:50818","event":"body","direction":"<--","bytes":179,"body":"<html>\n    <head>\n      ","truncated":true}
+{"timestamp":"2026-10-19T07:09:34.227Z","connection_id":"6ae2dc354b8887e0","request_id":"rt-1","peer":"127.0.0.1:50818","event":"close","direction":null,"duration_us":4708,"bytes_in":114,"bytes_out":335}
+"#;
+
+    fn export(contents: &str) -> JsonValue {
+        let fallback = NaiveDate::from_ymd_opt(2000, 1, 1).unwrap();
+        build_har(&read_transcript(contents, fallback))
+    }
+
+    fn entry(har: &JsonValue) -> &JsonValue {
+        let entries = har.get("log").and_then(|log| log.get("entries")).and_then(JsonValue::as_array).unwrap();
+        assert_eq!(entries.len(), 1);
+        &entries[0]
+    }
+
+    fn field<'a>(value: &'a JsonValue, path: &[&str]) -> &'a JsonValue {
+        path.iter().fold(value, |value, key| value.get(key).unwrap_or_else(|| panic!("missing {}", key)))
+    }
+
+    #[test]
+    fn exports_text_and_json_transcripts_alike() {
+        let text = export(TEXT);
+        let json = export(JSON);
+        assert_eq!(field(&text, &["log", "version"]).as_str(), Some("1.2"));
+
+        // The connection id is only in JSON transcripts
+        let (text, json) = (entry(&text), entry(&json));
+        for path in [&["request"][..], &["response"], &["timings"], &["startedDateTime"], &["_requestId"]] {
+            assert_eq!(field(text, path).to_string(), field(json, path).to_string(), "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn exports_request_and_response() {
+        let har = export(TEXT);
+        let entry = entry(&har);
+
+        assert_eq!(field(entry, &["startedDateTime"]).as_str(), Some("2026-10-19T07:09:34.223Z"));
+        assert_eq!(field(entry, &["_requestId"]).as_str(), Some("rt-1"));
+        assert_eq!(field(entry, &["request", "url"]).as_str(), Some("http://127.0.0.1:8080/test.html?a=1&b="));
+        assert_eq!(field(entry, &["request", "queryString"]).to_string(), r#"[{"name":"a","value":"1"},{"name":"b","value":""}]"#);
+        assert_eq!(field(entry, &["response", "status"]).as_f64(), Some(200.0));
+        assert_eq!(field(entry, &["response", "statusText"]).as_str(), Some("OK"));
+        assert_eq!(field(entry, &["response", "content", "size"]).as_f64(), Some(179.0));
+        assert_eq!(field(entry, &["response", "content", "text"]).as_str(), Some("<html>\n    <head>\n      "));
+        assert!(field(entry, &["response", "content"]).get("comment").is_some());
+        assert_eq!(field(entry, &["timings", "wait"]).as_f64(), Some(1.0));
+        assert_eq!(field(entry, &["timings", "receive"]).as_f64(), Some(3.0));
+    }
+}
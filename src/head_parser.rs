@@ -0,0 +1,242 @@
+use crate::config::Limits;
+use crate::http_error::HttpError;
+use crate::request;
+
+pub enum HeadState {
+    // Waiting for more bytes
+    Incomplete,
+    // The blank line ending the header section arrived
+    Complete,
+    // A limit was crossed or a line was malformed, the lines before it are still handed over
+    Invalid
+}
+
+// How the head handed over by `into_head` ended
+pub enum HeadEnd {
+    Complete,
+    // The connection closed first, with or without a request line
+    Closed,
+    Invalid(HttpError)
+}
+
+// Collects a request head from non-blocking reads, picking up where the previous read stopped.
+// Lines are split off and checked against the limits as they arrive, `HttpRequest::read_parsed_head`
+// then takes them as they are instead of reading the head again.
+pub struct HeadParser {
+    // The line being read, and how far it was already searched for its end
+    buffer: Vec<u8>,
+    scanned: usize,
+    // The request line and header lines read so far
+    lines: Vec<String>,
+    complete: bool,
+    error: Option<HttpError>,
+    empty_lines: usize,
+    header_count: usize,
+    header_size: usize,
+    max_request_line: usize,
+    max_header_line: usize,
+    max_header_size: usize,
+    max_header_count: usize
+}
+
+impl HeadParser {
+    pub fn new(limits: &Limits) -> Self {
+        Self {
+            buffer: Vec::new(),
+            scanned: 0,
+            lines: Vec::new(),
+            complete: false,
+            error: None,
+            empty_lines: 0,
+            header_count: 0,
+            header_size: 0,
+            max_request_line: limits.max_request_line,
+            max_header_line: limits.max_header_line,
+            max_header_size: limits.max_header_size,
+            max_header_count: limits.max_header_count
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) -> HeadState {
+        self.buffer.extend_from_slice(data);
+
+        let mut line_start = 0;
+        while let Some(position) = self.buffer[self.scanned..].iter().position(|byte| *byte == b'\n') {
+            let end = self.scanned + position;
+            let limit = self.get_line_limit();
+            // The blocking reader takes a line as long as its LF is within limit + 2 bytes
+            if end + 1 - line_start > limit + 2 {
+                return self.reject(request::line_too_long(self.has_request_line(), limit));
+            }
+
+            let mut line_end = end;
+            if line_end > line_start && self.buffer[line_end - 1] == b'\r' {
+                line_end -= 1;
+            }
+
+            let line = self.buffer[line_start..line_end].to_vec();
+            line_start = end + 1;
+            self.scanned = end + 1;
+
+            if line.len() > limit {
+                return self.reject(request::line_too_long(self.has_request_line(), limit));
+            }
+
+            if line.is_empty() {
+                // Leading empty lines before the request line are tolerated (RFC 9112 2.2)
+                if self.has_request_line() {
+                    self.complete = true;
+                    return HeadState::Complete;
+                }
+
+                // Bounded like `HttpRequest::read_head` bounds them
+                self.empty_lines += 1;
+                if self.empty_lines > self.max_header_count || self.empty_lines * 2 > self.max_request_line {
+                    return self.reject(request::too_many_empty_lines());
+                }
+
+                continue;
+            }
+
+            let Ok(line) = String::from_utf8(line) else {
+                return self.reject(request::invalid_utf8());
+            };
+
+            if self.has_request_line() {
+                self.header_count += 1;
+                self.header_size += line.len() + 2;
+
+                if self.header_count > self.max_header_count {
+                    return self.reject(request::too_many_header_fields(self.max_header_count));
+                }
+
+                if self.header_size > self.max_header_size {
+                    return self.reject(request::header_section_too_large(self.max_header_size));
+                }
+            }
+
+            self.lines.push(line);
+        }
+
+        // Only the unfinished line is kept
+        self.buffer.drain(..line_start);
+        self.scanned = self.buffer.len();
+
+        // The blocking reader gives up on a line once it read limit + 2 bytes without finding its end
+        let limit = self.get_line_limit();
+        if self.buffer.len() >= limit + 2 {
+            return self.reject(request::line_too_long(self.has_request_line(), limit));
+        }
+
+        HeadState::Incomplete
+    }
+
+    // Nothing was received yet, not even an empty line
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty() && self.lines.is_empty() && self.empty_lines == 0
+    }
+
+    // The request line and header lines, and how the head ended. Bytes after the head are dropped,
+    // request bodies are not read.
+    pub fn into_head(self) -> (Vec<String>, HeadEnd) {
+        let end = match self.error {
+            Some(err) => HeadEnd::Invalid(err),
+            None if self.complete => HeadEnd::Complete,
+            None => HeadEnd::Closed
+        };
+
+        (self.lines, end)
+    }
+
+    fn reject(&mut self, err: HttpError) -> HeadState {
+        self.error = Some(err);
+        HeadState::Invalid
+    }
+
+    fn has_request_line(&self) -> bool {
+        !self.lines.is_empty()
+    }
+
+    fn get_line_limit(&self) -> usize {
+        if self.has_request_line() { self.max_header_line } else { self.max_request_line }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_all(parser: &mut HeadParser, data: &[u8]) -> HeadState {
+        // A byte at a time, so every line end is found across reads
+        let mut state = HeadState::Incomplete;
+        for byte in data {
+            state = parser.push(std::slice::from_ref(byte));
+            if !matches!(state, HeadState::Incomplete) {
+                break;
+            }
+        }
+
+        state
+    }
+
+    fn parse(limits: &Limits, data: &[u8]) -> (Vec<String>, HeadEnd) {
+        let mut parser = HeadParser::new(limits);
+        push_all(&mut parser, data);
+        parser.into_head()
+    }
+
+    #[test]
+    fn completes_on_the_blank_line() {
+        let mut parser = HeadParser::new(&Limits::default());
+        assert!(matches!(push_all(&mut parser, b"\r\nGET / HTTP/1.1\r\nHost: x\r\n\r\nbody"), HeadState::Complete));
+
+        let (lines, end) = parser.into_head();
+        assert_eq!(lines, ["GET / HTTP/1.1", "Host: x"]);
+        assert!(matches!(end, HeadEnd::Complete));
+    }
+
+    #[test]
+    fn splits_lines_across_reads() {
+        let mut parser = HeadParser::new(&Limits::default());
+        assert!(matches!(parser.push(b"GET / HT"), HeadState::Incomplete));
+        assert!(matches!(parser.push(b"TP/1.1\nHost: x\r"), HeadState::Incomplete));
+        assert!(matches!(parser.push(b"\n\n"), HeadState::Complete));
+        assert_eq!(parser.into_head().0, ["GET / HTTP/1.1", "Host: x"]);
+    }
+
+    #[test]
+    fn bounds_leading_empty_lines() {
+        let limits = Limits { max_header_count: 4, ..Limits::default() };
+        let mut parser = HeadParser::new(&limits);
+        assert!(matches!(push_all(&mut parser, &b"\r\n".repeat(100)), HeadState::Invalid));
+        assert!(matches!(parser.into_head().1, HeadEnd::Invalid(_)));
+    }
+
+    #[test]
+    fn bounds_lines_and_header_section() {
+        let limits = Limits { max_request_line: 8, ..Limits::default() };
+        assert!(matches!(push_all(&mut HeadParser::new(&limits), b"GET /0123456789 HTTP/1.1\r\n"), HeadState::Invalid));
+
+        let limits = Limits { max_header_count: 1, ..Limits::default() };
+        let (lines, end) = parse(&limits, b"GET / HTTP/1.1\r\nHost: x\r\nA: 1\r\n\r\n");
+        assert_eq!(lines, ["GET / HTTP/1.1", "Host: x"]);
+        assert!(matches!(end, HeadEnd::Invalid(err) if err.code.get_code() == 431));
+    }
+
+    #[test]
+    fn rejects_invalid_utf8() {
+        assert!(matches!(parse(&Limits::default(), b"GET /\xff HTTP/1.1\r\n").1, HeadEnd::Invalid(_)));
+    }
+
+    #[test]
+    fn tells_closed_heads_apart() {
+        let mut parser = HeadParser::new(&Limits::default());
+        assert!(parser.is_empty());
+        parser.push(b"\r\n");
+        assert!(!parser.is_empty());
+
+        let (lines, end) = parse(&Limits::default(), b"GET / HTTP/1.1\r\nHost");
+        assert_eq!(lines, ["GET / HTTP/1.1"]);
+        assert!(matches!(end, HeadEnd::Closed));
+    }
+}
@@ -1,11 +1,22 @@
 use std::{collections::HashMap, fmt};
 
+use chrono::{DateTime, Utc};
+
 use crate::http_error::{HttpError, http_errors};
+use crate::media_type::{MediaRange, MediaType};
+use crate::str_util::base64_decode;
+use crate::util::{get_http_date, parse_http_date};
 
 pub struct HttpHeaders {
     pub map: HashMap<String, String>
 }
 
+#[derive(Clone)]
+pub struct Authorization {
+    pub scheme: String,
+    pub credentials: String
+}
+
 #[allow(unused)]
 impl HttpHeaders {
     pub fn new() -> Self {
@@ -15,21 +26,60 @@ impl HttpHeaders {
     }
 
     pub fn add_from_line(&mut self, line: &str) -> Result<(), HttpError> {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            return Err(http_errors::msg::bad_request("Obsolete line folding is not accepted")
+                .set_info("Malformed header"));
+        }
+
         let mut parts = line.splitn(2, ':');
 
         let name = parts.next()
                                 .ok_or_else(|| {
                                     http_errors::msg::bad_request("Failed to fetch header name")
                                         .set_info("Malformed header")
-                                })?.trim().to_string();
+                                })?;
 
         let data = parts.next()
                                 .ok_or_else(|| {
                                     http_errors::msg::bad_request("Failed to fetch header data")
                                         .set_info("Malformed header")
-                                })?.trim().to_string();
+                                })?.trim_matches(|c| c == ' ' || c == '\t');
+
+        // Also rejects whitespace between the name and the colon (RFC 9112 5.1)
+        if !is_token(name) {
+            return Err(http_errors::msg::bad_request(format!("Header name \"{}\" is not a valid token", name).as_str())
+                .set_info("Malformed header"));
+        }
+
+        if data.bytes().any(|b| (b < 0x20 && b != b'\t') || b == 0x7f) {
+            return Err(http_errors::msg::bad_request(format!("Header {} contains control characters", name).as_str())
+                .set_info("Malformed header"));
+        }
+
+        self.add_field(name, data)
+    }
+
+    // Repeated fields are combined into a list, except where a repeat is a smuggling vector
+    fn add_field(&mut self, name: &str, value: &str) -> Result<(), HttpError> {
+        let Some(existing) = self.get(name).cloned() else {
+            self.map.insert(name.to_string(), value.to_string());
+            return Ok(());
+        };
+
+        if name.eq_ignore_ascii_case("Content-Length") {
+            if existing != value {
+                return Err(http_errors::msg::bad_request("Conflicting Content-Length headers")
+                    .set_info("Malformed header"));
+            }
+        } else if name.eq_ignore_ascii_case("Host") {
+            return Err(http_errors::msg::bad_request("Multiple Host headers")
+                .set_info("Malformed header"));
+        } else {
+            // Cookie pairs are separated by semicolons, not commas (RFC 6265 5.4)
+            let separator = if name.eq_ignore_ascii_case("Cookie") { "; " } else { ", " };
+            self.set(name, format!("{}{}{}", existing, separator, value).as_str());
+        }
 
-        self.map.insert(name, data);
         Ok(())
     }
 
@@ -51,28 +101,252 @@ impl HttpHeaders {
         Ok(())
     }
 
-    pub fn get_from_name(&self, name: &String) -> Option<String> {
-        self.map.get(name).cloned()
+    pub fn get_from_name(&self, name: &str) -> Option<String> {
+        self.get(name).cloned()
+    }
+
+    // Field names are case-insensitive (RFC 9110 5.1)
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.map.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value)
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.map.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let key = self.map.keys().find(|key| key.eq_ignore_ascii_case(name))?.clone();
+        self.map.remove(&key)
     }
 
     pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
         self.map.iter()
     }
 
+    // Typed accessors
+    pub fn content_length(&self) -> Result<Option<u64>, HttpError> {
+        let Some(value) = self.get("Content-Length") else {
+            return Ok(None);
+        };
+
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(Self::malformed("Content-Length", value));
+        }
+
+        value.parse::<u64>().map(Some).map_err(|_| Self::malformed("Content-Length", value))
+    }
+
+    pub fn set_content_length(&mut self, length: u64) {
+        self.set("Content-Length", &length.to_string());
+    }
+
+    pub fn content_type(&self) -> Result<Option<MediaType>, HttpError> {
+        self.get("Content-Type").map(|value| MediaType::parse(value)).transpose()
+    }
+
+    pub fn set_content_type(&mut self, media_type: &MediaType) {
+        self.set("Content-Type", &media_type.to_string());
+    }
+
+    pub fn accept(&self) -> Result<Option<Vec<MediaRange>>, HttpError> {
+        self.get("Accept").map(|value| MediaRange::parse_list(value)).transpose()
+    }
+
+    pub fn set_accept(&mut self, ranges: &[MediaRange]) {
+        let value = ranges.iter().map(|range| range.to_string()).collect::<Vec<_>>().join(", ");
+        self.set("Accept", &value);
+    }
+
+    pub fn if_modified_since(&self) -> Result<Option<DateTime<Utc>>, HttpError> {
+        let Some(value) = self.get("If-Modified-Since") else {
+            return Ok(None);
+        };
+
+        parse_http_date(value).map(Some).ok_or_else(|| Self::malformed("If-Modified-Since", value))
+    }
+
+    pub fn set_if_modified_since(&mut self, date_time: &DateTime<Utc>) {
+        self.set("If-Modified-Since", &get_http_date(date_time));
+    }
+
+    pub fn authorization(&self) -> Result<Option<Authorization>, HttpError> {
+        let Some(value) = self.get("Authorization") else {
+            return Ok(None);
+        };
+
+        let (scheme, credentials) = value.split_once(' ').unwrap_or((value.as_str(), ""));
+        if !is_token(scheme) {
+            return Err(Self::malformed("Authorization", value));
+        }
+
+        Ok(Some(Authorization {
+            scheme: scheme.to_string(),
+            credentials: credentials.trim().to_string()
+        }))
+    }
+
+    pub fn set_authorization(&mut self, authorization: &Authorization) {
+        self.set("Authorization", &authorization.to_string());
+    }
+
+    pub fn cookie(&self) -> Result<Option<Vec<(String, String)>>, HttpError> {
+        let Some(value) = self.get("Cookie") else {
+            return Ok(None);
+        };
+
+        let mut cookies = Vec::new();
+        for pair in value.split(';') {
+            let pair = pair.trim();
+            if pair.is_empty() {
+                continue;
+            }
+
+            let (name, value) = pair.split_once('=').ok_or_else(|| Self::malformed("Cookie", pair))?;
+            if !is_token(name) {
+                return Err(Self::malformed("Cookie", pair));
+            }
+
+            cookies.push((name.to_string(), value.trim_matches('"').to_string()));
+        }
+
+        Ok(Some(cookies))
+    }
+
+    pub fn set_cookie(&mut self, cookies: &[(String, String)]) {
+        let value = cookies.iter().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<_>>().join("; ");
+        self.set("Cookie", &value);
+    }
+
+    fn malformed(name: &str, value: &str) -> HttpError {
+        http_errors::msg::bad_request(format!("Invalid {} header value \"{}\"", name, value).as_str())
+            .set_info("Malformed header")
+    }
+
     // Static
     pub fn is_restricted_header(name: &str) -> bool {
-        name == "Content-Type" || name == "Content-Length"
+        name.eq_ignore_ascii_case("Content-Type") || name.eq_ignore_ascii_case("Content-Length")
+    }
+}
+
+#[allow(unused)]
+impl Authorization {
+    pub fn basic(user: &str, password: &str) -> Self {
+        Self {
+            scheme: String::from("Basic"),
+            credentials: crate::str_util::base64_encode(format!("{}:{}", user, password).as_bytes())
+        }
+    }
+
+    pub fn is_scheme(&self, scheme: &str) -> bool {
+        self.scheme.eq_ignore_ascii_case(scheme)
+    }
+
+    pub fn basic_credentials(&self) -> Result<(String, String), HttpError> {
+        let malformed = || http_errors::msg::bad_request("Invalid Basic credentials").set_info("Malformed header");
+
+        if !self.is_scheme("Basic") {
+            return Err(malformed());
+        }
+
+        let decoded = base64_decode(&self.credentials).ok_or_else(malformed)?;
+        let decoded = String::from_utf8(decoded).map_err(|_| malformed())?;
+        let (user, password) = decoded.split_once(':').ok_or_else(malformed)?;
+
+        Ok((user.to_string(), password.to_string()))
+    }
+}
+
+// token = 1*tchar (RFC 9110 5.6.2)
+pub fn is_token(value: &str) -> bool {
+    !value.is_empty() && value.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+impl fmt::Display for Authorization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.credentials.is_empty() {
+            write!(f, "{}", self.scheme)
+        } else {
+            write!(f, "{} {}", self.scheme, self.credentials)
+        }
     }
 }
 
 impl fmt::Display for HttpHeaders {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "HttpHeaders[[\r\n")?;
-    
+
         for (key, value) in self.iter() {
             write!(f, "  {}: {}\r\n", key, value)?;
         }
 
         write!(f, "]]")
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookups_ignore_case() {
+        let mut headers = HttpHeaders::new();
+        headers.set("Content-Length", "12");
+        assert_eq!(headers.get("content-length").map(String::as_str), Some("12"));
+        assert_eq!(headers.content_length().unwrap(), Some(12));
+        assert!(HttpHeaders::is_restricted_header("content-type"));
+    }
+
+    #[test]
+    fn typed_accessors_reject_malformed_values() {
+        let mut headers = HttpHeaders::new();
+        headers.set("Content-Length", "-1");
+        headers.set("If-Modified-Since", "yesterday");
+        assert_eq!(headers.content_length().unwrap_err().code.get_code(), 400);
+        assert_eq!(headers.if_modified_since().unwrap_err().code.get_code(), 400);
+    }
+
+    #[test]
+    fn repeated_fields_are_combined() {
+        let mut headers = HttpHeaders::new();
+        for line in ["Cookie: a=1", "Cookie: b=2", "Accept: text/html", "accept: */*"] {
+            headers.add_from_line(line).unwrap();
+        }
+
+        let cookies = vec![(String::from("a"), String::from("1")), (String::from("b"), String::from("2"))];
+        assert_eq!(headers.cookie().unwrap(), Some(cookies));
+        assert_eq!(headers.get("Accept").map(String::as_str), Some("text/html, */*"));
+    }
+
+    #[test]
+    fn rejects_invalid_field_lines() {
+        let mut headers = HttpHeaders::new();
+        for line in [" folded: x", "Host : x", "No-Colon", "Bad: x\u{1}"] {
+            assert_eq!(headers.add_from_line(line).unwrap_err().code.get_code(), 400, "{}", line);
+        }
+
+        headers.add_from_line("Host: a").unwrap();
+        assert!(headers.add_from_line("Host: b").is_err());
+        headers.add_from_line("Content-Length: 1").unwrap();
+        assert!(headers.add_from_line("Content-Length: 2").is_err());
+    }
+
+    #[test]
+    fn basic_authorization_round_trips() {
+        let mut headers = HttpHeaders::new();
+        headers.set_authorization(&Authorization::basic("user", "pass:word"));
+
+        let authorization = headers.authorization().unwrap().unwrap();
+        assert!(authorization.is_scheme("basic"));
+        assert_eq!(authorization.basic_credentials().unwrap(), (String::from("user"), String::from("pass:word")));
+    }
+
+    #[test]
+    fn http_dates_round_trip() {
+        let date = DateTime::parse_from_rfc3339("1994-11-06T08:49:37Z").unwrap().with_timezone(&Utc);
+        let mut headers = HttpHeaders::new();
+        headers.set_if_modified_since(&date);
+        assert_eq!(headers.get("If-Modified-Since").map(String::as_str), Some("Sun, 06 Nov 1994 08:49:37 GMT"));
+        assert_eq!(headers.if_modified_since().unwrap(), Some(date));
+    }
+}
@@ -1,16 +1,107 @@
 
-use std::io::{Error, ErrorKind};
+use std::error::Error;
+use std::io;
+use std::sync::Arc;
 
 use crate::str_util::Builder;
 
-#[derive(Clone)]
-pub enum HttpCode {
-    E200,
-    E400,
-    E403,
-    E404,
-    E500,
-    E501
+// Generates the registered variants along with their numeric code and reason phrase
+macro_rules! http_codes {
+    ($($variant:ident => ($code:literal, $desc:literal)),* $(,)?) => {
+        #[derive(Clone, PartialEq, Debug)]
+        pub enum HttpCode {
+            $($variant,)*
+            Custom(i32, String)
+        }
+
+        impl HttpCode {
+            fn from_registered(code: i32) -> Option<Self> {
+                match code {
+                    $($code => Some(HttpCode::$variant),)*
+                    _ => None
+                }
+            }
+
+            pub fn get_code(&self) -> i32 {
+                match self {
+                    $(HttpCode::$variant => $code,)*
+                    HttpCode::Custom(code, _) => *code
+                }
+            }
+
+            pub fn get_desc(&self) -> &str {
+                match self {
+                    $(HttpCode::$variant => $desc,)*
+                    HttpCode::Custom(_, desc) => desc.as_str()
+                }
+            }
+        }
+    };
+}
+
+// IANA HTTP Status Code Registry
+http_codes! {
+    E100 => (100, "Continue"),
+    E101 => (101, "Switching Protocols"),
+    E102 => (102, "Processing"),
+    E103 => (103, "Early Hints"),
+    E200 => (200, "OK"),
+    E201 => (201, "Created"),
+    E202 => (202, "Accepted"),
+    E203 => (203, "Non-Authoritative Information"),
+    E204 => (204, "No Content"),
+    E205 => (205, "Reset Content"),
+    E206 => (206, "Partial Content"),
+    E207 => (207, "Multi-Status"),
+    E208 => (208, "Already Reported"),
+    E226 => (226, "IM Used"),
+    E300 => (300, "Multiple Choices"),
+    E301 => (301, "Moved Permanently"),
+    E302 => (302, "Found"),
+    E303 => (303, "See Other"),
+    E304 => (304, "Not Modified"),
+    E305 => (305, "Use Proxy"),
+    E307 => (307, "Temporary Redirect"),
+    E308 => (308, "Permanent Redirect"),
+    E400 => (400, "Bad Request"),
+    E401 => (401, "Unauthorized"),
+    E402 => (402, "Payment Required"),
+    E403 => (403, "Forbidden"),
+    E404 => (404, "Not Found"),
+    E405 => (405, "Method Not Allowed"),
+    E406 => (406, "Not Acceptable"),
+    E407 => (407, "Proxy Authentication Required"),
+    E408 => (408, "Request Timeout"),
+    E409 => (409, "Conflict"),
+    E410 => (410, "Gone"),
+    E411 => (411, "Length Required"),
+    E412 => (412, "Precondition Failed"),
+    E413 => (413, "Content Too Large"),
+    E414 => (414, "URI Too Long"),
+    E415 => (415, "Unsupported Media Type"),
+    E416 => (416, "Range Not Satisfiable"),
+    E417 => (417, "Expectation Failed"),
+    E421 => (421, "Misdirected Request"),
+    E422 => (422, "Unprocessable Content"),
+    E423 => (423, "Locked"),
+    E424 => (424, "Failed Dependency"),
+    E425 => (425, "Too Early"),
+    E426 => (426, "Upgrade Required"),
+    E428 => (428, "Precondition Required"),
+    E429 => (429, "Too Many Requests"),
+    E431 => (431, "Request Header Fields Too Large"),
+    E451 => (451, "Unavailable For Legal Reasons"),
+    E500 => (500, "Internal Server Error"),
+    E501 => (501, "Not Implemented"),
+    E502 => (502, "Bad Gateway"),
+    E503 => (503, "Service Unavailable"),
+    E504 => (504, "Gateway Timeout"),
+    E505 => (505, "HTTP Version Not Supported"),
+    E506 => (506, "Variant Also Negotiates"),
+    E507 => (507, "Insufficient Storage"),
+    E508 => (508, "Loop Detected"),
+    E510 => (510, "Not Extended"),
+    E511 => (511, "Network Authentication Required"),
 }
 
 pub enum HttpCodeRange {
@@ -21,33 +112,48 @@ pub enum HttpCodeRange {
     ServerError
 }
 
-#[derive(Clone)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HttpErrorKind {
+    Client,
+    Protocol,
+    Io,
+    Timeout,
+    Internal
+}
+
+#[derive(Clone, Debug)]
 pub struct HttpError {
     pub code: HttpCode,
+    pub kind: HttpErrorKind,
     pub desc: Option<String>,
-    pub info: Option<String>
+    pub info: Option<String>,
+    source: Option<Arc<dyn Error + Send + Sync>>
 }
 
 #[allow(unused)]
 impl HttpError {
     pub fn new(code: HttpCode) -> Self {
         Self {
-            code: code,
+            kind: HttpErrorKind::from_code(&code),
+            code,
             desc: None,
-            info: None
+            info: None,
+            source: None
         }
     }
 
     pub fn new_with_message(code: HttpCode, msg: &str) -> Self {
         Self {
-            code: code,
+            kind: HttpErrorKind::from_code(&code),
+            code,
             desc: Some(msg.to_string()),
-            info: None
+            info: None,
+            source: None
         }
     }
 
-    pub fn from_code(code: i32) -> Self {
-        Self::new(HttpCode::from(code))
+    pub fn from_code(code: i32) -> Result<Self, HttpError> {
+        Ok(Self::new(HttpCode::from(code)?))
     }
 
     pub fn get_error_msg(&self) -> String {
@@ -58,36 +164,123 @@ impl HttpError {
         }
     }
 
+    // Display plus every underlying cause, for logs rather than clients
+    pub fn get_chain_msg(&self) -> String {
+        describe_chain(self)
+    }
+
     pub fn set_info(&mut self, msg: &str) -> HttpError {
         self.info = Some(msg.to_string());
         self.to_owned()
     }
 
-    pub fn convert_from(e: Error, msg: Option<&str>) -> Self {
-        if let Some(msg) = msg {
-            println!("HTTP <-- IO Error: \"{}\" Reason: {}", e.to_string(), msg);
-            http_errors::msg::internal_server_error(format!("HTTP Error: \"{}\" from IO Error: \"{}\"", msg, e.to_string()).as_str())
-        } else {
-            println!("HTTP <-- IO Error: {}", e.to_string());
-            http_errors::msg::internal_server_error(format!("HTTP Error: {}", e.to_string()).as_str())
-        }
+    pub fn with_kind(mut self, kind: HttpErrorKind) -> Self {
+        self.kind = kind;
+        self
     }
 
-    pub fn convert_to(&self, msg: Option<&str>) -> Error {
-        if let Some(msg) = msg {
-            println!("IO <-- HTTP Error: \"{}\" Reason: {}", self.to_string(), msg);
-            Error::new(ErrorKind::Other, format!("IO Error: \"{}\" from HTTP Error: \"{}\"", msg, self.to_string()).as_str())
-        } else {
-            println!("IO <-- HTTP Error: {}", self.to_string());
-            Error::new(ErrorKind::Other, format!("IO Error: {}", self.to_string()))
+    pub fn with_source<E: Error + Send + Sync + 'static>(mut self, source: E) -> Self {
+        self.source = Some(Arc::new(source));
+        self
+    }
+
+    pub fn is_kind(&self, kind: HttpErrorKind) -> bool {
+        self.kind == kind
+    }
+
+    pub fn convert_from(e: io::Error, msg: Option<&str>) -> Self {
+        let kind = match e.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => HttpErrorKind::Timeout,
+            _ => HttpErrorKind::Io
+        };
+
+        let err = match msg {
+            Some(msg) => http_errors::msg::internal_server_error(msg),
+            None => http_errors::internal_server_error()
+        };
+
+        err.with_kind(kind).with_source(e)
+    }
+
+    pub fn convert_to(&self, msg: Option<&str>) -> io::Error {
+        let io_kind = match self.kind {
+            HttpErrorKind::Timeout => io::ErrorKind::TimedOut,
+            HttpErrorKind::Io => self.source.as_ref()
+                .and_then(|source| source.downcast_ref::<io::Error>())
+                .map(|source| source.kind())
+                .unwrap_or(io::ErrorKind::Other),
+            _ => io::ErrorKind::Other
+        };
+
+        // Wrap rather than flatten, so the original error stays reachable through source()
+        match msg {
+            Some(msg) => {
+                let mut context = HttpError::new_with_message(self.code.clone(), msg).with_kind(self.kind);
+                context.source = Some(Arc::new(self.clone()));
+                io::Error::new(io_kind, context)
+            },
+            None => io::Error::new(io_kind, self.clone())
         }
     }
 
-    pub fn convert_to_direct(err: HttpError) -> Error {
+    pub fn convert_to_direct(err: HttpError) -> io::Error {
         err.convert_to(None)
     }
 }
 
+#[allow(unused)]
+impl HttpErrorKind {
+    pub fn from_code(code: &HttpCode) -> Self {
+        match code.get_code() {
+            408 | 504 => HttpErrorKind::Timeout,
+            400 | 411 | 413 | 414 | 431 | 505 => HttpErrorKind::Protocol,
+            code if code >= 500 => HttpErrorKind::Internal,
+            _ => HttpErrorKind::Client
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        match self {
+            HttpErrorKind::Client => "client",
+            HttpErrorKind::Protocol => "protocol",
+            HttpErrorKind::Io => "io",
+            HttpErrorKind::Timeout => "timeout",
+            HttpErrorKind::Internal => "internal"
+        }
+    }
+}
+
+pub fn describe_chain(err: &dyn Error) -> String {
+    let mut msg = Builder::new(" <- ");
+    msg.append(&err.to_string());
+
+    let mut source = err.source();
+    while let Some(err) = source {
+        msg.append(&err.to_string());
+        source = err.source();
+    }
+
+    msg.result
+}
+
+impl From<io::Error> for HttpError {
+    fn from(e: io::Error) -> Self {
+        HttpError::convert_from(e, None)
+    }
+}
+
+impl From<HttpError> for io::Error {
+    fn from(e: HttpError) -> Self {
+        e.convert_to(None)
+    }
+}
+
+impl Error for HttpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_ref().map(|source| source.as_ref() as &(dyn Error + 'static))
+    }
+}
+
 // Normally wouldn't allow unused, but these are general helpers across the board,
 // and some might not be used now, but they might be used in the future
 #[allow(unused)]
@@ -99,6 +292,10 @@ pub mod http_errors {
             HttpError::new_with_message(HttpCode::E400, msg)
         }
 
+        pub fn unauthorized(msg: &str) -> HttpError {
+            HttpError::new_with_message(HttpCode::E401, msg)
+        }
+
         pub fn forbidden(msg: &str) -> HttpError {
             HttpError::new_with_message(HttpCode::E403, msg)
         }
@@ -107,6 +304,46 @@ pub mod http_errors {
             HttpError::new_with_message(HttpCode::E404, msg)
         }
 
+        pub fn method_not_allowed(msg: &str) -> HttpError {
+            HttpError::new_with_message(HttpCode::E405, msg)
+        }
+
+        pub fn not_acceptable(msg: &str) -> HttpError {
+            HttpError::new_with_message(HttpCode::E406, msg)
+        }
+
+        pub fn request_timeout(msg: &str) -> HttpError {
+            HttpError::new_with_message(HttpCode::E408, msg)
+        }
+
+        pub fn conflict(msg: &str) -> HttpError {
+            HttpError::new_with_message(HttpCode::E409, msg)
+        }
+
+        pub fn gone(msg: &str) -> HttpError {
+            HttpError::new_with_message(HttpCode::E410, msg)
+        }
+
+        pub fn content_too_large(msg: &str) -> HttpError {
+            HttpError::new_with_message(HttpCode::E413, msg)
+        }
+
+        pub fn uri_too_long(msg: &str) -> HttpError {
+            HttpError::new_with_message(HttpCode::E414, msg)
+        }
+
+        pub fn unsupported_media_type(msg: &str) -> HttpError {
+            HttpError::new_with_message(HttpCode::E415, msg)
+        }
+
+        pub fn too_many_requests(msg: &str) -> HttpError {
+            HttpError::new_with_message(HttpCode::E429, msg)
+        }
+
+        pub fn request_header_fields_too_large(msg: &str) -> HttpError {
+            HttpError::new_with_message(HttpCode::E431, msg)
+        }
+
         pub fn internal_server_error(msg: &str) -> HttpError {
             HttpError::new_with_message(HttpCode::E500, msg)
         }
@@ -114,6 +351,22 @@ pub mod http_errors {
         pub fn not_implemented(msg: &str) -> HttpError {
             HttpError::new_with_message(HttpCode::E501, msg)
         }
+
+        pub fn bad_gateway(msg: &str) -> HttpError {
+            HttpError::new_with_message(HttpCode::E502, msg)
+        }
+
+        pub fn service_unavailable(msg: &str) -> HttpError {
+            HttpError::new_with_message(HttpCode::E503, msg)
+        }
+
+        pub fn gateway_timeout(msg: &str) -> HttpError {
+            HttpError::new_with_message(HttpCode::E504, msg)
+        }
+
+        pub fn http_version_not_supported(msg: &str) -> HttpError {
+            HttpError::new_with_message(HttpCode::E505, msg)
+        }
     }
 
     use super::{ HttpError, HttpCode };
@@ -122,6 +375,10 @@ pub mod http_errors {
         HttpError::new(HttpCode::E400)
     }
 
+    pub fn unauthorized() -> HttpError {
+        HttpError::new(HttpCode::E401)
+    }
+
     pub fn forbidden() -> HttpError {
         HttpError::new(HttpCode::E403)
     }
@@ -130,6 +387,46 @@ pub mod http_errors {
         HttpError::new(HttpCode::E404)
     }
 
+    pub fn method_not_allowed() -> HttpError {
+        HttpError::new(HttpCode::E405)
+    }
+
+    pub fn not_acceptable() -> HttpError {
+        HttpError::new(HttpCode::E406)
+    }
+
+    pub fn request_timeout() -> HttpError {
+        HttpError::new(HttpCode::E408)
+    }
+
+    pub fn conflict() -> HttpError {
+        HttpError::new(HttpCode::E409)
+    }
+
+    pub fn gone() -> HttpError {
+        HttpError::new(HttpCode::E410)
+    }
+
+    pub fn content_too_large() -> HttpError {
+        HttpError::new(HttpCode::E413)
+    }
+
+    pub fn uri_too_long() -> HttpError {
+        HttpError::new(HttpCode::E414)
+    }
+
+    pub fn unsupported_media_type() -> HttpError {
+        HttpError::new(HttpCode::E415)
+    }
+
+    pub fn too_many_requests() -> HttpError {
+        HttpError::new(HttpCode::E429)
+    }
+
+    pub fn request_header_fields_too_large() -> HttpError {
+        HttpError::new(HttpCode::E431)
+    }
+
     pub fn internal_server_error() -> HttpError {
         HttpError::new(HttpCode::E500)
     }
@@ -137,42 +434,46 @@ pub mod http_errors {
     pub fn not_implemented() -> HttpError {
         HttpError::new(HttpCode::E501)
     }
+
+    pub fn bad_gateway() -> HttpError {
+        HttpError::new(HttpCode::E502)
+    }
+
+    pub fn service_unavailable() -> HttpError {
+        HttpError::new(HttpCode::E503)
+    }
+
+    pub fn gateway_timeout() -> HttpError {
+        HttpError::new(HttpCode::E504)
+    }
+
+    pub fn http_version_not_supported() -> HttpError {
+        HttpError::new(HttpCode::E505)
+    }
 }
 
 #[allow(unused)]
 impl HttpCode {
-    pub fn from(code: i32) -> Self {
-        match code {
-            200 => HttpCode::E200,
-            400 => HttpCode::E400,
-            403 => HttpCode::E403,
-            404 => HttpCode::E404,
-            500 => HttpCode::E500,
-            501 => HttpCode::E501,
-            _ => HttpCode::E501
-        }
+    // Unregistered codes within 100..=599 are accepted with an empty reason phrase
+    pub fn from(code: i32) -> Result<Self, HttpError> {
+        Self::custom(code, "")
     }
 
-    pub fn get_code(&self) -> i32 {
-        match self {
-            HttpCode::E200 => 200,
-            HttpCode::E400 => 400,
-            HttpCode::E403 => 403,
-            HttpCode::E404 => 404,
-            HttpCode::E500 => 500,
-            HttpCode::E501 => 501,
+    // Registered codes always become their variant with the registered reason phrase, so a code
+    // has a single value to compare and match against
+    pub fn custom(code: i32, reason: &str) -> Result<Self, HttpError> {
+        if !(100..=599).contains(&code) {
+            return Err(http_errors::msg::internal_server_error(format!("Invalid HTTP status code {}", code).as_str())
+                .set_info("Invalid status code"));
         }
-    }
 
-    pub fn get_desc(&self) -> &str {
-        match self {
-            HttpCode::E200 => "OK",
-            HttpCode::E400 => "Bad Request",
-            HttpCode::E403 => "Forbidden",
-            HttpCode::E404 => "Not Found",
-            HttpCode::E500 => "Internal Server Error",
-            HttpCode::E501 => "Not Implemented",
+        // reason-phrase = 1*( HTAB / SP / VCHAR / obs-text ) (RFC 9112 4)
+        if reason.chars().any(|c| c != '\t' && c.is_ascii_control()) {
+            return Err(http_errors::msg::internal_server_error(format!("Invalid reason phrase for HTTP status code {}", code).as_str())
+                .set_info("Invalid status code"));
         }
+
+        Ok(Self::from_registered(code).unwrap_or_else(|| HttpCode::Custom(code, reason.to_string())))
     }
 
     pub fn get_type(&self) -> HttpCodeRange {
@@ -192,6 +493,16 @@ impl HttpCode {
         }
     }
 
+    pub fn allows_body(&self) -> bool {
+        let code = self.get_code();
+        code >= 200 && code != 204 && code != 304
+    }
+
+    // 3xx codes that carry a Location to follow, 300, 304 and the unused ones do not
+    pub fn is_redirect(&self) -> bool {
+        matches!(self.get_code(), 301 | 302 | 303 | 307 | 308)
+    }
+
     pub fn is_error(&self) -> bool {
         let code_type = self.get_type();
         matches!(code_type, HttpCodeRange::ClientError) || matches!(code_type, HttpCodeRange::ServerError)
@@ -220,4 +531,69 @@ impl std::fmt::Display for HttpError {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registered_codes_map_to_their_variant() {
+        assert_eq!(HttpCode::from(404).unwrap(), HttpCode::E404);
+        assert_eq!(HttpCode::custom(404, "Nope").unwrap(), HttpCode::E404);
+        assert_eq!(HttpCode::E429.to_string(), "429 Too Many Requests");
+    }
+
+    #[test]
+    fn unregistered_codes_keep_their_reason() {
+        let code = HttpCode::custom(299, "Fine").unwrap();
+        assert_eq!((code.get_code(), code.get_desc()), (299, "Fine"));
+        assert!(!code.is_error());
+        assert!(HttpCode::custom(599, "").unwrap().is_error());
+    }
+
+    #[test]
+    fn rejects_invalid_codes() {
+        for code in [0, 99, 600, 1000] {
+            assert!(HttpCode::from(code).is_err(), "{}", code);
+        }
+
+        assert!(HttpCode::custom(299, "Bad\r\nX-Injected: 1").is_err());
+    }
+
+    #[test]
+    fn bodies_are_not_allowed_for_1xx_204_and_304() {
+        for code in [100, 204, 304] {
+            assert!(!HttpCode::from(code).unwrap().allows_body(), "{}", code);
+        }
+
+        assert!(HttpCode::from(200).unwrap().allows_body());
+    }
+
+    #[test]
+    fn kinds_follow_the_status() {
+        assert_eq!(HttpError::new(HttpCode::E408).kind, HttpErrorKind::Timeout);
+        assert_eq!(HttpError::new(HttpCode::E431).kind, HttpErrorKind::Protocol);
+        assert_eq!(HttpError::new(HttpCode::E404).kind, HttpErrorKind::Client);
+        assert_eq!(HttpError::new(HttpCode::E502).kind, HttpErrorKind::Internal);
+    }
+
+    #[test]
+    fn io_errors_keep_their_kind_and_source() {
+        let err = HttpError::convert_from(io::Error::new(io::ErrorKind::TimedOut, "stalled"), Some("Reading body"));
+        assert!(err.is_kind(HttpErrorKind::Timeout));
+        assert_eq!(err.code, HttpCode::E500);
+        assert_eq!(err.get_chain_msg(), "ERROR 500 Internal Server Error: Reading body <- stalled");
+
+        let io_err = err.convert_to(Some("Serving file"));
+        assert_eq!(io_err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(describe_chain(&io_err), "ERROR 500 Internal Server Error: Serving file <- ERROR 500 Internal Server Error: Reading body <- stalled");
+    }
+
+    #[test]
+    fn io_kind_survives_a_round_trip() {
+        let err = HttpError::from(io::Error::from(io::ErrorKind::BrokenPipe));
+        assert!(err.is_kind(HttpErrorKind::Io));
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::BrokenPipe);
+    }
+}
@@ -1,4 +1,4 @@
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 
 use crate::{http_error::{http_errors, HttpError}, HttpRequest};
 
@@ -17,7 +17,7 @@ pub fn get_valid_path(request: &HttpRequest) -> Result<String, HttpError> {
         return Err(http_errors::msg::internal_server_error("Failed to fetch valid path, request is not valid"));
     }
 
-    let base_path = Path::new("./public").canonicalize().map_err(|e| HttpError::convert_from(e, Some("Failed to resolve the path")))?;
+    let base_path = request.config.root.canonicalize().map_err(|e| HttpError::convert_from(e, Some("Failed to resolve the path")))?;
     let mut full_path = PathBuf::from(&base_path);
 
     full_path.push(request.get_file_name().trim_start_matches("/"));
@@ -32,4 +32,38 @@ pub fn get_valid_path(request: &HttpRequest) -> Result<String, HttpError> {
         },
         Err(_) => Err(http_errors::msg::not_found("Invalid path provided")),
     }
-}
\ No newline at end of file
+}
+
+// Resolves a request path to a directory inside the document root, if it is one
+pub fn get_directory(request: &HttpRequest, path: &str) -> Option<PathBuf> {
+    let base_path = request.config.root.canonicalize().ok()?;
+    let mut full_path = PathBuf::from(&base_path);
+    full_path.push(path.trim_start_matches("/"));
+
+    let canonical_path = full_path.canonicalize().ok()?;
+    (canonical_path.starts_with(&base_path) && canonical_path.is_dir()).then_some(canonical_path)
+}
+
+// True if Accept-Encoding lists `encoding` (or *) without q=0
+pub fn accepts_encoding(request: &HttpRequest, encoding: &str) -> bool {
+    let Some(accepted) = request.headers.get("Accept-Encoding") else {
+        return false;
+    };
+
+    accepted.split(',').any(|item| {
+        let mut parts = item.split(';').map(str::trim);
+        let name = parts.next().unwrap_or("");
+        let quality = parts.find_map(|param| param.strip_prefix("q=")).and_then(|q| q.parse::<f32>().ok()).unwrap_or(1.0);
+
+        (name.eq_ignore_ascii_case(encoding) || name == "*") && quality > 0.0
+    })
+}
+
+// If-None-Match uses the weak comparison (RFC 9110 13.1.2), so W/ prefixes are ignored
+pub fn matches_etag(request: &HttpRequest, etag: &str) -> bool {
+    let Some(value) = request.headers.get("If-None-Match") else {
+        return false;
+    };
+
+    value.trim() == "*" || value.split(',').any(|tag| tag.trim().trim_start_matches("W/") == etag.trim_start_matches("W/"))
+}
@@ -1,63 +1,133 @@
-use std::{fs::File, io::{Read, Write}, net::TcpStream, path::PathBuf};
+use std::{io::{BufRead, Read, Write}, net::TcpStream, path::PathBuf};
 
-use crate::{http_error::HttpError, transcript::Transcript};
+use crate::{access_log::{self, AccessLogEntry}, error_pages::render_error, http_error::{HttpCode, HttpError}, mapped_file::{MappedFile, CHUNK_SIZE}, metrics, request::{self, HttpRequest}, transcript::{BodyData, Direction, Transcript, TranscriptEvent}};
+
+pub enum LimitedLine {
+    Line(String),
+    TooLong,
+    End
+}
 
 pub fn get_stream_name(stream: &TcpStream) -> String {
     stream.peer_addr().map(|addr| addr.to_string()).unwrap_or(String::from("Unknown Address"))
 }
 
-pub fn write_line(ts: &mut Transcript, mut stream: &TcpStream, line: &str) -> Result<(), HttpError> {
-    ts.with_prefix("<--", |ts| ts.push(line))?;
-    writeln!(stream, "{}\r", line).map_err(|e| HttpError::convert_from(e, Some("Failed to write line to HTTP stream")))
+fn send_line<W: Write>(stream: &mut W, line: &str) -> Result<(), HttpError> {
+    write!(stream, "{}\r\n", line).map_err(|e| HttpError::convert_from(e, Some("Failed to write line to HTTP stream")))
+}
+
+pub fn write_status<W: Write>(ts: &Transcript, stream: &mut W, code: &HttpCode) -> Result<(), HttpError> {
+    let line = format!("HTTP/1.1 {}", code);
+    ts.record(TranscriptEvent::Status(code.get_code(), &line))?;
+    send_line(stream, &line)
 }
 
-pub fn write_data(ts: &mut Transcript, mut stream: &TcpStream, data: &Vec<u8>) -> Result<(), HttpError> {
-    ts.with_prefix("<--", |ts| ts.push("<binary data>"))?;
-    stream.write(&data[..]).map_err(|e| HttpError::convert_from(e, Some("Failed to write binary data to HTTP stream")))?;
+pub fn write_header<W: Write>(ts: &Transcript, stream: &mut W, name: &str, value: &str) -> Result<(), HttpError> {
+    ts.record(TranscriptEvent::Header(Direction::Outgoing, name, value))?;
+    send_line(stream, format!("{}: {}", name, value).as_str())
+}
+
+pub fn write_head_end<W: Write>(ts: &Transcript, stream: &mut W) -> Result<(), HttpError> {
+    ts.record(TranscriptEvent::HeaderEnd(Direction::Outgoing))?;
+    send_line(stream, "")
+}
+
+pub fn write_data<W: Write>(ts: &Transcript, stream: &mut W, data: &[u8]) -> Result<(), HttpError> {
+    ts.record(TranscriptEvent::Body(Direction::Outgoing, BodyData::Binary(data)))?;
+    stream.write_all(data).map_err(|e| HttpError::convert_from(e, Some("Failed to write binary data to HTTP stream")))?;
 
     Ok(())
 }
 
-pub fn write_body(ts: &mut Transcript, stream: &TcpStream, body: &str) -> Result<(), HttpError> {
+pub fn write_body<W: Write>(ts: &Transcript, stream: &mut W, body: &str) -> Result<(), HttpError> {
     let len = body.len();
 
-    write_line(ts, stream, format!("Content-Length: {}", len).as_str())?;
-    write_line(ts, stream, "")?;
-    write_line(ts, stream, body)
+    write_header(ts, stream, "Content-Length", &len.to_string())?;
+    write_head_end(ts, stream)?;
+
+    ts.record(TranscriptEvent::Body(Direction::Outgoing, BodyData::Text(body)))?;
+    stream.write_all(body.as_bytes()).map_err(|e| HttpError::convert_from(e, Some("Failed to write body to HTTP stream")))
 }
 
-pub fn write_body_data(ts: &mut Transcript, stream: &mut TcpStream, data: &Vec<u8>) -> Result<(), HttpError> {
+pub fn write_body_data<W: Write>(ts: &Transcript, stream: &mut W, data: &[u8]) -> Result<(), HttpError> {
     let len = data.len();
 
-    write_line(ts, stream, format!("Content-Length: {}", len).as_str())?;
-    write_line(ts, stream, "")?;
+    write_header(ts, stream, "Content-Length", &len.to_string())?;
+    write_head_end(ts, stream)?;
     write_data(ts, stream, data)
 }
 
-pub fn read_string_file(path: &str) -> Result<String, HttpError> {
-    let mut file = File::open(path).map_err(|e| HttpError::convert_from(e, Some("Failed to open file")))?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).map_err(|e| HttpError::convert_from(e, Some("Failed to read file contents")))?;
+// Everything up to a mapped body, which `write_mapped_chunks` or the async core sends after it
+pub fn write_mapped_head<W: Write>(ts: &Transcript, stream: &mut W, file: &MappedFile) -> Result<(), HttpError> {
+    write_header(ts, stream, "Content-Length", &file.len.to_string())?;
+    write_head_end(ts, stream)?;
+    // Read rather than taken from the mapping, a file truncated since would fault while it is copied
+    let size = match ts.get_max_body() {
+        0 => CHUNK_SIZE,
+        max_body => max_body.min(CHUNK_SIZE)
+    };
+    ts.record(TranscriptEvent::Body(Direction::Outgoing, BodyData::Prefix(&file.read_prefix(size)?, file.len)))
+}
+
+// Sent a chunk at a time, a file truncated while being sent ends the response short of its Content-Length
+pub fn write_mapped_chunks<W: Write>(stream: &mut W, file: &MappedFile) -> Result<(), HttpError> {
+    for offset in (0..file.len as usize).step_by(CHUNK_SIZE) {
+        let chunk = file.get_chunk(offset)?;
+        stream.write_all(chunk).map_err(|e| HttpError::convert_from(e, Some("Failed to write mapped file to HTTP stream")))?;
+    }
 
-    Ok(contents)
+    Ok(())
 }
 
-pub fn read_binary_file(path: &str) -> Result<Vec<u8>, HttpError> {
-    let mut file = File::open(path).map_err(|e| HttpError::convert_from(e, Some("Failed to open file")))?;
+// Reads a single CRLF (or bare LF) terminated line without buffering more than `limit` bytes of it
+pub fn read_limited_line<R: BufRead>(reader: &mut R, limit: usize) -> Result<LimitedLine, HttpError> {
     let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer).map_err(|e| HttpError::convert_from(e, Some("Failed to read file buffer")))?;
+    let max = limit as u64 + 2;
+
+    let read = reader.by_ref().take(max).read_until(b'\n', &mut buffer).map_err(|e| HttpError::convert_from(e, Some("Failed to read line from HTTP stream")))?;
+    if read == 0 {
+        return Ok(LimitedLine::End);
+    }
 
-    Ok(buffer)
+    if buffer.last() == Some(&b'\n') {
+        buffer.pop();
+        if buffer.last() == Some(&b'\r') {
+            buffer.pop();
+        }
+    } else if read as u64 >= max {
+        return Ok(LimitedLine::TooLong);
+    }
+
+    if buffer.len() > limit {
+        return Ok(LimitedLine::TooLong);
+    }
+
+    String::from_utf8(buffer)
+        .map(LimitedLine::Line)
+        .map_err(|_| request::invalid_utf8())
 }
 
-pub fn write_error(ts: &mut Transcript, stream: &TcpStream, http_err: HttpError) -> Result<(), HttpError> {
-    write_line(ts, &stream, format!("HTTP/1.1 {}", http_err.code).as_str())?;
-    write_line(ts, &stream, format!("X-Error-Info: {}", http_err).as_str())?;
-    write_line(ts, &stream, "Connection: close")?;
-    write_line(ts, &stream, "Connection-Type: text/html")?;
+pub fn write_error<W: Write>(request: &HttpRequest, stream: &mut W, http_err: HttpError) -> Result<(), HttpError> {
+    let page = render_error(request, &http_err);
+    let ts = &request.transcript;
+
+    write_status(ts, stream, &http_err.code)?;
+    write_header(ts, stream, "X-Request-Id", &request.id)?;
+    for (key, value) in &page.headers {
+        write_header(ts, stream, key, value)?;
+    }
 
-    let error_html = format!("<html><body><h1>{}</h1></body></html>", http_err.code.get_desc());
-    write_body(ts, &stream, error_html.as_str())?;
+    write_header(ts, stream, "Connection", "close")?;
+    write_header(ts, stream, "Content-Type", &page.content_type)?;
+    write_body(ts, stream, page.body.as_str())?;
+
+    access_log::record(&AccessLogEntry {
+        request,
+        status: http_err.code.get_code(),
+        body_bytes: page.body.len() as u64,
+        response_headers: None
+    });
+    metrics::record_request(request, http_err.code.get_code());
 
     Ok(())
 }
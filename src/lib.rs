@@ -0,0 +1,397 @@
+mod http_util;
+mod request;
+mod response;
+mod io_util;
+mod util;
+mod http_error;
+mod str_util;
+mod headers;
+mod media_type;
+mod transcript;
+mod config;
+mod rules;
+mod json;
+mod error_pages;
+mod log_tools;
+mod log_query;
+mod access_log;
+mod log_rotation;
+mod transcript_writer;
+mod transcript_sink;
+mod signals;
+mod redaction;
+mod transcript_reader;
+mod har;
+mod replay;
+mod metrics;
+mod admin;
+mod connections;
+mod file_cache;
+mod mapped_file;
+mod head_parser;
+mod event_loop;
+#[cfg(feature = "async")]
+mod async_server;
+
+// The server and its log tools, the myhttp binary is a thin wrapper around `run`.
+// Transcripts can also be read and exported from other programs.
+pub use har::build_har;
+pub use json::JsonValue;
+pub use transcript_reader::{read_transcript, RecordedBody, RecordedExchange};
+// Handlers for the async core can be served from other programs too
+pub use http_error::HttpCode;
+pub use response::HttpResponse;
+#[cfg(feature = "async")]
+pub use async_server::{serve, AsyncResponse};
+
+use http_util::{accepts_encoding, get_directory, get_valid_path, matches_etag};
+use file_cache::{CachedFile, FileContent};
+use request::HttpRequest;
+use util::log_title;
+use config::ServerConfig;
+use connections::ConnectionHandle;
+use event_loop::ServerCore;
+use head_parser::HeadParser;
+use rules::RuleOutcome;
+use http_error::{describe_chain, HttpError, http_errors};
+
+use std::{env, process, thread};
+use std::path::Path;
+use std::sync::Arc;
+use std::net::{TcpListener, TcpStream};
+use std::io::{self, BufReader, Write};
+
+use crate::io_util::write_error;
+
+fn respond_client_error(request: &mut HttpRequest, mut stream: &TcpStream, err: HttpError) -> io::Result<()> {
+    write_error(request, &mut stream, err).map_err(|e| e.convert_to(Some("Failed to send HTTP Error to client")))
+}
+
+fn end_client(mut stream: &TcpStream) -> io::Result<()> {
+    stream.flush()?;
+    Ok(())
+}
+
+// `head` is a head the event loop already read, otherwise it is read from the stream
+fn handle_client(mut stream: TcpStream, config: Arc<ServerConfig>, connection: &ConnectionHandle, head: Option<HeadParser>) -> io::Result<()> {
+    let mut request = HttpRequest::new(io_util::get_stream_name(&stream), config).map_err(|e| e.convert_to(Some("Failed to create HTTP Request")))?;
+    if let Err(http_err) = log_title(&request.transcript, "HTTP Request") {
+        respond_client_error(&mut request, &stream, http_err)?;
+        return end_client(&stream);
+    }
+
+    connection.set_request(&request);
+    let head = match head {
+        Some(head) => request.read_parsed_head(head),
+        None => request.read_head(&mut BufReader::new(&stream))
+    };
+    connection.set_request(&request);
+
+    if let Err(http_err) = head {
+        respond_client_error(&mut request, &stream, http_err)?;
+        return end_client(&stream);
+    }
+
+    // Closed before sending a request line, e.g. a health check or port scan, there is nobody to answer
+    if !request.is_init {
+        return end_client(&stream);
+    }
+
+    let mut response = HttpResponse::new(request, &mut stream);
+    if let Err(http_err) = response.headers.add_from_pair("Connection", "close") {
+        respond_client_error(&mut response.request, &stream, http_err)?;
+        return end_client(&stream);
+    }
+
+    if let Err(http_err) = log_title(&response.request.transcript, "HTTP Response") {
+        respond_client_error(&mut response.request, &stream, http_err)?;
+        return end_client(&stream);
+    }
+
+    if !apply_rules(&mut response) {
+        serve_static(&mut response);
+    }
+
+    response.flush()?;
+    end_client(&stream)
+}
+
+// Applies the configured rewrite/redirect rules, returns true if the response was decided by them
+fn apply_rules<W: Write>(response: &mut HttpResponse<W>) -> bool {
+    let request = &mut response.request;
+    if !request.is_init || !request.valid {
+        return false;
+    }
+
+    let config = Arc::clone(&request.config);
+    let mut rewritten = false;
+    let redirect = match rules::evaluate(&config.rules, &request.path) {
+        RuleOutcome::Redirect(code, location) => {
+            request.transcript.push(format!("Redirect rule matched: {} -> {}", request.path, location).as_str()).ok();
+            Some((code, location))
+        },
+        RuleOutcome::Rewrite(target) => {
+            request.transcript.push(format!("Rewrite rule matched: {} -> {}", request.path, target).as_str()).ok();
+
+            let query = request.query.take();
+            request.set_target(&target);
+            if request.query.is_none() {
+                request.query = query;
+            }
+
+            rewritten = true;
+            None
+        },
+        RuleOutcome::None => None
+    };
+
+    // Directories are served from their index, so make relative links inside them resolve.
+    // A rewritten path is internal and must not leak into a Location header
+    let redirect = redirect.or_else(|| {
+        if config.trailing_slash_redirect && !rewritten && !request.path.ends_with('/') && get_directory(request, &request.path).is_some() {
+            Some((HttpCode::E301, format!("{}/", request.path)))
+        } else {
+            None
+        }
+    });
+
+    let Some((code, mut location)) = redirect else {
+        return false;
+    };
+
+    if let Some(query) = &request.query {
+        if !location.contains('?') {
+            location = format!("{}?{}", location, query);
+        }
+    }
+
+    if let Err(e) = response.redirect(code, &location) {
+        response.request.transcript.push(format!("Failed to set redirect response: {}", e).as_str()).ok();
+        response.set_error(e);
+    }
+
+    true
+}
+
+fn serve_static<W: Write>(response: &mut HttpResponse<W>) {
+    if let Err(e) = response.request.init_resource_type() {
+        response.set_error(e);
+        return;
+    }
+
+    match get_valid_path(&response.request) {
+        Ok(path) => {
+            let resource_type = response.request.resource_type.as_str();
+            if resource_type != "image/x-icon" && resource_type != "text/html" {
+                response.set_error(http_errors::msg::forbidden(format!("Invalid file type: {}", response.request.path).as_str()));
+                return;
+            }
+
+            match file_cache::get(Path::new(&path), &response.request.config.cache) {
+                Ok(file) => send_file(response, &file),
+                Err(e) => response.set_error(e)
+            }
+        },
+        Err(e) => {
+            response.request.transcript.push(format!("Failed to recognize requested file: {}", e).as_str()).ok();
+            response.set_error(e);
+        }
+    }
+
+}
+
+// Picks the gzip variant when the client takes it, and answers 304 when the client already has it
+fn send_file<W: Write>(response: &mut HttpResponse<W>, file: &CachedFile) {
+    let gzip = file.gzip.as_ref().filter(|_| response.request.config.cache.gzip && accepts_encoding(&response.request, "gzip"));
+    let etag = if gzip.is_some() { file.get_gzip_etag() } else { file.etag.clone() };
+
+    if file.gzip.is_some() {
+        response.headers.set("Vary", "Accept-Encoding");
+    }
+
+    response.headers.set("ETag", &etag);
+    if matches_etag(&response.request, &etag) {
+        response.set_code(HttpCode::E304);
+        return;
+    }
+
+    let result = if let Some(gzip) = gzip {
+        response.headers.set("Content-Encoding", "gzip");
+        response.set_shared_response(HttpCode::E200, Arc::clone(gzip))
+    } else {
+        match &file.content {
+            // Not checked for UTF-8, that would read the whole mapping up front
+            FileContent::Mapped(mapped) => response.set_mapped_response(HttpCode::E200, Arc::clone(mapped)),
+            FileContent::Memory(content) if response.request.resource_type == "text/html" && std::str::from_utf8(content).is_err() => {
+                Err(http_errors::msg::internal_server_error("Failed to read file contents, not valid UTF-8"))
+            },
+            FileContent::Memory(content) => response.set_shared_response(HttpCode::E200, Arc::clone(content))
+        }
+    };
+
+    if let Err(e) = result {
+        response.request.transcript.push(format!("Failed to set file response: {}", e).as_str()).ok();
+        response.set_error(e);
+    }
+}
+
+// Runs the command line in env::args, either one of the log tools or the server
+pub fn run() -> io::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("logs") {
+        process::exit(log_tools::run(&args[2..]));
+    }
+
+    if args.get(1).map(String::as_str) == Some("transcript") {
+        process::exit(log_tools::run_transcript(&args[2..]));
+    }
+
+    if args.get(1).map(String::as_str) == Some("replay") {
+        process::exit(replay::run(&args[2..]));
+    }
+
+    let config = match ServerConfig::load_from_args(&args) {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            eprintln!("Failed to load config: {}", e.get_chain_msg());
+            process::exit(1);
+        }
+    };
+
+    config::set_current(Arc::clone(&config));
+
+    if let Err(e) = access_log::init(&config) {
+        eprintln!("Failed to open access log: {}", e.get_chain_msg());
+        process::exit(1);
+    }
+
+    if let Err(e) = transcript::init(&config.transcript) {
+        eprintln!("Failed to open transcript: {}", e.get_chain_msg());
+        process::exit(1);
+    }
+
+    if let Err(e) = transcript_writer::start(&config.transcript) {
+        eprintln!("Failed to start transcript sinks: {}", e.get_chain_msg());
+        process::exit(1);
+    }
+
+    log_rotation::start_janitor();
+
+    if let Err(e) = signals::install_signal_handlers() {
+        eprintln!("{}", e.get_chain_msg());
+        process::exit(1);
+    }
+
+    if let Err(e) = admin::start(&config) {
+        eprintln!("{}", e.get_chain_msg());
+        process::exit(1);
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:8080")?;
+
+    println!("Server listening on port 8080");
+    admin::set_accepting(true);
+
+    match config.core {
+        ServerCore::Threads => serve_threads(listener),
+        ServerCore::EventLoop => event_loop::run(listener, config.workers),
+        #[cfg(feature = "async")]
+        ServerCore::Async => async_server::run(listener, config.workers)
+    }
+}
+
+fn serve_threads(listener: TcpListener) -> io::Result<()> {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                println!("New connection: {}", stream.peer_addr()?);
+                // Picked up per connection, so reloads apply to the next one
+                let config = config::get_current();
+                thread::spawn(move || {
+                    let connection = connections::track(io_util::get_stream_name(&stream));
+                    let client_addr = stream.peer_addr();
+                    if let Err(e) = handle_client(stream, config, &connection, None) {
+                        eprintln!("{} Failed to handle client: {}", client_addr
+                            .map(|addr| addr.to_string())
+                            .unwrap_or("Unknown Address".to_string()), describe_chain(&e));
+                    }
+                });
+            },
+            Err(e) => {
+                eprintln!("Connection failed: {:?}", e);
+            },
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use response::{HttpDataType, HttpResponseData};
+    use rules::Rule;
+    use transcript::TranscriptLevel;
+
+    fn respond(target: &str, rules: &[(&str, &str)]) -> HttpResponse<Vec<u8>> {
+        let root = std::env::temp_dir().join(format!("myhttp-rules-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+
+        let mut config = ServerConfig::new();
+        config.transcript.level = TranscriptLevel::Off;
+        config.root = root;
+        config.rules = rules.iter().map(|(action, value)| Rule::parse(action, value).unwrap()).collect();
+
+        let mut request = HttpRequest::new(String::from("127.0.0.1:1"), Arc::new(config)).unwrap();
+        let head = format!("GET {} HTTP/1.1\r\nHost: x\r\n\r\n", target);
+        request.read_head(&mut Cursor::new(head.as_bytes())).unwrap();
+
+        let mut response = HttpResponse::new(request, Vec::new());
+        apply_rules(&mut response);
+        response
+    }
+
+    #[test]
+    fn redirects_directories_to_their_slash() {
+        let response = respond("/docs?a=1", &[]);
+        assert_eq!(response.code, HttpCode::E301);
+        assert_eq!(response.headers.get("Location").map(String::as_str), Some("/docs/?a=1"));
+    }
+
+    #[test]
+    fn rewrite_to_a_directory_does_not_leak_the_target() {
+        let response = respond("/manual?a=1", &[("rewrite", "exact /manual /docs")]);
+        assert_eq!(response.code, HttpCode::E200);
+        assert!(response.headers.get("Location").is_none());
+        assert_eq!(response.request.path, "/docs");
+        assert_eq!(response.request.query.as_deref(), Some("a=1"));
+    }
+
+    #[test]
+    fn redirect_rule_keeps_the_query() {
+        let response = respond("/old?a=1", &[("redirect", "prefix /old /new 308")]);
+        assert_eq!(response.code, HttpCode::E308);
+        assert_eq!(response.headers.get("Location").map(String::as_str), Some("/new?a=1"));
+    }
+
+    #[test]
+    fn cached_bodies_are_shared_with_responses() {
+        let content: Arc<[u8]> = Arc::from(&b"<p>cached</p>"[..]);
+        let file = CachedFile {
+            content: FileContent::Memory(Arc::clone(&content)),
+            gzip: None,
+            modified: std::time::UNIX_EPOCH,
+            len: content.len() as u64,
+            etag: String::from("\"d-0\"")
+        };
+
+        let mut response = respond("/index.html", &[]);
+        response.request.resource_type = String::from("text/html");
+        send_file(&mut response, &file);
+
+        assert!(matches!(&response.data, HttpResponseData::Content(HttpDataType::Shared(data)) if Arc::ptr_eq(data, &content)));
+        assert_eq!(response.headers.get("ETag").map(String::as_str), Some("\"d-0\""));
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, SecondsFormat, Utc};
+
+use crate::config::parse_duration;
+use crate::log_tools::get_option;
+use crate::rules::RuleMatch;
+use crate::transcript_reader::RecordedExchange;
+
+// Options taking a value, shared by `logs search` and `logs summary`
+pub const FILTER_OPTIONS: [&str; 8] = ["--since", "--until", "--peer", "--path", "--path-regex", "--status", "--min-duration", "--max-duration"];
+
+const DEFAULT_TOP: usize = 10;
+
+pub struct LogFilter {
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    peer: Option<String>,
+    path: Option<RuleMatch>,
+    // Inclusive range, e.g. 400-499 for 4xx
+    status: Option<(i32, i32)>,
+    min_duration: Option<f64>,
+    max_duration: Option<f64>
+}
+
+struct PathStats {
+    count: usize,
+    errors: usize,
+    durations: Vec<f64>
+}
+
+impl LogFilter {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let option = |name: &str| get_option(args, name);
+        let now = Utc::now();
+
+        let path = match (option("--path"), option("--path-regex")) {
+            (Some(_), Some(_)) => return Err(String::from("--path and --path-regex can not be combined")),
+            (Some(prefix), None) => Some(RuleMatch::parse("prefix", prefix)?),
+            (None, Some(regex)) => Some(RuleMatch::parse("regex", regex)?),
+            (None, None) => None
+        };
+
+        Ok(Self {
+            since: option("--since").map(|value| parse_time(value, now)).transpose()?,
+            until: option("--until").map(|value| parse_time(value, now)).transpose()?,
+            peer: option("--peer").map(str::to_string),
+            path,
+            status: option("--status").map(parse_status).transpose()?,
+            min_duration: option("--min-duration").map(parse_millis).transpose()?,
+            max_duration: option("--max-duration").map(parse_millis).transpose()?
+        })
+    }
+
+    pub fn matches(&self, exchange: &RecordedExchange) -> bool {
+        let Some(time) = exchange.request_time else {
+            return false;
+        };
+
+        if self.since.is_some_and(|since| time < since) || self.until.is_some_and(|until| time > until) {
+            return false;
+        }
+
+        // An address matches with or without the port
+        if let Some(peer) = &self.peer {
+            if exchange.peer != *peer && !exchange.peer.starts_with(&format!("{}:", peer)) {
+                return false;
+            }
+        }
+
+        if let Some(path) = &self.path {
+            if !get_path(exchange).is_some_and(|target| path.matches(target)) {
+                return false;
+            }
+        }
+
+        if let Some((from, to)) = self.status {
+            if !exchange.status.is_some_and(|status| status >= from && status <= to) {
+                return false;
+            }
+        }
+
+        if self.min_duration.is_some() || self.max_duration.is_some() {
+            let Some(duration) = get_duration(exchange) else {
+                return false;
+            };
+
+            if self.min_duration.is_some_and(|min| duration < min) || self.max_duration.is_some_and(|max| duration > max) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+impl PathStats {
+    fn new() -> Self {
+        Self {
+            count: 0,
+            errors: 0,
+            durations: Vec::new()
+        }
+    }
+}
+
+// One line per matching request, oldest first
+pub fn print_search(exchanges: &[&RecordedExchange]) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+
+    for exchange in exchanges {
+        let time = exchange.request_time.map(|time| time.to_rfc3339_opts(SecondsFormat::Millis, true)).unwrap_or_default();
+        let status = exchange.status.map(|status| status.to_string()).unwrap_or_else(|| String::from("-"));
+        let duration = get_duration(exchange).map(format_millis).unwrap_or_else(|| String::from("-"));
+
+        writeln!(out, "{} {} {} {:>9} {} [{}]", time, exchange.peer, status, duration, exchange.request_line.as_deref().unwrap_or(""), exchange.request_id)?;
+    }
+
+    Ok(())
+}
+
+pub fn print_summary(exchanges: &[&RecordedExchange], top: Option<&str>) -> Result<(), String> {
+    let top = match top {
+        Some(top) => top.parse::<usize>().map_err(|_| format!("\"{}\" is not a valid number", top))?,
+        None => DEFAULT_TOP
+    };
+
+    let total = exchanges.len();
+    let mut classes = [0; 6];
+    let mut durations = Vec::new();
+    let mut paths: HashMap<&str, PathStats> = HashMap::new();
+
+    for exchange in exchanges {
+        let class = exchange.status.map(|status| (status / 100).clamp(0, 5) as usize).unwrap_or(0);
+        classes[class] += 1;
+
+        let stats = paths.entry(get_path(exchange).unwrap_or("-")).or_insert_with(PathStats::new);
+        stats.count += 1;
+        if class >= 4 {
+            stats.errors += 1;
+        }
+
+        if let Some(duration) = get_duration(exchange) {
+            durations.push(duration);
+            stats.durations.push(duration);
+        }
+    }
+
+    let first = exchanges.iter().filter_map(|exchange| exchange.request_time).min();
+    let last = exchanges.iter().filter_map(|exchange| exchange.request_time).max();
+
+    println!("Requests:   {}", total);
+    if let (Some(first), Some(last)) = (first, last) {
+        println!("Time range: {} - {}", first.to_rfc3339_opts(SecondsFormat::Millis, true), last.to_rfc3339_opts(SecondsFormat::Millis, true));
+    }
+
+    let status: Vec<String> = (1..6).filter(|&class| classes[class] > 0)
+        .map(|class| format!("{}xx {} ({})", class, classes[class], format_percent(classes[class], total)))
+        .chain((classes[0] > 0).then(|| format!("none {} ({})", classes[0], format_percent(classes[0], total))))
+        .collect();
+
+    println!("Status:     {}", status.join(", "));
+    println!("Errors:     {} 4xx, {} 5xx ({})", classes[4], classes[5], format_percent(classes[4] + classes[5], total));
+    println!("Latency:    {}", format_latency(&mut durations));
+
+    let mut paths: Vec<(&str, PathStats)> = paths.into_iter().collect();
+    paths.sort_by(|(a_path, a), (b_path, b)| b.count.cmp(&a.count).then_with(|| a_path.cmp(b_path)));
+
+    println!();
+    println!("{:>8} {:>8} {:>9} {:>9}  Path", "Requests", "Errors", "p50", "p99");
+    for (path, mut stats) in paths.into_iter().take(top) {
+        stats.durations.sort_by(f64::total_cmp);
+        let p50 = get_percentile(&stats.durations, 50.0).map(format_millis).unwrap_or_else(|| String::from("-"));
+        let p99 = get_percentile(&stats.durations, 99.0).map(format_millis).unwrap_or_else(|| String::from("-"));
+        println!("{:>8} {:>8} {:>9} {:>9}  {}", stats.count, stats.errors, p50, p99, path);
+    }
+
+    Ok(())
+}
+
+// Target without the query string
+fn get_path(exchange: &RecordedExchange) -> Option<&str> {
+    let (_, target, _) = exchange.get_request_parts()?;
+    Some(target.split('?').next().unwrap_or(target))
+}
+
+// Milliseconds from the request line to the last line logged for the exchange
+fn get_duration(exchange: &RecordedExchange) -> Option<f64> {
+    let duration = exchange.last_time? - exchange.request_time?;
+    Some(duration.num_microseconds()?.max(0) as f64 / 1000.0)
+}
+
+// Nearest rank on already sorted values
+fn get_percentile(sorted: &[f64], percentile: f64) -> Option<f64> {
+    if sorted.is_empty() {
+        return None;
+    }
+
+    let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.saturating_sub(1)).copied()
+}
+
+fn format_latency(durations: &mut [f64]) -> String {
+    durations.sort_by(f64::total_cmp);
+    if durations.is_empty() {
+        return String::from("-");
+    }
+
+    [("p50", 50.0), ("p90", 90.0), ("p99", 99.0), ("max", 100.0)].iter()
+        .filter_map(|(name, percentile)| get_percentile(durations, *percentile).map(|value| format!("{} {}", name, format_millis(value))))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn format_millis(millis: f64) -> String {
+    format!("{:.1}ms", millis)
+}
+
+fn format_percent(count: usize, total: usize) -> String {
+    if total == 0 {
+        return String::from("0.0%");
+    }
+
+    format!("{:.1}%", count as f64 * 100.0 / total as f64)
+}
+
+// RFC 3339, `YYYY-MM-DD HH:MM:SS`, a date, or a duration into the past such as "15m"
+fn parse_time(value: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(time) = NaiveDateTime::parse_from_str(value, format) {
+            return Ok(time.and_utc());
+        }
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc());
+    }
+
+    let ago = parse_duration(value).map_err(|_| format!("\"{}\" is not a valid time", value))?;
+    chrono::Duration::from_std(ago).ok().and_then(|ago| now.checked_sub_signed(ago)).ok_or_else(|| format!("\"{}\" is not a valid time", value))
+}
+
+// A code, a class such as 4xx, or a range such as 400-499
+fn parse_status(value: &str) -> Result<(i32, i32), String> {
+    let invalid = || format!("\"{}\" is not a valid status filter, expected e.g. 404, 4xx or 400-499", value);
+
+    if let Some(class) = value.strip_suffix("xx") {
+        let class = class.parse::<i32>().map_err(|_| invalid())?;
+        return Ok((class * 100, class * 100 + 99));
+    }
+
+    if let Some((from, to)) = value.split_once('-') {
+        let from = from.parse::<i32>().map_err(|_| invalid())?;
+        let to = to.parse::<i32>().map_err(|_| invalid())?;
+        return Ok((from, to));
+    }
+
+    let code = value.parse::<i32>().map_err(|_| invalid())?;
+    Ok((code, code))
+}
+
+// Plain milliseconds or an ms/s suffix, e.g. "250ms" or "1.5s"
+fn parse_millis(value: &str) -> Result<f64, String> {
+    let (number, multiplier) = if let Some(number) = value.strip_suffix("ms") {
+        (number, 1.0)
+    } else if let Some(number) = value.strip_suffix('s') {
+        (number, 1000.0)
+    } else {
+        (value, 1.0)
+    };
+
+    match number.trim().parse::<f64>() {
+        Ok(number) if number >= 0.0 => Ok(number * multiplier),
+        _ => Err(format!("\"{}\" is not a valid duration", value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcript_reader::read_transcript;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    fn exchanges() -> Vec<RecordedExchange> {
+        let text = "[10:00:00.000] [a] 10.0.0.1:5000 --> GET /api/users?id=1 HTTP/1.1\n\
+                    [10:00:00.100] [a] 10.0.0.1:5000 <-- HTTP/1.1 200 OK\n\
+                    [10:00:00.250] [a] 10.0.0.1:5000 Transcript ended after 250ms\n\
+                    [10:00:01.000] [b] 10.0.0.2:5001 --> GET /index.html HTTP/1.1\n\
+                    [10:00:01.020] [b] 10.0.0.2:5001 <-- HTTP/1.1 404 Not Found\n";
+
+        read_transcript(text, NaiveDate::from_ymd_opt(2026, 10, 19).unwrap())
+    }
+
+    fn matching(filter: &[&str]) -> Vec<String> {
+        let filter = LogFilter::parse(&args(filter)).unwrap();
+        exchanges().iter().filter(|exchange| filter.matches(exchange)).map(|exchange| exchange.request_id.clone()).collect()
+    }
+
+    #[test]
+    fn parses_status_filters() {
+        assert_eq!(parse_status("404"), Ok((404, 404)));
+        assert_eq!(parse_status("5xx"), Ok((500, 599)));
+        assert_eq!(parse_status("200-299"), Ok((200, 299)));
+        assert!(parse_status("4x").is_err());
+        assert!(parse_status("a-b").is_err());
+    }
+
+    #[test]
+    fn parses_times_and_durations() {
+        let now = Utc::now();
+        assert_eq!(parse_time("2026-10-19T10:00:00+02:00", now).unwrap().to_rfc3339(), "2026-10-19T08:00:00+00:00");
+        assert_eq!(parse_time("2026-10-19 10:00", now).unwrap().to_rfc3339(), "2026-10-19T10:00:00+00:00");
+        assert_eq!(parse_time("2026-10-19", now).unwrap().to_rfc3339(), "2026-10-19T00:00:00+00:00");
+        assert_eq!(parse_time("15m", now).unwrap(), now - chrono::Duration::minutes(15));
+        assert!(parse_time("yesterday", now).is_err());
+
+        assert_eq!(parse_millis("250ms"), Ok(250.0));
+        assert_eq!(parse_millis("1.5s"), Ok(1500.0));
+        assert_eq!(parse_millis("20"), Ok(20.0));
+        assert!(parse_millis("-1").is_err());
+    }
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let sorted: Vec<f64> = (1..=10).map(f64::from).collect();
+        assert_eq!(get_percentile(&sorted, 50.0), Some(5.0));
+        assert_eq!(get_percentile(&sorted, 99.0), Some(10.0));
+        assert_eq!(get_percentile(&sorted, 100.0), Some(10.0));
+        assert_eq!(get_percentile(&[], 50.0), None);
+    }
+
+    #[test]
+    fn filters_exchanges() {
+        assert_eq!(matching(&[]), ["a", "b"]);
+        assert_eq!(matching(&["--status", "4xx"]), ["b"]);
+        assert_eq!(matching(&["--peer", "10.0.0.1"]), ["a"]);
+        assert_eq!(matching(&["--peer", "10.0.0.1:5001"]), Vec::<String>::new());
+        assert_eq!(matching(&["--path", "/api/"]), ["a"]);
+        assert_eq!(matching(&["--path-regex", "\\.html$"]), ["b"]);
+        assert_eq!(matching(&["--min-duration", "100ms"]), ["a"]);
+        assert_eq!(matching(&["--since", "2026-10-19T10:00:00.500Z"]), ["b"]);
+
+        assert!(LogFilter::parse(&args(&["--path", "/a", "--path-regex", "a"])).is_err());
+    }
+}
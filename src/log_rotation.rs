@@ -0,0 +1,279 @@
+use std::fs::{metadata, read_dir, remove_dir, remove_file, rename, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use chrono::Utc;
+use flate2::{write::GzEncoder, Compression};
+use regex::Regex;
+
+use crate::config::{self, ServerConfig, TranscriptConfig};
+use crate::connections;
+use crate::http_error::HttpError;
+use crate::log_tools::list_log_files;
+use crate::transcript;
+
+// A single buffered log file shared by all connections, renamed aside once it grows too large or too old
+pub struct RotatingFile {
+    path: PathBuf,
+    file: BufWriter<File>,
+    size: u64,
+    opened: SystemTime,
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    compress: bool
+}
+
+#[allow(unused)]
+impl RotatingFile {
+    pub fn open(path: &Path, config: &TranscriptConfig) -> Result<Self, HttpError> {
+        let file = OpenOptions::new().create(true).append(true).open(path)
+            .map_err(|e| HttpError::convert_from(e, Some("Failed to open rotating log file")))?;
+        let size = file.metadata().map(|meta| meta.len()).unwrap_or(0);
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: BufWriter::new(file),
+            size,
+            opened: SystemTime::now(),
+            max_size: config.rotate_size.map(|size| size as u64),
+            max_age: config.rotate_age,
+            compress: config.compress
+        })
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.rotate_if_due(line.len() as u64 + 2)?;
+
+        write!(self.file, "{}\r\n", line)?;
+        self.size += line.len() as u64 + 2;
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    // Rotates before a write of `incoming` bytes would cross the size limit, or once the file is too old
+    pub fn rotate_if_due(&mut self, incoming: u64) -> io::Result<()> {
+        let too_large = self.max_size.is_some_and(|max_size| self.size > 0 && self.size + incoming > max_size);
+        let too_old = self.max_age.is_some_and(|max_age| self.size > 0 && self.opened.elapsed().unwrap_or_default() >= max_age);
+
+        if too_large || too_old {
+            self.rotate()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn set_limits(&mut self, config: &TranscriptConfig) {
+        self.max_size = config.rotate_size.map(|size| size as u64);
+        self.max_age = config.rotate_age;
+    }
+
+    // Starts writing to a fresh file at the same path, after an external tool such as logrotate moved it away
+    pub fn reopen(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = file.metadata().map(|meta| meta.len()).unwrap_or(0);
+        self.file = BufWriter::new(file);
+        self.opened = SystemTime::now();
+
+        Ok(())
+    }
+
+    pub fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        let rotated = get_rotated_path(&self.path);
+        rename(&self.path, &rotated)?;
+
+        self.file = BufWriter::new(OpenOptions::new().create(true).append(true).open(&self.path)?);
+        self.size = 0;
+        self.opened = SystemTime::now();
+
+        // Compress off the write path, other connections are waiting on this file
+        if self.compress {
+            thread::spawn(move || {
+                if let Err(e) = gzip_file(&rotated) {
+                    eprintln!("Failed to compress {}: {}", rotated.display(), e);
+                }
+            });
+        }
+
+        Ok(())
+    }
+}
+
+// transcript.log -> transcript.20261019T120000.log, with a counter if that already exists
+fn get_rotated_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    let extension = path.extension().map(|extension| extension.to_string_lossy().to_string()).unwrap_or_default();
+    let time = Utc::now().format("%Y%m%dT%H%M%S");
+
+    let mut counter = 0;
+    loop {
+        let name = if counter > 0 {
+            format!("{}.{}_{}.{}", stem, time, counter, extension)
+        } else {
+            format!("{}.{}.{}", stem, time, extension)
+        };
+
+        let rotated = path.with_file_name(name);
+        if !rotated.exists() {
+            return rotated;
+        }
+
+        counter += 1;
+    }
+}
+
+// Replaces `path` with `path.gz`
+pub fn gzip_file(path: &Path) -> io::Result<PathBuf> {
+    let mut target = path.as_os_str().to_owned();
+    target.push(".gz");
+    let target = PathBuf::from(target);
+
+    let mut input = BufReader::new(File::open(path)?);
+    let mut encoder = GzEncoder::new(BufWriter::new(File::create(&target)?), Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.flush()?;
+
+    remove_file(path)?;
+    Ok(target)
+}
+
+// <peer>_<time>[_<n>].<ext> per connection, transcript.<time>[_<n>].<ext> once rotated, either maybe gzipped
+static TRANSCRIPT_NAME: OnceLock<Regex> = OnceLock::new();
+
+// Only names this server generates, and never the files of connections still writing to them
+pub fn is_transcript_file(path: &Path, live: &[String]) -> bool {
+    let regex = TRANSCRIPT_NAME.get_or_init(|| {
+        Regex::new(r"^(?:transcript\.\d{8}T\d{6}|(?P<peer>.+)_\d{9,})(?:_\d+)?\.(?:log|jsonl)(?:\.gz)?$").expect("valid transcript name regex")
+    });
+
+    let Some(captures) = path.file_name().and_then(|name| name.to_str()).and_then(|name| regex.captures(name)) else {
+        return false;
+    };
+
+    captures.name("peer").is_none_or(|peer| !live.iter().any(|live| live == peer.as_str()))
+}
+
+// Periodically rotates the shared transcript by age and enforces the retention limits.
+// Reads the current config on every run, so reloaded limits apply from the next one.
+pub fn start_janitor() {
+    thread::spawn(|| loop {
+        thread::sleep(config::get_current().transcript.janitor_interval);
+
+        let config = config::get_current();
+        let transcript = &config.transcript;
+        if transcript.max_files.is_none() && transcript.max_age.is_none() && transcript.max_total_size.is_none() && transcript.rotate_age.is_none() {
+            continue;
+        }
+
+        if let Some(shared) = transcript::get_shared_file() {
+            let mut shared = shared.lock().unwrap_or_else(|e| e.into_inner());
+            if let Err(e) = shared.rotate_if_due(0) {
+                eprintln!("Failed to rotate {}: {}", shared.get_path().display(), e);
+            }
+        }
+
+        enforce_retention(&config);
+    });
+}
+
+// Deletes the oldest transcripts until the count, age and total size limits hold
+pub fn enforce_retention(config: &ServerConfig) {
+    let transcript = &config.transcript;
+
+    // The access log and the live shared transcript may sit in the same directory
+    let mut keep = Vec::new();
+    if let Some(path) = &config.access_log.path {
+        keep.extend(path.canonicalize().ok());
+    }
+
+    if let Some(shared) = transcript::get_shared_file() {
+        let shared = shared.lock().unwrap_or_else(|e| e.into_inner());
+        keep.extend(shared.get_path().canonicalize().ok());
+    }
+
+    let live: Vec<String> = connections::list().iter().map(|connection| transcript::get_file_prefix(&connection.peer)).collect();
+
+    let mut files: Vec<(PathBuf, SystemTime, u64)> = list_log_files(&transcript.dir).into_iter()
+        .filter(|path| is_transcript_file(path, &live))
+        .filter(|path| path.canonicalize().map(|path| !keep.contains(&path)).unwrap_or(false))
+        .filter_map(|path| {
+            let meta = metadata(&path).ok()?;
+            Some((path, meta.modified().ok()?, meta.len()))
+        })
+        .collect();
+
+    // Newest first, so everything past the first limit hit is removed
+    files.sort_by_key(|(_, modified, _)| std::cmp::Reverse(*modified));
+
+    let now = SystemTime::now();
+    let mut total_size = 0;
+
+    for (index, (path, modified, size)) in files.iter().enumerate() {
+        total_size += *size as usize;
+
+        let over_count = transcript.max_files.is_some_and(|max_files| index >= max_files);
+        let over_age = transcript.max_age.is_some_and(|max_age| now.duration_since(*modified).unwrap_or_default() > max_age);
+        let over_size = transcript.max_total_size.is_some_and(|max_total_size| total_size > max_total_size);
+
+        if over_count || over_age || over_size {
+            if let Err(e) = remove_file(path) {
+                eprintln!("Failed to remove {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    remove_empty_dirs(&transcript.dir);
+}
+
+// Cleans up date partitions left empty by retention, never the root or today's partition
+fn remove_empty_dirs(dir: &Path) {
+    let Ok(entries) = read_dir(dir) else {
+        return;
+    };
+
+    let today = transcript::get_partition_name(&Utc::now());
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() && entry.file_name().to_string_lossy() != today {
+            remove_empty_dirs(&path);
+            remove_dir(&path).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_only_generated_transcript_names() {
+        for name in ["127_0_0_1_5555_1760000000.log", "127_0_0_1_5555_1760000000_2.jsonl", "[__1]_80_1760000000.log.gz", "transcript.20261019T120000.log", "transcript.20261019T120000_1.jsonl.gz"] {
+            assert!(is_transcript_file(Path::new(name), &[]), "{}", name);
+        }
+
+        for name in ["transcript.log", "access.log", "app.jsonl", "backup.gz", "notes_1.log"] {
+            assert!(!is_transcript_file(Path::new(name), &[]), "{}", name);
+        }
+    }
+
+    #[test]
+    fn skips_live_connections() {
+        let live = vec![transcript::get_file_prefix("127.0.0.1:5555")];
+        assert!(!is_transcript_file(Path::new("127_0_0_1_5555_1760000000_1.log"), &live));
+        assert!(is_transcript_file(Path::new("127_0_0_1_5556_1760000000.log"), &live));
+    }
+}
@@ -0,0 +1,279 @@
+use std::fs::{metadata, read_dir, read_to_string, write, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+
+use crate::har::build_har;
+use crate::log_query::{print_search, print_summary, LogFilter, FILTER_OPTIONS};
+use crate::transcript_reader::{read_transcript, RecordedExchange};
+
+pub const DEFAULT_LOG_DIR: &str = "./logs";
+
+const USAGE: &str = "Usage: myhttp logs request <id> [--dir <path>]
+       myhttp logs search [filters] [--dir <path>] [<file or dir>...]
+       myhttp logs summary [filters] [--top <n>] [--dir <path>] [<file or dir>...]
+Filters: --since <time> --until <time> --peer <addr> --path <prefix> --path-regex <regex>
+         --status <code|4xx|from-to> --min-duration <ms> --max-duration <ms>";
+const TRANSCRIPT_USAGE: &str = "Usage: myhttp transcript export --har [--request <id>] [--out <file>] [<file or dir>...]";
+
+// Entry point for `myhttp logs ...`, returns the process exit code
+pub fn run(args: &[String]) -> i32 {
+    let dir = get_option(args, "--dir").unwrap_or(DEFAULT_LOG_DIR);
+
+    match args.first().map(String::as_str) {
+        Some("request") => {
+            let Some(id) = args.get(1) else {
+                eprintln!("{}", USAGE);
+                return 2;
+            };
+
+            find_request(Path::new(dir), id)
+        },
+        Some(command @ ("search" | "summary")) => query(command, &args[1..], dir),
+        _ => {
+            eprintln!("{}", USAGE);
+            2
+        }
+    }
+}
+
+// Entry point for `myhttp transcript ...`, returns the process exit code
+pub fn run_transcript(args: &[String]) -> i32 {
+    if args.first().map(String::as_str) != Some("export") || !args.iter().any(|arg| arg == "--har") {
+        eprintln!("{}", TRANSCRIPT_USAGE);
+        return 2;
+    }
+
+    let request = get_option(args, "--request");
+    let out = get_option(args, "--out");
+    let paths = get_positional(&args[1..], &["--request", "--out"]);
+
+    let mut exchanges = read_exchanges(&paths);
+    if let Some(id) = request {
+        exchanges.retain(|exchange| exchange.request_id == id);
+    }
+
+    if exchanges.iter().all(|exchange| exchange.request_line.is_none()) {
+        eprintln!("No recorded requests found");
+        return 1;
+    }
+
+    let har = build_har(&exchanges).to_string();
+    match out {
+        Some(out) => {
+            if let Err(e) = write(out, har) {
+                eprintln!("Failed to write {}: {}", out, e);
+                return 1;
+            }
+        },
+        None => {
+            if writeln!(io::stdout().lock(), "{}", har).is_err() {
+                return 0;
+            }
+        }
+    }
+
+    0
+}
+
+pub fn get_option<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter().position(|arg| arg == name).and_then(|index| args.get(index + 1)).map(String::as_str)
+}
+
+// Every value of an option that may be given more than once
+pub fn get_options<'a>(args: &'a [String], name: &str) -> Vec<&'a str> {
+    args.windows(2).filter(|pair| pair[0] == name).map(|pair| pair[1].as_str()).collect()
+}
+
+// Filters exchanges read back from the transcripts, then lists or summarises them
+fn query(command: &str, args: &[String], dir: &str) -> i32 {
+    let filter = match LogFilter::parse(args) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+
+    let mut options = FILTER_OPTIONS.to_vec();
+    options.extend(["--dir", "--top"]);
+
+    let mut paths = get_positional(args, &options);
+    if paths.is_empty() {
+        paths.push(dir);
+    }
+
+    let exchanges = read_exchanges(&paths);
+    let mut matching: Vec<&RecordedExchange> = exchanges.iter()
+        .filter(|exchange| exchange.request_line.is_some() && filter.matches(exchange))
+        .collect();
+
+    if matching.is_empty() {
+        eprintln!("No matching requests found");
+        return 1;
+    }
+
+    matching.sort_by_key(|exchange| exchange.request_time);
+
+    if command == "search" {
+        // Stop quietly when the reader goes away, e.g. piping into head
+        print_search(&matching).ok();
+    } else if let Err(e) = print_summary(&matching, get_option(args, "--top")) {
+        eprintln!("{}", e);
+        return 2;
+    }
+
+    0
+}
+
+// Every transcript line carries the request id, in brackets for text and as a field for JSON, so this is a plain scan.
+// Lines logged before a client supplied X-Request-Id was adopted are found through the generated id.
+fn find_request(dir: &Path, id: &str) -> i32 {
+    let adopted = format!("replaced by X-Request-Id {}", id);
+    let mut out = io::stdout().lock();
+    let mut found = 0;
+
+    for path in list_log_files(dir) {
+        let Some(contents) = read_log_file(&path) else {
+            continue;
+        };
+
+        let mut needles = request_needles(id);
+        for line in contents.lines().filter(|line| line.contains(&adopted)) {
+            if let Some(generated) = line.split("Request id ").nth(1).and_then(|rest| rest.split(' ').next()) {
+                needles.extend(request_needles(generated));
+            }
+        }
+
+        for line in contents.lines().filter(|line| needles.iter().any(|needle| line.contains(needle))) {
+            // Stop quietly when the reader goes away, e.g. piping into head
+            if writeln!(out, "{}: {}", path.display(), line).is_err() {
+                return 0;
+            }
+
+            found += 1;
+        }
+    }
+
+    if found == 0 {
+        eprintln!("No transcript lines found for request {}", id);
+        1
+    } else {
+        0
+    }
+}
+
+// Arguments that are neither flags nor flag values, e.g. paths
+pub fn get_positional<'a>(args: &'a [String], options: &[&str]) -> Vec<&'a str> {
+    let mut positional = Vec::new();
+    let mut skip = false;
+
+    for arg in args {
+        if skip {
+            skip = false;
+        } else if options.contains(&arg.as_str()) {
+            skip = true;
+        } else if !arg.starts_with("--") {
+            positional.push(arg.as_str());
+        }
+    }
+
+    positional
+}
+
+// Reads every transcript below the given paths, the default log directory if none are given
+pub fn read_exchanges(paths: &[&str]) -> Vec<RecordedExchange> {
+    let paths = if paths.is_empty() { vec![DEFAULT_LOG_DIR] } else { paths.to_vec() };
+    let mut exchanges = Vec::new();
+
+    for path in paths {
+        let path = Path::new(path);
+        let files = if path.is_dir() { list_log_files(path) } else { vec![path.to_path_buf()] };
+
+        for file in files {
+            let Some(contents) = read_log_file(&file) else {
+                continue;
+            };
+
+            // Text transcripts only carry the date in their open event
+            let modified: DateTime<Utc> = metadata(&file).and_then(|meta| meta.modified()).map(Into::into).unwrap_or_else(|_| Utc::now());
+            exchanges.extend(read_transcript(&contents, modified.date_naive()));
+        }
+    }
+
+    exchanges
+}
+
+fn request_needles(id: &str) -> Vec<String> {
+    vec![format!("[{}]", id), format!("\"request_id\":\"{}\"", id)]
+}
+
+// Reads a transcript, decompressing rotated .gz files
+pub fn read_log_file(path: &Path) -> Option<String> {
+    if path.extension().is_some_and(|extension| extension == "gz") {
+        let mut contents = String::new();
+        GzDecoder::new(File::open(path).ok()?).read_to_string(&mut contents).ok()?;
+        Some(contents)
+    } else {
+        read_to_string(path).ok()
+    }
+}
+
+pub fn list_log_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let Ok(entries) = read_dir(dir) else {
+        return files;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            files.extend(list_log_files(&path));
+        } else {
+            files.push(path);
+        }
+    }
+
+    files.sort();
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn separates_options_from_paths() {
+        let args = args(&["--dir", "logs", "--timing", "a.log", "--header", "A: 1", "--header", "B: 2", "b"]);
+        assert_eq!(get_positional(&args, &["--dir", "--header"]), ["a.log", "b"]);
+        assert_eq!(get_option(&args, "--dir"), Some("logs"));
+        assert_eq!(get_options(&args, "--header"), ["A: 1", "B: 2"]);
+        assert_eq!(get_option(&args, "--out"), None);
+    }
+
+    #[test]
+    fn reads_plain_and_rotated_transcripts() {
+        let dir = std::env::temp_dir().join(format!("myhttp-log-tools-{}", std::process::id()));
+        let nested = dir.join("2026-10-19");
+        std::fs::create_dir_all(&nested).unwrap();
+
+        write(dir.join("a.log"), "[10:00:00.000] [a] 127.0.0.1:1 --> GET /a HTTP/1.1\n").unwrap();
+
+        let mut encoder = GzEncoder::new(File::create(nested.join("b.log.gz")).unwrap(), Compression::default());
+        encoder.write_all(b"[10:00:00.000] [b] 127.0.0.1:2 --> GET /b HTTP/1.1\n").unwrap();
+        encoder.finish().unwrap();
+
+        let exchanges = read_exchanges(&[dir.to_str().unwrap()]);
+        let ids: Vec<&str> = exchanges.iter().map(|exchange| exchange.request_id.as_str()).collect();
+        assert_eq!(ids, ["b", "a"]);
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use memmap2::Mmap;
+
+use crate::http_error::{http_errors, HttpError};
+
+// Large enough to keep syscalls down, small enough that a truncation is noticed before much of it is read
+pub const CHUNK_SIZE: usize = 256 * 1024;
+
+// A memory mapped static file. Reading pages past the end of a file that was truncated
+// after mapping raises SIGBUS, so content is only handed out through `get_chunk`, which is
+// copied by the kernel, or read with pread where it would be copied in process.
+pub struct MappedFile {
+    map: Mmap,
+    // Kept open to check the current length against the mapping
+    file: File,
+    pub modified: SystemTime,
+    pub len: u64
+}
+
+// Mappings in use, so concurrent requests for one file share a single mapping.
+// Entries go away with the last response holding them.
+static MAPPED: Mutex<Option<HashMap<PathBuf, Weak<MappedFile>>>> = Mutex::new(None);
+
+impl MappedFile {
+    fn open(path: &Path) -> Result<Self, HttpError> {
+        let file = File::open(path).map_err(|e| HttpError::convert_from(e, Some("Failed to open file")))?;
+        let metadata = file.metadata().map_err(|e| HttpError::convert_from(e, Some("Failed to read file metadata")))?;
+
+        // SAFETY: the mapping is read only and never handed out as a whole, `get_chunk` checks that
+        // the file still covers a range before returning it
+        let map = unsafe { Mmap::map(&file) }.map_err(|e| HttpError::convert_from(e, Some("Failed to map file")))?;
+
+        Ok(Self {
+            map,
+            file,
+            modified: metadata.modified().unwrap_or(UNIX_EPOCH),
+            len: metadata.len()
+        })
+    }
+
+    // Returns up to CHUNK_SIZE bytes from `offset`, or an error if the file was truncated below that range.
+    // Chunks are only copied by the kernel while writing to the socket, a truncation after the check
+    // fails that write with EFAULT instead of faulting the process.
+    pub fn get_chunk(&self, offset: usize) -> Result<&[u8], HttpError> {
+        let end = (offset + CHUNK_SIZE).min(self.map.len());
+        self.check_len(end)?;
+
+        Ok(&self.map[offset..end])
+    }
+
+    // Reads up to `size` bytes from the start of the file, fewer if it was truncated since
+    pub fn read_prefix(&self, size: usize) -> Result<Vec<u8>, HttpError> {
+        let mut buffer = vec![0; size.min(self.len as usize)];
+        let read = self.read_at(0, &mut buffer)?;
+        buffer.truncate(read);
+
+        Ok(buffer)
+    }
+
+    // Like `get_chunk`, but read into a buffer, for writers that copy the chunk in process
+    #[cfg(feature = "async")]
+    pub fn read_chunk(&self, offset: usize) -> Result<Vec<u8>, HttpError> {
+        let end = (offset + CHUNK_SIZE).min(self.len as usize);
+        let mut buffer = vec![0; end.saturating_sub(offset)];
+        let read = self.read_at(offset, &mut buffer)?;
+        if read < buffer.len() {
+            return Err(http_errors::msg::internal_server_error(format!("File was truncated to {} bytes while being sent", offset + read).as_str())
+                .set_info("Failed to send mapped file"));
+        }
+
+        Ok(buffer)
+    }
+
+    // Fills `buffer` from `offset`, returns how much was read before the end of the file
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize, HttpError> {
+        let mut read = 0;
+        while read < buffer.len() {
+            match self.file.read_at(&mut buffer[read..], (offset + read) as u64) {
+                Ok(0) => break,
+                Ok(count) => read += count,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(HttpError::convert_from(e, Some("Failed to read mapped file")))
+            }
+        }
+
+        Ok(read)
+    }
+
+    fn check_len(&self, end: usize) -> Result<(), HttpError> {
+        let current = self.file.metadata().map_err(|e| HttpError::convert_from(e, Some("Failed to read file metadata")))?.len();
+        if current < end as u64 {
+            return Err(http_errors::msg::internal_server_error(format!("File was truncated to {} bytes while being sent", current).as_str())
+                .set_info("Failed to send mapped file"));
+        }
+
+        Ok(())
+    }
+
+    fn is_current(&self, len: u64, modified: SystemTime) -> bool {
+        self.len == len && self.modified == modified
+    }
+}
+
+// Returns the shared mapping of the canonical `path`, mapping it again if its size or mtime changed
+pub fn get(path: &Path, len: u64, modified: SystemTime) -> Result<Arc<MappedFile>, HttpError> {
+    {
+        let mut mapped = MAPPED.lock().unwrap_or_else(|e| e.into_inner());
+        let mapped = mapped.get_or_insert_with(HashMap::new);
+
+        if let Some(file) = mapped.get(path).and_then(Weak::upgrade) {
+            if file.is_current(len, modified) {
+                return Ok(file);
+            }
+        }
+    }
+
+    let file = Arc::new(MappedFile::open(path)?);
+
+    let mut mapped = MAPPED.lock().unwrap_or_else(|e| e.into_inner());
+    let mapped = mapped.get_or_insert_with(HashMap::new);
+    mapped.retain(|_, file| file.strong_count() > 0);
+    mapped.insert(path.to_path_buf(), Arc::downgrade(&file));
+
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapped(name: &str, data: &[u8]) -> (PathBuf, Arc<MappedFile>) {
+        let path = std::env::temp_dir().join(format!("myhttp-mapped-{}-{}", name, std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+        let file = get(&path, metadata.len(), metadata.modified().unwrap()).unwrap();
+
+        (path, file)
+    }
+
+    #[test]
+    fn reads_the_prefix_and_chunks() {
+        let data: Vec<u8> = (0..CHUNK_SIZE + 10).map(|i| i as u8).collect();
+        let (path, file) = mapped("chunks", &data);
+
+        assert_eq!(file.read_prefix(16).unwrap(), &data[..16]);
+        #[cfg(feature = "async")]
+        {
+            assert_eq!(file.read_chunk(0).unwrap(), &data[..CHUNK_SIZE]);
+            assert_eq!(file.read_chunk(CHUNK_SIZE).unwrap(), &data[CHUNK_SIZE..]);
+        }
+        assert_eq!(file.get_chunk(CHUNK_SIZE).unwrap(), &data[CHUNK_SIZE..]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn truncated_files_are_not_read_past_their_end() {
+        let data = vec![7u8; 3 * 4096];
+        let (path, file) = mapped("truncated", &data);
+        std::fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(100).unwrap();
+
+        assert_eq!(file.read_prefix(4096).unwrap(), &data[..100]);
+        #[cfg(feature = "async")]
+        assert!(file.read_chunk(0).is_err());
+        assert!(file.get_chunk(0).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
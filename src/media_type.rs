@@ -0,0 +1,223 @@
+use std::fmt;
+
+use crate::http_error::{HttpError, http_errors};
+use crate::headers::is_token;
+
+#[derive(Clone, PartialEq)]
+pub struct MediaType {
+    pub main_type: String,
+    pub sub_type: String,
+    pub params: Vec<(String, String)>
+}
+
+#[derive(Clone)]
+pub struct MediaRange {
+    pub media_type: MediaType,
+    pub quality: f32
+}
+
+#[allow(unused)]
+impl MediaType {
+    pub fn new(main_type: &str, sub_type: &str) -> Self {
+        Self {
+            main_type: main_type.to_ascii_lowercase(),
+            sub_type: sub_type.to_ascii_lowercase(),
+            params: Vec::new()
+        }
+    }
+
+    pub fn parse(input: &str) -> Result<Self, HttpError> {
+        let mut parts = input.split(';');
+
+        let essence = parts.next().unwrap_or("").trim();
+        let (main_type, sub_type) = essence.split_once('/').ok_or_else(|| {
+            http_errors::msg::bad_request(format!("Media type \"{}\" is missing a subtype", essence).as_str())
+                .set_info("Malformed media type")
+        })?;
+
+        if !is_token(main_type) || !is_token(sub_type) {
+            return Err(http_errors::msg::bad_request(format!("Media type \"{}\" is not a valid token pair", essence).as_str())
+                .set_info("Malformed media type"));
+        }
+
+        let mut media_type = Self::new(main_type, sub_type);
+
+        for param in parts {
+            let param = param.trim();
+            if param.is_empty() {
+                continue;
+            }
+
+            let (name, value) = param.split_once('=').ok_or_else(|| {
+                http_errors::msg::bad_request(format!("Media type parameter \"{}\" has no value", param).as_str())
+                    .set_info("Malformed media type")
+            })?;
+
+            let name = name.trim();
+            if !is_token(name) {
+                return Err(http_errors::msg::bad_request(format!("Media type parameter \"{}\" is not a valid token", name).as_str())
+                    .set_info("Malformed media type"));
+            }
+
+            let value = value.trim();
+            let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                value[1..value.len() - 1].replace("\\\"", "\"")
+            } else {
+                value.to_string()
+            };
+
+            media_type.params.push((name.to_ascii_lowercase(), value));
+        }
+
+        Ok(media_type)
+    }
+
+    pub fn with_param(mut self, name: &str, value: &str) -> Self {
+        self.params.push((name.to_ascii_lowercase(), value.to_string()));
+        self
+    }
+
+    pub fn get_param(&self, name: &str) -> Option<&String> {
+        self.params.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value)
+    }
+
+    pub fn essence(&self) -> String {
+        format!("{}/{}", self.main_type, self.sub_type)
+    }
+
+    // Wildcards are only meaningful on the Accept side, so `self` is the range being tested
+    pub fn matches(&self, other: &MediaType) -> bool {
+        (self.main_type == "*" || self.main_type == other.main_type)
+            && (self.sub_type == "*" || self.sub_type == other.sub_type)
+    }
+
+    pub fn specificity(&self) -> i32 {
+        if self.main_type == "*" {
+            0
+        } else if self.sub_type == "*" {
+            1
+        } else {
+            2 + self.params.len() as i32
+        }
+    }
+}
+
+#[allow(unused)]
+impl MediaRange {
+    pub fn parse_list(input: &str) -> Result<Vec<Self>, HttpError> {
+        let mut ranges = Vec::new();
+
+        for item in input.split(',') {
+            let item = item.trim();
+            if item.is_empty() {
+                continue;
+            }
+
+            let mut media_type = MediaType::parse(item)?;
+            let mut quality = 1.0;
+
+            if let Some(index) = media_type.params.iter().position(|(key, _)| key == "q") {
+                let (_, value) = media_type.params.remove(index);
+                quality = parse_quality(&value)?;
+            }
+
+            ranges.push(Self { media_type, quality });
+        }
+
+        // Highest quality first, more specific ranges win ties (RFC 9110 12.5.1)
+        ranges.sort_by(|a, b| {
+            b.quality.partial_cmp(&a.quality).unwrap_or(std::cmp::Ordering::Equal)
+                .then(b.media_type.specificity().cmp(&a.media_type.specificity()))
+        });
+
+        Ok(ranges)
+    }
+
+    // Picks the acceptable candidate with the highest quality, earlier candidates win ties
+    pub fn negotiate<'a>(ranges: &[MediaRange], candidates: &[&'a str]) -> Option<&'a str> {
+        let mut best: Option<(&'a str, f32)> = None;
+
+        for candidate in candidates {
+            let Ok(media_type) = MediaType::parse(candidate) else {
+                continue;
+            };
+
+            // The most specific matching range decides the quality (RFC 9110 12.5.1)
+            let quality = ranges.iter()
+                .filter(|range| range.media_type.matches(&media_type))
+                .max_by_key(|range| range.media_type.specificity())
+                .map(|range| range.quality)
+                .unwrap_or(0.0);
+
+            if quality > 0.0 && best.is_none_or(|(_, best_quality)| quality > best_quality) {
+                best = Some((candidate, quality));
+            }
+        }
+
+        best.map(|(candidate, _)| candidate)
+    }
+}
+
+fn parse_quality(value: &str) -> Result<f32, HttpError> {
+    let malformed = || http_errors::msg::bad_request(format!("Invalid quality value \"{}\"", value).as_str())
+        .set_info("Malformed media type");
+
+    if value.is_empty() || value.len() > 5 || !value.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return Err(malformed());
+    }
+
+    let quality: f32 = value.parse().map_err(|_| malformed())?;
+    if !(0.0..=1.0).contains(&quality) {
+        return Err(malformed());
+    }
+
+    Ok(quality)
+}
+
+impl fmt::Display for MediaType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.main_type, self.sub_type)?;
+
+        for (name, value) in &self.params {
+            if is_token(value) {
+                write!(f, "; {}={}", name, value)?;
+            } else {
+                write!(f, "; {}=\"{}\"", name, value.replace('"', "\\\""))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for MediaRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.quality < 1.0 {
+            write!(f, "{}; q={}", self.media_type, self.quality)
+        } else {
+            write!(f, "{}", self.media_type)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_parameters() {
+        let media_type = MediaType::parse("Text/HTML; Charset=\"utf-8\"").unwrap();
+        assert_eq!(media_type.essence(), "text/html");
+        assert_eq!(media_type.get_param("charset").map(String::as_str), Some("utf-8"));
+        assert!(MediaType::parse("text").is_err());
+        assert!(MediaType::parse("text/html; charset").is_err());
+    }
+
+    #[test]
+    fn sorts_ranges_by_quality_then_specificity() {
+        let ranges = MediaRange::parse_list("*/*;q=0.5, text/*, text/html").unwrap();
+        let order: Vec<String> = ranges.iter().map(|range| range.media_type.essence()).collect();
+        assert_eq!(order, ["text/html", "text/*", "*/*"]);
+        assert!(MediaRange::parse_list("text/html;q=2").is_err());
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use chrono::Utc;
+
+use crate::event_loop;
+use crate::file_cache;
+use crate::request::HttpRequest;
+use crate::rules::RuleMatch;
+use crate::transcript_writer;
+
+// Upper bounds in seconds, requests served from disk mostly land in the first few
+const DURATION_BUCKETS: [f64; 13] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+// Anything else is counted as "other", clients pick methods and this keeps label values bounded
+const KNOWN_METHODS: [&str; 9] = ["GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH"];
+
+const OTHER_LABEL: &str = "other";
+
+// Names a group of request paths for the route label, the first match wins
+pub struct MetricsRoute {
+    pub matcher: RuleMatch,
+    pub name: String
+}
+
+// Decrements the in flight gauge when the connection ends
+pub struct ConnectionGuard;
+
+struct Histogram {
+    // Not cumulative, rendering adds them up
+    buckets: [u64; DURATION_BUCKETS.len()],
+    sum: f64,
+    count: u64
+}
+
+struct Registry {
+    // (method, status, route)
+    requests: BTreeMap<(String, String, String), u64>,
+    durations: BTreeMap<String, Histogram>
+}
+
+static REGISTRY: Mutex<Option<Registry>> = Mutex::new(None);
+static CONNECTIONS_TOTAL: AtomicU64 = AtomicU64::new(0);
+static CONNECTIONS_IN_FLIGHT: AtomicI64 = AtomicI64::new(0);
+static BYTES_RECEIVED: AtomicU64 = AtomicU64::new(0);
+static BYTES_SENT: AtomicU64 = AtomicU64::new(0);
+static TRANSCRIPT_WRITE_FAILURES: AtomicU64 = AtomicU64::new(0);
+
+impl MetricsRoute {
+    // Parses `<exact|prefix|regex> <pattern> <name>`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = value.split_whitespace().collect();
+        if parts.len() != 3 {
+            return Err(format!("Metrics route \"{}\" did not match <exact|prefix|regex> <pattern> <name>", value));
+        }
+
+        Ok(Self {
+            matcher: RuleMatch::parse(parts[0], parts[1])?,
+            name: parts[2].to_string()
+        })
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        CONNECTIONS_IN_FLIGHT.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self {
+            buckets: [0; DURATION_BUCKETS.len()],
+            sum: 0.0,
+            count: 0
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(index) = DURATION_BUCKETS.iter().position(|bound| value <= *bound) {
+            self.buckets[index] += 1;
+        }
+
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+impl Registry {
+    fn new() -> Self {
+        Self {
+            requests: BTreeMap::new(),
+            durations: BTreeMap::new()
+        }
+    }
+}
+
+pub fn track_connection() -> ConnectionGuard {
+    CONNECTIONS_TOTAL.fetch_add(1, Ordering::Relaxed);
+    CONNECTIONS_IN_FLIGHT.fetch_add(1, Ordering::Relaxed);
+    ConnectionGuard
+}
+
+// Called once per response, next to the access log
+pub fn record_request(request: &HttpRequest, status: i32) {
+    let method = if KNOWN_METHODS.contains(&request.method.as_str()) { request.method.as_str() } else { OTHER_LABEL };
+    let route = get_route(request);
+    let duration = (Utc::now() - request.start).num_microseconds().unwrap_or(0).max(0) as f64 / 1_000_000.0;
+
+    let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    let registry = registry.get_or_insert_with(Registry::new);
+
+    *registry.requests.entry((method.to_string(), status.to_string(), route.to_string())).or_insert(0) += 1;
+    registry.durations.entry(route.to_string()).or_insert_with(Histogram::new).observe(duration);
+}
+
+// Wire bytes of a finished connection, as counted by its transcript
+pub fn add_bytes(received: u64, sent: u64) {
+    BYTES_RECEIVED.fetch_add(received, Ordering::Relaxed);
+    BYTES_SENT.fetch_add(sent, Ordering::Relaxed);
+}
+
+pub fn add_transcript_write_failure() {
+    TRANSCRIPT_WRITE_FAILURES.fetch_add(1, Ordering::Relaxed);
+}
+
+fn get_route(request: &HttpRequest) -> &str {
+    if !request.is_init {
+        return OTHER_LABEL;
+    }
+
+    request.config.metrics.routes.iter()
+        .find(|route| route.matcher.matches(&request.path))
+        .map(|route| route.name.as_str())
+        .unwrap_or(OTHER_LABEL)
+}
+
+// Prometheus text exposition format, version 0.0.4
+pub fn render() -> String {
+    let mut out = String::new();
+
+    {
+        let registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+        let empty = Registry::new();
+        let registry = registry.as_ref().unwrap_or(&empty);
+
+        write_family(&mut out, "myhttp_requests_total", "counter", "Requests answered, by method, status and route.");
+        for ((method, status, route), count) in &registry.requests {
+            writeln!(out, "myhttp_requests_total{{method=\"{}\",status=\"{}\",route=\"{}\"}} {}", escape_label(method), status, escape_label(route), count).ok();
+        }
+
+        write_family(&mut out, "myhttp_request_duration_seconds", "histogram", "Time from accepting a connection to the end of its response, by route.");
+        for (route, histogram) in &registry.durations {
+            let route = escape_label(route);
+            let mut cumulative = 0;
+
+            for (bound, count) in DURATION_BUCKETS.iter().zip(histogram.buckets.iter()) {
+                cumulative += count;
+                writeln!(out, "myhttp_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}", route, bound, cumulative).ok();
+            }
+
+            writeln!(out, "myhttp_request_duration_seconds_bucket{{route=\"{}\",le=\"+Inf\"}} {}", route, histogram.count).ok();
+            writeln!(out, "myhttp_request_duration_seconds_sum{{route=\"{}\"}} {}", route, histogram.sum).ok();
+            writeln!(out, "myhttp_request_duration_seconds_count{{route=\"{}\"}} {}", route, histogram.count).ok();
+        }
+    }
+
+    write_value(&mut out, "myhttp_connections_total", "counter", "Connections accepted.", CONNECTIONS_TOTAL.load(Ordering::Relaxed));
+    write_value(&mut out, "myhttp_connections_in_flight", "gauge", "Connections currently being handled.", CONNECTIONS_IN_FLIGHT.load(Ordering::Relaxed));
+    write_value(&mut out, "myhttp_received_bytes_total", "counter", "Bytes received from clients.", BYTES_RECEIVED.load(Ordering::Relaxed));
+    write_value(&mut out, "myhttp_sent_bytes_total", "counter", "Bytes sent to clients.", BYTES_SENT.load(Ordering::Relaxed));
+    write_value(&mut out, "myhttp_transcript_write_failures_total", "counter", "Transcript lines or files the sinks failed to write.", TRANSCRIPT_WRITE_FAILURES.load(Ordering::Relaxed));
+    write_value(&mut out, "myhttp_transcript_dropped_lines_total", "counter", "Transcript lines discarded because the writer queue was full.", transcript_writer::get_dropped_count());
+
+    let cache = file_cache::get_stats();
+    write_value(&mut out, "myhttp_file_cache_hits_total", "counter", "Static files served from the in-memory cache.", cache.hits);
+    write_value(&mut out, "myhttp_file_cache_misses_total", "counter", "Static files read from disk.", cache.misses);
+    write_value(&mut out, "myhttp_file_cache_mapped_total", "counter", "Static files served from a memory mapping.", cache.mapped);
+    write_value(&mut out, "myhttp_file_cache_entries", "gauge", "Files held in the in-memory cache.", cache.entries);
+    write_value(&mut out, "myhttp_file_cache_bytes", "gauge", "Bytes held in the in-memory cache, gzip variants included.", cache.size);
+
+    // Where work waits: the transcript writer queue, and with the event loop core the worker queue
+    write_value(&mut out, "myhttp_transcript_queue_depth", "gauge", "Transcript lines waiting for the writer thread.", transcript_writer::get_queue_depth());
+    write_value(&mut out, "myhttp_worker_queue_depth", "gauge", "Connections with a complete request head waiting for an event loop worker.", event_loop::get_queue_depth());
+
+    out
+}
+
+fn write_family(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).ok();
+    writeln!(out, "# TYPE {} {}", name, kind).ok();
+}
+
+fn write_value<T: std::fmt::Display>(out: &mut String, name: &str, kind: &str, help: &str, value: T) {
+    write_family(out, name, kind, help);
+    writeln!(out, "{} {}", name, value).ok();
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::config::ServerConfig;
+    use crate::transcript::TranscriptLevel;
+
+    #[test]
+    fn histogram_buckets_are_cumulative_when_rendered() {
+        let mut histogram = Histogram::new();
+        for value in [0.0005, 0.001, 0.3, 60.0] {
+            histogram.observe(value);
+        }
+
+        assert_eq!(histogram.buckets[0], 2);
+        assert_eq!(histogram.buckets[8], 1);
+        assert_eq!(histogram.buckets.iter().sum::<u64>(), 3);
+        assert_eq!(histogram.count, 4);
+    }
+
+    #[test]
+    fn records_requests_by_route() {
+        let mut config = ServerConfig::new();
+        config.transcript.level = TranscriptLevel::Off;
+        config.metrics.routes = vec![MetricsRoute::parse("prefix /metrics-test/ metrics\"test").unwrap()];
+
+        let mut request = HttpRequest::new(String::from("127.0.0.1:1"), Arc::new(config)).unwrap();
+        request.read_head(&mut Cursor::new(&b"GET /metrics-test/a HTTP/1.1\r\nHost: x\r\n\r\n"[..])).unwrap();
+        record_request(&request, 200);
+        record_request(&request, 200);
+
+        let out = render();
+        assert!(out.contains("myhttp_requests_total{method=\"GET\",status=\"200\",route=\"metrics\\\"test\"} 2\n"), "{}", out);
+        assert!(out.contains("myhttp_request_duration_seconds_count{route=\"metrics\\\"test\"} 2\n"));
+        assert!(out.contains("# TYPE myhttp_connections_in_flight gauge\n"));
+    }
+
+    #[test]
+    fn rejects_malformed_routes() {
+        assert!(MetricsRoute::parse("prefix /api/").is_err());
+        assert!(MetricsRoute::parse("glob /api/ api").is_err());
+    }
+}
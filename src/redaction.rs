@@ -0,0 +1,194 @@
+use std::borrow::Cow;
+
+use regex::{Captures, Regex};
+use sha2::{Digest, Sha256};
+
+use crate::util::generate_id;
+
+pub const DEFAULT_HEADERS: [&str; 4] = ["Authorization", "Proxy-Authorization", "Cookie", "Set-Cookie"];
+pub const DEFAULT_MASK: &str = "[REDACTED]";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RedactionStyle {
+    // Replace values with the configured mask
+    Mask,
+    // Replace values with a salted SHA-256 prefix, so equal values can still be correlated
+    Hash
+}
+
+#[derive(Clone)]
+pub struct Redaction {
+    pub headers: Vec<String>,
+    query_params: Vec<String>,
+    pub body_patterns: Vec<Regex>,
+    pub style: RedactionStyle,
+    pub mask: String,
+    pub salt: String,
+    // Matches `name=value` for any of query_params
+    query_regex: Option<Regex>
+}
+
+impl RedactionStyle {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "mask" => Ok(RedactionStyle::Mask),
+            "hash" => Ok(RedactionStyle::Hash),
+            _ => Err(format!("Unknown redaction style \"{}\", expected mask or hash", value))
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            RedactionStyle::Mask => "mask",
+            RedactionStyle::Hash => "hash"
+        }
+    }
+}
+
+impl Default for Redaction {
+    fn default() -> Self {
+        Self {
+            headers: DEFAULT_HEADERS.iter().map(|name| name.to_string()).collect(),
+            query_params: Vec::new(),
+            body_patterns: Vec::new(),
+            style: RedactionStyle::Mask,
+            mask: String::from(DEFAULT_MASK),
+            // Without a configured salt hashes only correlate within one run
+            salt: format!("{}{}", generate_id(), generate_id()),
+            query_regex: None
+        }
+    }
+}
+
+impl Redaction {
+    // Comma separated list, an empty value disables the list
+    pub fn parse_list(value: &str) -> Vec<String> {
+        value.split(',').map(str::trim).filter(|item| !item.is_empty()).map(str::to_string).collect()
+    }
+
+    pub fn parse_body_pattern(value: &str) -> Result<Regex, String> {
+        Regex::new(value).map_err(|e| format!("Invalid body redaction pattern \"{}\": {}", value, e))
+    }
+
+    pub fn set_query_params(&mut self, names: Vec<String>) -> Result<(), String> {
+        self.query_regex = if names.is_empty() {
+            None
+        } else {
+            let escaped: Vec<String> = names.iter().map(|name| regex::escape(name)).collect();
+            let pattern = format!("([?&;](?:{})=)([^&;#\\s]*)", escaped.join("|"));
+            Some(Regex::new(&pattern).map_err(|e| format!("Invalid query redaction list: {}", e))?)
+        };
+
+        self.query_params = names;
+        Ok(())
+    }
+
+    pub fn get_query_params(&self) -> &[String] {
+        &self.query_params
+    }
+
+    pub fn is_redacted_header(&self, name: &str) -> bool {
+        let name = name.trim();
+        self.headers.iter().any(|header| header.eq_ignore_ascii_case(name))
+    }
+
+    // True for values that went through redact_value with either style, e.g. when replaying transcripts
+    pub fn is_redacted_value(value: &str, mask: &str) -> bool {
+        value == mask || value.strip_prefix("sha256:").is_some_and(|hex| hex.len() == 16 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+    }
+
+    pub fn redact_value(&self, value: &str) -> String {
+        match self.style {
+            RedactionStyle::Mask => self.mask.clone(),
+            RedactionStyle::Hash => {
+                let digest = Sha256::new().chain_update(self.salt.as_bytes()).chain_update(value.as_bytes()).finalize();
+                let hex: String = digest.iter().take(8).map(|byte| format!("{:02x}", byte)).collect();
+                format!("sha256:{}", hex)
+            }
+        }
+    }
+
+    pub fn redact_header<'a>(&self, name: &str, value: &'a str) -> Cow<'a, str> {
+        if self.is_redacted_header(name) {
+            Cow::Owned(self.redact_value(value))
+        } else {
+            self.redact_query(value)
+        }
+    }
+
+    // Masks configured parameters wherever a query string appears, e.g. request lines and Referer
+    pub fn redact_query<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        match &self.query_regex {
+            Some(regex) => regex.replace_all(text, |captures: &Captures| {
+                format!("{}{}", &captures[1], self.redact_value(&captures[2]))
+            }),
+            None => Cow::Borrowed(text)
+        }
+    }
+
+    // A pattern with a capture group only redacts the first group, otherwise the whole match
+    pub fn redact_body<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut result = Cow::Borrowed(text);
+
+        for pattern in &self.body_patterns {
+            let replaced = pattern.replace_all(&result, |captures: &Captures| {
+                let whole = captures.get(0).map(|group| group.as_str()).unwrap_or("");
+                match captures.get(1) {
+                    Some(group) => {
+                        let start = group.start() - captures.get(0).map(|whole| whole.start()).unwrap_or(0);
+                        format!("{}{}{}", &whole[..start], self.redact_value(group.as_str()), &whole[start + group.len()..])
+                    },
+                    None => self.redact_value(whole)
+                }
+            });
+
+            if let Cow::Owned(replaced) = replaced {
+                result = Cow::Owned(replaced);
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_names_match_untrimmed() {
+        let redaction = Redaction::default();
+        assert!(redaction.is_redacted_header("authorization"));
+        assert!(redaction.is_redacted_header("Authorization "));
+        assert_eq!(redaction.redact_header("Cookie\t", "a=1"), "[REDACTED]");
+        assert_eq!(redaction.redact_header("Accept", "*/*"), "*/*");
+    }
+
+    #[test]
+    fn redacts_query_parameters_only() {
+        let mut redaction = Redaction::default();
+        redaction.set_query_params(vec![String::from("token")]).unwrap();
+
+        assert_eq!(redaction.redact_query("GET /a?token=abc&mytoken=1;token=x HTTP/1.1"), "GET /a?token=[REDACTED]&mytoken=1;token=[REDACTED] HTTP/1.1");
+        assert!(matches!(redaction.redact_query("GET /a?b=1 HTTP/1.1"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn hashes_equal_values_equally() {
+        let redaction = Redaction { style: RedactionStyle::Hash, salt: String::from("salt"), ..Redaction::default() };
+        let hash = redaction.redact_value("secret");
+        assert!(hash.starts_with("sha256:") && hash.len() == 23);
+        assert_eq!(hash, redaction.redact_value("secret"));
+        assert_ne!(hash, redaction.redact_value("other"));
+    }
+
+    #[test]
+    fn body_patterns_redact_their_first_group() {
+        let redaction = Redaction {
+            body_patterns: vec![Redaction::parse_body_pattern("\"password\":\"([^\"]*)\"").unwrap(), Redaction::parse_body_pattern("\\d{16}").unwrap()],
+            ..Redaction::default()
+        };
+
+        assert_eq!(redaction.redact_body("{\"password\":\"hunter2\",\"card\":\"4111111111111111\"}"), "{\"password\":\"[REDACTED]\",\"card\":\"[REDACTED]\"}");
+    }
+}
@@ -0,0 +1,311 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use crate::headers::is_token;
+use crate::log_tools::{get_option, get_options, get_positional, read_exchanges};
+use crate::redaction::{Redaction, DEFAULT_MASK};
+use crate::transcript_reader::RecordedExchange;
+
+const USAGE: &str = "Usage: myhttp replay [--target <host:port>] [--timing] [--speed <factor>] [--request <id>] [--ignore-header <name,...>]
+                     [--header <name: value>]... [--mask <text>] [<file or dir>...]";
+const DEFAULT_TARGET: &str = "127.0.0.1:8080";
+
+// Differ on every run, so never reported unless asked for
+const VOLATILE_HEADERS: [&str; 3] = ["X-Request-Id", "X-Error-Id", "Date"];
+
+struct ReplayedResponse {
+    status_line: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>
+}
+
+// Entry point for `myhttp replay ...`, returns the process exit code
+pub fn run(args: &[String]) -> i32 {
+    let target = get_option(args, "--target").unwrap_or(DEFAULT_TARGET);
+    let timing = args.iter().any(|arg| arg == "--timing");
+    let speed = match get_option(args, "--speed").map(str::parse::<f64>) {
+        None => 1.0,
+        Some(Ok(speed)) if speed > 0.0 => speed,
+        Some(_) => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+
+    let mut ignored: Vec<String> = VOLATILE_HEADERS.iter().map(|name| name.to_string()).collect();
+    if let Some(names) = get_option(args, "--ignore-header") {
+        ignored.extend(names.split(',').map(|name| name.trim().to_string()));
+    }
+
+    // Replaces recorded headers, e.g. credentials the transcript redacted
+    let mut headers = Vec::new();
+    for header in get_options(args, "--header") {
+        match header.split_once(':') {
+            Some((name, value)) if is_token(name) => headers.push((name.to_string(), value.trim().to_string())),
+            _ => {
+                eprintln!("\"{}\" is not a header, expected <name: value>", header);
+                return 2;
+            }
+        }
+    }
+
+    let mask = get_option(args, "--mask").unwrap_or(DEFAULT_MASK);
+
+    let paths = get_positional(args, &["--target", "--speed", "--request", "--ignore-header", "--header", "--mask"]);
+    let mut exchanges: Vec<RecordedExchange> = read_exchanges(&paths).into_iter()
+        .filter(|exchange| exchange.request_line.is_some())
+        .filter(|exchange| get_option(args, "--request").is_none_or(|id| exchange.request_id == id))
+        .collect();
+
+    if exchanges.is_empty() {
+        eprintln!("No recorded requests found");
+        return 1;
+    }
+
+    exchanges.sort_by_key(|exchange| exchange.request_time);
+
+    let first = exchanges[0].request_time;
+    let started = std::time::Instant::now();
+    let mut differences = 0;
+
+    for exchange in &exchanges {
+        // Keep the recorded spacing between requests, scaled by --speed
+        if timing {
+            if let (Some(first), Some(time)) = (first, exchange.request_time) {
+                let offset = Duration::from_secs_f64((time - first).num_microseconds().unwrap_or(0).max(0) as f64 / 1_000_000.0 / speed);
+                if let Some(remaining) = offset.checked_sub(started.elapsed()) {
+                    thread::sleep(remaining);
+                }
+            }
+        }
+
+        let request_line = exchange.request_line.as_deref().unwrap_or("");
+        let (request, warnings) = build_request(exchange, &headers, mask);
+        for warning in warnings {
+            println!("WARN [{}] {}", exchange.request_id, warning);
+        }
+
+        let response = match send_request(target, &request) {
+            Ok(response) => response,
+            Err(e) => {
+                println!("FAIL [{}] {}: {}", exchange.request_id, request_line, e);
+                differences += 1;
+                continue;
+            }
+        };
+
+        let diffs = compare(exchange, &response, &ignored);
+        if diffs.is_empty() {
+            println!("OK   [{}] {} -> {}", exchange.request_id, request_line, response.status_line);
+        } else {
+            println!("DIFF [{}] {}", exchange.request_id, request_line);
+            for diff in diffs {
+                println!("    {}", diff);
+            }
+
+            differences += 1;
+        }
+    }
+
+    println!("{} requests replayed, {} differed", exchanges.len(), differences);
+    if differences > 0 { 1 } else { 0 }
+}
+
+// Rebuilds the raw request from the recorded request line and headers, with warnings for what redaction lost.
+// `headers` replace recorded ones, redacted headers without a replacement are left out rather than sent as the mask.
+fn build_request(exchange: &RecordedExchange, headers: &[(String, String)], mask: &str) -> (String, Vec<String>) {
+    let mut warnings = Vec::new();
+
+    let query = exchange.get_request_parts().and_then(|(_, target, _)| target.split_once('?')).map(|(_, query)| query).unwrap_or("");
+    if query.split(['&', ';']).filter_map(|pair| pair.split_once('=')).any(|(_, value)| Redaction::is_redacted_value(value, mask)) {
+        warnings.push(String::from("query has redacted values, they are sent as recorded"));
+    }
+
+    let mut request = format!("{}\r\n", exchange.request_line.as_deref().unwrap_or(""));
+    for (name, value) in &exchange.request_headers {
+        if headers.iter().any(|(replaced, _)| replaced.eq_ignore_ascii_case(name)) {
+            continue;
+        }
+
+        if Redaction::is_redacted_value(value, mask) {
+            warnings.push(format!("header {} was redacted and is not sent, supply it with --header", name));
+            continue;
+        }
+
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+
+    request.push_str("\r\n");
+    (request, warnings)
+}
+
+fn send_request(target: &str, request: &str) -> io::Result<ReplayedResponse> {
+    let mut stream = TcpStream::connect(target)?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    stream.write_all(request.as_bytes())?;
+
+    // The server closes every connection after one response
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw)?;
+
+    parse_response(&raw).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Malformed response"))
+}
+
+fn parse_response(raw: &[u8]) -> Option<ReplayedResponse> {
+    let head_end = raw.windows(4).position(|window| window == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&raw[..head_end]).ok()?;
+    let mut lines = head.split("\r\n");
+
+    let status_line = lines.next()?.to_string();
+    let headers = lines.filter_map(|line| line.split_once(':')).map(|(name, value)| (name.to_string(), value.trim().to_string())).collect();
+
+    Some(ReplayedResponse {
+        status_line,
+        headers,
+        body: raw[head_end + 4..].to_vec()
+    })
+}
+
+// Only compares what the transcript captured, e.g. headers are skipped for summary level transcripts
+fn compare(exchange: &RecordedExchange, response: &ReplayedResponse, ignored: &[String]) -> Vec<String> {
+    let mut diffs = Vec::new();
+    let is_ignored = |name: &str| ignored.iter().any(|ignored| ignored.eq_ignore_ascii_case(name));
+
+    if let Some(status_line) = &exchange.status_line {
+        if *status_line != response.status_line {
+            diffs.push(format!("status: expected \"{}\", got \"{}\"", status_line, response.status_line));
+        }
+    }
+
+    for (name, value) in exchange.response_headers.iter().filter(|(name, _)| !is_ignored(name)) {
+        match response.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)) {
+            Some((_, actual)) if actual == value => {},
+            Some((_, actual)) => diffs.push(format!("header {}: expected \"{}\", got \"{}\"", name, value, actual)),
+            None => diffs.push(format!("header {}: missing, expected \"{}\"", name, value))
+        }
+    }
+
+    if !exchange.response_headers.is_empty() {
+        for (name, value) in response.headers.iter().filter(|(name, _)| !is_ignored(name)) {
+            if exchange.get_response_header(name).is_none() {
+                diffs.push(format!("header {}: unexpected \"{}\"", name, value));
+            }
+        }
+    }
+
+    if let Some(body) = &exchange.response_body {
+        // Text transcripts store bodies line by line, so line endings are normalised on both sides
+        let (expected, actual) = match &body.binary {
+            Some(binary) => (binary.clone(), response.body.clone()),
+            None => (
+                normalize_lines(body.text.as_deref().unwrap_or("")).into_bytes(),
+                normalize_lines(&String::from_utf8_lossy(&response.body)).into_bytes()
+            )
+        };
+
+        // Truncated captures can only be checked as a prefix
+        let matches = if body.truncated {
+            actual.starts_with(&expected)
+        } else {
+            actual == expected
+        };
+
+        if !matches {
+            let position = expected.iter().zip(actual.iter()).position(|(a, b)| a != b).unwrap_or(expected.len().min(actual.len()));
+            diffs.push(format!("body: differs at byte {} (expected {} bytes captured, got {} bytes)", position, expected.len(), actual.len()));
+        }
+    }
+
+    diffs
+}
+
+fn normalize_lines(text: &str) -> String {
+    text.lines().collect::<Vec<&str>>().join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::transcript_reader::read_transcript;
+
+    fn exchange(lines: &[&str]) -> RecordedExchange {
+        let contents: String = lines.iter().map(|line| format!("[10:00:00.000] [a] 127.0.0.1:1 {}\n", line)).collect();
+        read_transcript(&contents, NaiveDate::from_ymd_opt(2026, 10, 19).unwrap()).remove(0)
+    }
+
+    fn response(raw: &str) -> ReplayedResponse {
+        parse_response(raw.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn leaves_out_redacted_headers() {
+        let exchange = exchange(&[
+            "--> GET /a?token=[REDACTED]&b=1 HTTP/1.1",
+            "--> Host: x",
+            "--> Authorization: [REDACTED]",
+            "--> Cookie: sha256:0123456789abcdef",
+            "--> Accept: */*"
+        ]);
+
+        let (request, warnings) = build_request(&exchange, &[], DEFAULT_MASK);
+        assert_eq!(request, "GET /a?token=[REDACTED]&b=1 HTTP/1.1\r\nHost: x\r\nAccept: */*\r\n\r\n");
+        assert_eq!(warnings.len(), 3);
+        assert!(warnings[0].starts_with("query"));
+
+        let headers = [(String::from("cookie"), String::from("id=1"))];
+        let (request, warnings) = build_request(&exchange, &headers, "***");
+        assert_eq!(request, "GET /a?token=[REDACTED]&b=1 HTTP/1.1\r\nHost: x\r\nAuthorization: [REDACTED]\r\nAccept: */*\r\ncookie: id=1\r\n\r\n");
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn parses_responses() {
+        let response = response("HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nX-Empty:\r\n\r\n<p>\r\n</p>");
+        assert_eq!(response.status_line, "HTTP/1.1 200 OK");
+        assert_eq!(response.headers, vec![(String::from("Content-Type"), String::from("text/html")), (String::from("X-Empty"), String::new())]);
+        assert_eq!(response.body, b"<p>\r\n</p>");
+
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n").is_none());
+    }
+
+    #[test]
+    fn compares_truncated_bodies_as_a_prefix() {
+        let exchange = exchange(&[
+            "--> GET / HTTP/1.1",
+            "<-- HTTP/1.1 200 OK",
+            "<-- Content-Type: text/html",
+            "<-- ",
+            "<-- <html>",
+            "<--   <head>",
+            "<-- <30 more bytes not captured>"
+        ]);
+
+        let ignored = vec![String::from("Date")];
+        let matching = response("HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nDate: now\r\n\r\n<html>\r\n  <head>\r\n  </head>\r\n</html>\r\n");
+        assert!(compare(&exchange, &matching, &ignored).is_empty());
+
+        let differing = response("HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n<html>\r\n  <body>\r\n");
+        assert_eq!(compare(&exchange, &differing, &ignored), vec!["body: differs at byte 10 (expected 15 bytes captured, got 15 bytes)"]);
+    }
+
+    #[test]
+    fn compares_whole_bodies_exactly() {
+        let exchange = exchange(&["--> GET / HTTP/1.1", "<-- HTTP/1.1 200 OK", "<-- ", "<-- ok"]);
+
+        assert!(compare(&exchange, &response("HTTP/1.1 200 OK\r\n\r\nok"), &[]).is_empty());
+
+        let diffs = compare(&exchange, &response("HTTP/1.1 404 Not Found\r\nServer: x\r\n\r\nok!"), &[]);
+        assert_eq!(diffs, vec![
+            "status: expected \"HTTP/1.1 200 OK\", got \"HTTP/1.1 404 Not Found\"",
+            "body: differs at byte 2 (expected 2 bytes captured, got 3 bytes)"
+        ]);
+    }
+}
@@ -1,16 +1,29 @@
-use std::net::TcpStream;
+use std::io::BufRead;
+use std::sync::Arc;
 
+use chrono::{DateTime, Utc};
+
+use crate::config::ServerConfig;
+use crate::head_parser::{HeadEnd, HeadParser};
 use crate::http_util::split_method;
 use crate::http_error::{HttpError, http_errors};
-use crate::io_util::get_stream_name;
-use crate::headers::HttpHeaders;
-use crate::transcript::Transcript;
+use crate::io_util::{read_limited_line, LimitedLine};
+use crate::headers::{is_token, HttpHeaders};
+use crate::transcript::{Direction, Transcript, TranscriptEvent};
+use crate::util::{generate_id, read_line};
 
 pub struct HttpRequest {
     pub who: String,
+    pub id: String,
+    pub start: DateTime<Utc>,
     pub transcript: Transcript,
+    pub config: Arc<ServerConfig>,
     pub headers: HttpHeaders,
+    pub line: String,
+    pub method: String,
+    pub target: String,
     pub path: String,
+    pub query: Option<String>,
     pub resource_type: String,
     pub version: String,
     pub valid: bool,
@@ -18,12 +31,22 @@ pub struct HttpRequest {
 }
 
 impl HttpRequest {
-    pub fn new(stream: &TcpStream) -> Result<Self, HttpError> {
+    // `peer` as io_util::get_stream_name formats it
+    pub fn new(peer: String, config: Arc<ServerConfig>) -> Result<Self, HttpError> {
+        let id = generate_id();
+
         Ok(Self {
-            who: get_stream_name(stream),
-            transcript: Transcript::new(stream)?,
+            transcript: Transcript::new(&peer, &id, &config.transcript, Arc::clone(&config.redaction))?,
+            who: peer,
+            id,
+            start: Utc::now(),
+            config,
             headers: HttpHeaders::new(),
+            line: String::new(),
+            method: String::new(),
+            target: String::new(),
             path: String::new(),
+            query: None,
             resource_type: String::new(),
             version: String::new(),
             valid: false,
@@ -39,13 +62,28 @@ impl HttpRequest {
         }
     }
 
-    fn init_resource_type(&mut self) -> Result<(), HttpError> {
+    // Splits a request target (or a rewritten one) into path and query
+    pub fn set_target(&mut self, target: &str) {
+        match target.split_once('?') {
+            Some((path, query)) => {
+                self.path = path.to_string();
+                self.query = Some(query.to_string());
+            },
+            None => {
+                self.path = target.to_string();
+                self.query = None;
+            }
+        }
+    }
+
+    pub fn init_resource_type(&mut self) -> Result<(), HttpError> {
+        let file_name = self.get_file_name();
         let resource_type;
-        if self.path.ends_with(".html") {
+        if file_name.ends_with(".html") {
             resource_type = "text/html";
-        } else if self.path.ends_with(".png") {
+        } else if file_name.ends_with(".png") {
             resource_type = "image/png";
-        } else if self.path.ends_with(".ico") {
+        } else if file_name.ends_with(".ico") {
             resource_type = "image/x-icon";
         } else {
             return Err(http_errors::msg::forbidden("Invalid/Unaccepted resource type").set_info("Invalid MIME Type"));
@@ -56,8 +94,15 @@ impl HttpRequest {
         Ok(())
     }
 
-    pub fn init(&mut self, input: &String) -> Result<(), HttpError> {
-        let (method, path, version) = split_method(input.as_str()).ok_or_else(|| http_errors::msg::bad_request("Request did not match <method> <path> <version> format").set_info("Malformed request"))?;
+    pub fn init(&mut self, input: &str) -> Result<(), HttpError> {
+        self.line = input.to_string();
+        let (method, path, version) = split_method(input).ok_or_else(|| http_errors::msg::bad_request("Request did not match <method> <path> <version> format").set_info("Malformed request"))?;
+
+        if !is_token(&method) || path.is_empty() || path.bytes().any(|b| b <= b' ' || b == 0x7f) || version.contains(' ') {
+            return Err(http_errors::msg::bad_request("Request line contains invalid characters").set_info("Malformed request"));
+        }
+
+        self.method = method.clone();
 
         if method == "GET" {
             self.transcript.push("GET Request")?;
@@ -66,16 +111,14 @@ impl HttpRequest {
             return Err(http_errors::msg::not_implemented(format!("Method {} is not implemented", method).as_str()).set_info("Unknown HTTP Method"));
         }
 
-        self.path = if path.ends_with("/") {
-            format!("{}index.html", path)
-        } else {
-            path.clone()
-        };
-        
-        self.init_resource_type()?;
+        self.set_target(&path);
+        self.target = path;
+
+        let config = Arc::clone(&self.config);
+        self.transcript.resolve_level(&config.transcript, &self.path)?;
 
         if version != "HTTP/1.1" {
-            return Err(http_errors::msg::not_implemented(format!("HTTP version {} is unsupported", version).as_str()).set_info("Unsupported HTTP version"));
+            return Err(http_errors::msg::http_version_not_supported(format!("HTTP version {} is unsupported", version).as_str()).set_info("Unsupported HTTP version"));
         } else {
             self.version = version;
         }
@@ -86,13 +129,299 @@ impl HttpRequest {
         Ok(())
     }
 
-    pub fn feed(&mut self, input: &String) -> Result<(), HttpError> {
+    // Reads the request line and header section, enforcing the configured limits
+    pub fn read_head<R: BufRead>(&mut self, reader: &mut R) -> Result<(), HttpError> {
+        let config = Arc::clone(&self.config);
+        let limits = &config.limits;
+        let mut header_count = 0;
+        let mut header_size = 0;
+        let mut empty_lines = 0;
+
+        loop {
+            let limit = if self.is_init { limits.max_header_line } else { limits.max_request_line };
+
+            let line = match read_limited_line(reader, limit)? {
+                LimitedLine::Line(line) => line,
+                // A client closing before sending anything is not an error, one closing mid-head is
+                LimitedLine::End if !self.is_init => break,
+                LimitedLine::End => return Err(closed_mid_head()),
+                LimitedLine::TooLong => return Err(line_too_long(self.is_init, limit))
+            };
+
+            // Tolerate leading empty lines before the request line (RFC 9112 2.2)
+            if line.is_empty() {
+                if self.is_init {
+                    self.transcript.record(TranscriptEvent::HeaderEnd(Direction::Incoming))?;
+                    break;
+                }
+
+                // Bounded by the same limits as the head itself, so they cannot hold a connection forever
+                empty_lines += 1;
+                if empty_lines > limits.max_header_count || empty_lines * 2 > limits.max_request_line {
+                    return Err(too_many_empty_lines());
+                }
+
+                continue;
+            }
+
+            read_line(&self.transcript, line.as_str(), !self.is_init)?;
+
+            if self.is_init {
+                header_count += 1;
+                header_size += line.len() + 2;
+
+                if header_count > limits.max_header_count {
+                    return Err(too_many_header_fields(limits.max_header_count));
+                }
+
+                if header_size > limits.max_header_size {
+                    return Err(header_section_too_large(limits.max_header_size));
+                }
+            }
+
+            self.feed(&line)?;
+        }
+
+        if self.is_init {
+            self.adopt_request_id();
+            self.validate_head()?;
+        }
+
+        Ok(())
+    }
+
+    // Takes a head already split into lines by `HeadParser`, which checked the limits while reading it
+    pub fn read_parsed_head(&mut self, head: HeadParser) -> Result<(), HttpError> {
+        let (lines, end) = head.into_head();
+        for line in lines {
+            read_line(&self.transcript, line.as_str(), !self.is_init)?;
+            self.feed(&line)?;
+        }
+
+        match end {
+            HeadEnd::Complete => self.transcript.record(TranscriptEvent::HeaderEnd(Direction::Incoming))?,
+            HeadEnd::Invalid(err) => return Err(err),
+            HeadEnd::Closed if !self.is_init => return Ok(()),
+            HeadEnd::Closed => return Err(closed_mid_head())
+        }
+
+        self.adopt_request_id();
+        self.validate_head()
+    }
+
+    // A client or proxy supplied X-Request-Id replaces ours, so ids can be followed across services
+    fn adopt_request_id(&mut self) {
+        let Some(incoming) = self.headers.get("X-Request-Id").cloned() else {
+            return;
+        };
+
+        if !is_valid_request_id(&incoming) {
+            self.transcript.push("Ignoring invalid X-Request-Id").ok();
+            return;
+        }
+
+        self.transcript.push(format!("Request id {} replaced by X-Request-Id {}", self.id, incoming).as_str()).ok();
+        self.transcript.set_request_id(&incoming);
+        self.id = incoming;
+    }
+
+    fn validate_head(&self) -> Result<(), HttpError> {
+        if self.headers.get("Host").is_none() {
+            return Err(http_errors::msg::bad_request("HTTP/1.1 request is missing the Host header").set_info("Malformed request"));
+        }
+
+        // Either framing header may be honored by a proxy in front of us, so having both is a smuggling vector
+        if self.headers.get("Transfer-Encoding").is_some() {
+            if self.headers.get("Content-Length").is_some() {
+                return Err(http_errors::msg::bad_request("Request has both Transfer-Encoding and Content-Length").set_info("Malformed request"));
+            }
+
+            return Err(http_errors::msg::not_implemented("Transfer-Encoding is not supported").set_info("Unsupported request body"));
+        }
+
+        self.headers.content_length()?;
+
+        Ok(())
+    }
+
+    pub fn feed(&mut self, input: &str) -> Result<(), HttpError> {
         if !self.is_init {
-            return self.init(&input);
+            return self.init(input);
         }
 
-        self.headers.add_from_line(input.as_str())?;
+        self.headers.add_from_line(input)?;
 
         Ok(())
     }
 }
+
+// Head errors, shared by the blocking reader and `HeadParser`
+pub fn line_too_long(is_init: bool, limit: usize) -> HttpError {
+    if is_init {
+        http_errors::msg::request_header_fields_too_large(format!("Header line exceeds {} bytes", limit).as_str())
+    } else {
+        http_errors::msg::uri_too_long(format!("Request line exceeds {} bytes", limit).as_str())
+    }.set_info("Request too large")
+}
+
+pub fn too_many_header_fields(max: usize) -> HttpError {
+    http_errors::msg::request_header_fields_too_large(format!("More than {} header fields", max).as_str()).set_info("Request too large")
+}
+
+pub fn header_section_too_large(max: usize) -> HttpError {
+    http_errors::msg::request_header_fields_too_large(format!("Header section exceeds {} bytes", max).as_str()).set_info("Request too large")
+}
+
+pub fn too_many_empty_lines() -> HttpError {
+    http_errors::msg::bad_request("Too many empty lines before the request line").set_info("Malformed request")
+}
+
+pub fn invalid_utf8() -> HttpError {
+    http_errors::msg::bad_request("Request contains invalid UTF-8").set_info("Malformed request")
+}
+
+fn closed_mid_head() -> HttpError {
+    http_errors::msg::bad_request("Connection closed before the end of the header section").set_info("Malformed request")
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 128 && id.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::transcript::TranscriptLevel;
+
+    fn read(head: &str, configure: impl FnOnce(&mut ServerConfig)) -> (HttpRequest, Result<(), HttpError>) {
+        let mut config = ServerConfig::new();
+        config.transcript.level = TranscriptLevel::Off;
+        configure(&mut config);
+
+        let mut request = HttpRequest::new(String::from("127.0.0.1:1"), Arc::new(config)).unwrap();
+        let result = request.read_head(&mut Cursor::new(head.as_bytes()));
+        (request, result)
+    }
+
+    fn read_parsed(head: &str, configure: impl FnOnce(&mut ServerConfig)) -> (HttpRequest, Result<(), HttpError>) {
+        let mut config = ServerConfig::new();
+        config.transcript.level = TranscriptLevel::Off;
+        configure(&mut config);
+
+        let mut parser = HeadParser::new(&config.limits);
+        parser.push(head.as_bytes());
+
+        let mut request = HttpRequest::new(String::from("127.0.0.1:1"), Arc::new(config)).unwrap();
+        let result = request.read_parsed_head(parser);
+        (request, result)
+    }
+
+    #[test]
+    fn reads_complete_head() {
+        let (request, result) = read("GET /a?b HTTP/1.1\r\nHost: x\r\n\r\n", |_| {});
+        assert!(result.is_ok());
+        assert_eq!(request.path, "/a");
+        assert_eq!(request.query.as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn skips_a_few_leading_empty_lines() {
+        let (request, result) = read("\r\n\nGET / HTTP/1.1\r\nHost: x\r\n\r\n", |_| {});
+        assert!(result.is_ok());
+        assert!(request.is_init);
+    }
+
+    #[test]
+    fn rejects_too_many_leading_empty_lines() {
+        let head = format!("{}GET / HTTP/1.1\r\nHost: x\r\n\r\n", "\r\n".repeat(5));
+        let (_, result) = read(&head, |config| config.limits.max_header_count = 4);
+        assert_eq!(result.unwrap_err().code.get_code(), 400);
+
+        let (_, result) = read(&head, |config| config.limits.max_request_line = 8);
+        assert_eq!(result.unwrap_err().code.get_code(), 400);
+    }
+
+    #[test]
+    fn empty_connection_is_not_an_error() {
+        let (request, result) = read("", |_| {});
+        assert!(result.is_ok());
+        assert!(!request.is_init);
+    }
+
+    #[test]
+    fn rejects_eof_inside_head() {
+        let (_, result) = read("GET / HTTP/1.1\r\nHost: x\r\n", |_| {});
+        assert_eq!(result.unwrap_err().code.get_code(), 400);
+    }
+
+    #[test]
+    fn enforces_line_limits() {
+        let (_, result) = read("GET /0123456789 HTTP/1.1\r\nHost: x\r\n\r\n", |config| config.limits.max_request_line = 16);
+        assert_eq!(result.unwrap_err().code.get_code(), 414);
+
+        let (_, result) = read("GET / HTTP/1.1\r\nHost: 0123456789\r\n\r\n", |config| config.limits.max_header_line = 8);
+        assert_eq!(result.unwrap_err().code.get_code(), 431);
+    }
+
+    #[test]
+    fn enforces_header_section_limits() {
+        let head = "GET / HTTP/1.1\r\nHost: x\r\nA: 1\r\nB: 2\r\n\r\n";
+        let (_, result) = read(head, |config| config.limits.max_header_count = 2);
+        assert_eq!(result.unwrap_err().code.get_code(), 431);
+
+        let (_, result) = read(head, |config| config.limits.max_header_size = 16);
+        assert_eq!(result.unwrap_err().code.get_code(), 431);
+    }
+
+    #[test]
+    fn adopts_a_valid_request_id() {
+        let (request, result) = read("GET / HTTP/1.1\r\nHost: x\r\nX-Request-Id: edge-7f.3:a_b\r\n\r\n", |_| {});
+        assert!(result.is_ok());
+        assert_eq!(request.id, "edge-7f.3:a_b");
+    }
+
+    #[test]
+    fn keeps_its_own_id_over_an_invalid_one() {
+        for incoming in ["a/b", "<x>", &"a".repeat(129)] {
+            let (request, result) = read(&format!("GET / HTTP/1.1\r\nHost: x\r\nX-Request-Id: {}\r\n\r\n", incoming), |_| {});
+            assert!(result.is_ok());
+            assert_eq!(request.id.len(), 16, "{}", incoming);
+            assert!(request.id.bytes().all(|b| b.is_ascii_hexdigit()));
+        }
+    }
+
+    #[test]
+    fn generated_ids_differ() {
+        let (first, _) = read("GET / HTTP/1.1\r\nHost: x\r\n\r\n", |_| {});
+        let (second, _) = read("GET / HTTP/1.1\r\nHost: x\r\n\r\n", |_| {});
+        assert_ne!(first.id, second.id);
+    }
+
+    #[test]
+    fn parsed_heads_read_like_streamed_ones() {
+        type Configure = fn(&mut ServerConfig);
+        let heads: [(&str, Configure); 8] = [
+            ("\r\nGET /a?b HTTP/1.1\r\nHost: x\r\nX-Request-Id: edge-1\r\n\r\n", |_| {}),
+            ("", |_| {}),
+            ("\r\n", |_| {}),
+            ("GET / HTTP/1.1\r\nHost: x\r\n", |_| {}),
+            ("GET / HTTP/1.1\r\nHost: x\r\n\r\n", |config| config.limits.max_request_line = 8),
+            ("GET / HTTP/1.1\r\nHost: 0123456789\r\n\r\n", |config| config.limits.max_header_line = 8),
+            ("GET / HTTP/1.1\r\nHost: x\r\nA: 1\r\n\r\n", |config| config.limits.max_header_count = 1),
+            ("GET / HTTP/1.1\r\nHost: x\r\nA: 1\r\n\r\n", |config| config.limits.max_header_size = 12)
+        ];
+
+        for (head, configure) in heads {
+            let (streamed, streamed_result) = read(head, configure);
+            let (parsed, parsed_result) = read_parsed(head, configure);
+
+            assert_eq!(parsed_result.err().map(|e| e.code.get_code()), streamed_result.err().map(|e| e.code.get_code()), "{:?}", head);
+            assert_eq!(parsed.is_init, streamed.is_init, "{:?}", head);
+            assert_eq!(parsed.path, streamed.path, "{:?}", head);
+            assert_eq!(parsed.query, streamed.query, "{:?}", head);
+            assert_eq!(parsed.id == streamed.id, head.contains("X-Request-Id"), "{:?}", head);
+        }
+    }
+}
@@ -1,33 +1,45 @@
-use std::net::TcpStream;
+use std::io::Write;
+use std::sync::Arc;
 
+use crate::access_log::{self, AccessLogEntry};
+use crate::error_pages::{render_error, ErrorPage};
 use crate::headers::HttpHeaders;
+use crate::metrics;
 use crate::request::HttpRequest;
 use crate::http_error::{ http_errors, HttpCode, HttpError };
-use crate::io_util::{ write_body, write_body_data, write_line };
+use crate::io_util::{ write_body, write_body_data, write_head_end, write_header, write_mapped_chunks, write_mapped_head, write_status };
+use crate::mapped_file::MappedFile;
+use crate::str_util::html_escape;
 
 pub enum HttpDataType {
     Text(String),
-    Binary(Vec<u8>)
+    Binary(Vec<u8>),
+    // Cached static files, shared with the cache and other responses sending the same file
+    Shared(Arc<[u8]>),
+    // Large static files, shared with other responses sending the same file
+    Mapped(Arc<MappedFile>)
 }
 
 pub enum HttpResponseData {
     Content(HttpDataType),
-    Error(String),
+    Error(ErrorPage),
+    Redirect(String),
     None
 }
 
-pub struct HttpResponse<'a> {
+// Written to the client's TcpStream, or to a buffer the async core sends on
+pub struct HttpResponse<W: Write> {
     pub request: HttpRequest,
     pub headers: HttpHeaders,
-    stream: &'a mut TcpStream,
+    stream: W,
     pub error: Option<HttpError>,
     pub code: HttpCode,
     pub data: HttpResponseData
 }
 
 #[allow(unused)]
-impl<'a> HttpResponse<'a> {
-    pub fn new(request: HttpRequest, stream: &'a mut TcpStream) -> Self {
+impl<W: Write> HttpResponse<W> {
+    pub fn new(request: HttpRequest, stream: W) -> Self {
         Self {
             request,
             headers: HttpHeaders::new(),
@@ -38,14 +50,14 @@ impl<'a> HttpResponse<'a> {
         }
     }
 
-    fn get_error_content(code: HttpCode) -> String {
-        format!("<html><body><h1>{}</h1></body></html>", code.get_desc())
+    fn get_error_content(&self, error: &HttpError) -> ErrorPage {
+        render_error(&self.request, error)
     }
 
     pub fn set_error(&mut self, error: HttpError) {
         self.code = error.code.clone();
-        self.error = Some(error.clone());
-        self.data = HttpResponseData::Error(Self::get_error_content(error.code));
+        self.data = HttpResponseData::Error(self.get_error_content(&error));
+        self.error = Some(error);
     }
 
     pub fn set_code(&mut self, code: HttpCode) {
@@ -58,12 +70,31 @@ impl<'a> HttpResponse<'a> {
         }
     }
 
+    pub fn redirect(&mut self, code: HttpCode, location: &str) -> Result<(), HttpError> {
+        if !code.is_redirect() {
+            return Err(http_errors::msg::internal_server_error(format!("Cannot redirect with non-redirect status {}", code).as_str()));
+        }
+
+        self.headers.set("Location", location);
+        self.error = None;
+        self.code = code;
+        self.data = HttpResponseData::Redirect(location.to_string());
+
+        Ok(())
+    }
+
     pub fn set_string_response(&mut self, code: HttpCode, content: String) -> Result<(), HttpError> {
         if code.is_error() {
-            self.code = code.clone();
-            self.error = Some(HttpError::new(code));
-            self.data = HttpResponseData::Error(content);
-            
+            // Rendered like any other error for the id header and transcript entry, only the body is the caller's
+            let error = HttpError::new(code);
+            let mut page = self.get_error_content(&error);
+            page.content_type = String::from("text/html");
+            page.body = content;
+
+            self.code = error.code.clone();
+            self.data = HttpResponseData::Error(page);
+            self.error = Some(error);
+
             Ok(())
         } else {
             self.error = None;
@@ -86,45 +117,125 @@ impl<'a> HttpResponse<'a> {
         }
     }
 
-    pub fn flush(&mut self) -> std::io::Result<()> {
-        let ts = &mut self.request.transcript;
+    pub fn set_shared_response(&mut self, code: HttpCode, data: Arc<[u8]>) -> Result<(), HttpError> {
+        if !code.is_error() {
+            self.error = None;
+            self.code = code;
+            self.data = HttpResponseData::Content(HttpDataType::Shared(data));
 
-        write_line(ts, self.stream, format!("HTTP/1.1 {}", self.code).as_str()).map_err(HttpError::convert_to_direct)?;
-        if let Some(http_err) = &self.error {
-            write_line(ts, self.stream, format!("X-Error-Info: {}", http_err.get_error_msg()).as_str()).map_err(HttpError::convert_to_direct)?;
-            ts.push(format!("Full Error Info: {}", http_err).as_str()).map_err(HttpError::convert_to_direct)?;
+            Ok(())
+        } else {
+            Err(http_errors::msg::internal_server_error("Failed to set shared response"))
         }
+    }
 
+    pub fn set_mapped_response(&mut self, code: HttpCode, file: Arc<MappedFile>) -> Result<(), HttpError> {
+        if !code.is_error() {
+            self.error = None;
+            self.code = code;
+            self.data = HttpResponseData::Content(HttpDataType::Mapped(file));
+
+            Ok(())
+        } else {
+            Err(http_errors::msg::internal_server_error("Failed to set mapped response"))
+        }
+    }
+
+    pub fn get_stream(&mut self) -> &mut W {
+        &mut self.stream
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        let (body_bytes, mapped) = self.flush_head()?;
+        if let Some(file) = mapped {
+            write_mapped_chunks(&mut self.stream, &file).map_err(HttpError::convert_to_direct)?;
+        }
+
+        self.record(body_bytes);
+        Ok(())
+    }
+
+    // Writes the response except for the contents of a mapped body, that file is returned for the
+    // caller to send along with the body size for `record`
+    pub fn flush_head(&mut self) -> std::io::Result<(usize, Option<Arc<MappedFile>>)> {
+        let ts = &self.request.transcript;
+        let mut mapped = None;
+
+        write_status(ts, &mut self.stream, &self.code).map_err(HttpError::convert_to_direct)?;
+        write_header(ts, &mut self.stream, "X-Request-Id", &self.request.id).map_err(HttpError::convert_to_direct)?;
         for (key, value) in self.headers.iter() {
             if HttpHeaders::is_restricted_header(key) {
                 continue;
             }
 
-            write_line(ts, self.stream, format!("{}: {}", key, value).as_str()).map_err(HttpError::convert_to_direct)?;
+            write_header(ts, &mut self.stream, key, value).map_err(HttpError::convert_to_direct)?;
         }
 
-        match &self.data {
+        let body_bytes = match &self.data {
             HttpResponseData::Content(content) => {
-                write_line(ts, self.stream, format!("Content-Type: {}", self.request.resource_type).as_str()).map_err(HttpError::convert_to_direct)?;
+                write_header(ts, &mut self.stream, "Content-Type", &self.request.resource_type).map_err(HttpError::convert_to_direct)?;
                 match content {
                     HttpDataType::Binary(data) => {
                         write_body_data(ts, &mut self.stream, data).map_err(HttpError::convert_to_direct)?;
+                        data.len()
                     },
                     HttpDataType::Text(text) => {
-                        write_body(ts, self.stream,text.as_str()).map_err(HttpError::convert_to_direct)?;
+                        write_body(ts, &mut self.stream, text.as_str()).map_err(HttpError::convert_to_direct)?;
+                        text.len()
+                    },
+                    HttpDataType::Shared(data) => {
+                        // Pages are transcribed as text like any other text response, everything else as binary
+                        match std::str::from_utf8(data).ok().filter(|_| self.request.resource_type.starts_with("text/")) {
+                            Some(text) => write_body(ts, &mut self.stream, text),
+                            None => write_body_data(ts, &mut self.stream, data)
+                        }.map_err(HttpError::convert_to_direct)?;
+                        data.len()
+                    },
+                    HttpDataType::Mapped(file) => {
+                        write_mapped_head(ts, &mut self.stream, file).map_err(HttpError::convert_to_direct)?;
+                        mapped = Some(Arc::clone(file));
+                        file.len as usize
                     }
                 }
             },
-            HttpResponseData::Error(content) => {
-                write_line(ts, self.stream, "Content-Type: text/html").map_err(HttpError::convert_to_direct)?;
-                write_body(ts, self.stream, content.as_str()).map_err(HttpError::convert_to_direct)?;
+            HttpResponseData::Error(page) => {
+                for (key, value) in &page.headers {
+                    write_header(ts, &mut self.stream, key, value).map_err(HttpError::convert_to_direct)?;
+                }
+
+                write_header(ts, &mut self.stream, "Content-Type", &page.content_type).map_err(HttpError::convert_to_direct)?;
+                write_body(ts, &mut self.stream, page.body.as_str()).map_err(HttpError::convert_to_direct)?;
+                page.body.len()
+            },
+            HttpResponseData::Redirect(location) => {
+                let location = html_escape(location);
+                let content = format!("<html><body><h1>{}</h1><a href=\"{}\">{}</a></body></html>", self.code.get_desc(), location, location);
+                write_header(ts, &mut self.stream, "Content-Type", "text/html").map_err(HttpError::convert_to_direct)?;
+                write_body(ts, &mut self.stream, content.as_str()).map_err(HttpError::convert_to_direct)?;
+                content.len()
             },
             HttpResponseData::None => {
-                write_line(ts, self.stream, "Content-Type: text/html").map_err(HttpError::convert_to_direct)?;
-                write_body(ts, self.stream, Self::get_error_content(HttpCode::E501).as_str()).map_err(HttpError::convert_to_direct)?;
+                // 1xx, 204 and 304 responses never carry a body (RFC 9110 6.4.1)
+                if self.code.allows_body() {
+                    write_header(ts, &mut self.stream, "Content-Length", "0").map_err(HttpError::convert_to_direct)?;
+                }
+
+                write_head_end(ts, &mut self.stream).map_err(HttpError::convert_to_direct)?;
+                0
             }
-        }
+        };
 
-        Ok(())
+        Ok((body_bytes, mapped))
     }
-}
\ No newline at end of file
+
+    // Once the whole response was sent
+    pub fn record(&self, body_bytes: usize) {
+        access_log::record(&AccessLogEntry {
+            request: &self.request,
+            status: self.code.get_code(),
+            body_bytes: body_bytes as u64,
+            response_headers: Some(&self.headers)
+        });
+        metrics::record_request(&self.request, self.code.get_code());
+    }
+}
@@ -0,0 +1,197 @@
+use regex::Regex;
+
+use crate::http_error::HttpCode;
+
+pub enum RuleMatch {
+    Exact(String),
+    Prefix(String),
+    Regex(Regex)
+}
+
+pub enum RuleAction {
+    Redirect(HttpCode),
+    Rewrite
+}
+
+pub struct Rule {
+    pub matcher: RuleMatch,
+    pub target: String,
+    pub action: RuleAction
+}
+
+pub enum RuleOutcome {
+    Redirect(HttpCode, String),
+    Rewrite(String),
+    None
+}
+
+impl RuleMatch {
+    pub fn parse(kind: &str, pattern: &str) -> Result<Self, String> {
+        match kind {
+            "exact" => Ok(RuleMatch::Exact(pattern.to_string())),
+            "prefix" => Ok(RuleMatch::Prefix(pattern.to_string())),
+            "regex" => Ok(RuleMatch::Regex(Regex::new(pattern).map_err(|e| format!("Invalid regex \"{}\": {}", pattern, e))?)),
+            kind => Err(format!("Unknown rule match type \"{}\"", kind))
+        }
+    }
+
+    // `<exact|prefix|regex> <pattern>`, as written in the config
+    pub fn describe(&self) -> String {
+        match self {
+            RuleMatch::Exact(pattern) => format!("exact {}", pattern),
+            RuleMatch::Prefix(pattern) => format!("prefix {}", pattern),
+            RuleMatch::Regex(regex) => format!("regex {}", regex.as_str())
+        }
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        match self {
+            RuleMatch::Exact(pattern) => path == pattern,
+            RuleMatch::Prefix(pattern) => path.starts_with(pattern.as_str()),
+            RuleMatch::Regex(regex) => regex.is_match(path)
+        }
+    }
+}
+
+impl Rule {
+    // Parses `<exact|prefix|regex> <pattern> <target> [code]`, the code only being valid for redirects
+    pub fn parse(action: &str, value: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = value.split_whitespace().collect();
+        if parts.len() < 3 || parts.len() > 4 {
+            return Err(format!("Rule \"{}\" did not match <exact|prefix|regex> <pattern> <target> [code]", value));
+        }
+
+        let matcher = RuleMatch::parse(parts[0], parts[1])?;
+
+        let action = match action {
+            "redirect" => {
+                let code = match parts.get(3) {
+                    Some(code) => {
+                        let code = code.parse::<i32>().map_err(|_| format!("\"{}\" is not a status code", code))?;
+                        HttpCode::from(code).map_err(|e| e.to_string())?
+                    },
+                    None => HttpCode::E302
+                };
+
+                if !code.is_redirect() {
+                    return Err(format!("Redirect rule uses status {}, expected 301, 302, 303, 307 or 308", code));
+                }
+
+                RuleAction::Redirect(code)
+            },
+            "rewrite" => {
+                if parts.len() > 3 {
+                    return Err(String::from("Rewrite rules do not take a status code"));
+                }
+
+                RuleAction::Rewrite
+            },
+            other => return Err(format!("Unknown rule action \"{}\"", other))
+        };
+
+        Ok(Self {
+            matcher,
+            target: parts[2].to_string(),
+            action
+        })
+    }
+
+    pub fn describe(&self) -> String {
+        match &self.action {
+            RuleAction::Redirect(code) => format!("redirect {} {} {}", self.matcher.describe(), self.target, code.get_code()),
+            RuleAction::Rewrite => format!("rewrite {} {}", self.matcher.describe(), self.target)
+        }
+    }
+
+    pub fn apply(&self, path: &str) -> Option<String> {
+        match &self.matcher {
+            RuleMatch::Exact(pattern) => {
+                (path == pattern).then(|| self.target.clone())
+            },
+            RuleMatch::Prefix(pattern) => {
+                path.strip_prefix(pattern.as_str()).map(|rest| format!("{}{}", self.target, rest))
+            },
+            RuleMatch::Regex(regex) => {
+                regex.captures(path).map(|captures| {
+                    let mut result = String::new();
+                    captures.expand(&self.target, &mut result);
+                    result
+                })
+            }
+        }
+    }
+}
+
+// Rules are evaluated in config order and the first match wins
+pub fn evaluate(rules: &[Rule], path: &str) -> RuleOutcome {
+    for rule in rules {
+        if let Some(target) = rule.apply(path) {
+            return match &rule.action {
+                RuleAction::Redirect(code) => RuleOutcome::Redirect(code.clone(), target),
+                RuleAction::Rewrite => RuleOutcome::Rewrite(target)
+            };
+        }
+    }
+
+    RuleOutcome::None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redirect_accepts_only_location_codes() {
+        for code in ["301", "302", "303", "307", "308"] {
+            assert!(Rule::parse("redirect", &format!("exact /a /b {}", code)).is_ok());
+        }
+
+        for code in ["300", "304", "305", "306", "200", "404"] {
+            assert!(Rule::parse("redirect", &format!("exact /a /b {}", code)).is_err());
+        }
+    }
+
+    fn rules(lines: &[(&str, &str)]) -> Vec<Rule> {
+        lines.iter().map(|(action, value)| Rule::parse(action, value).unwrap()).collect()
+    }
+
+    #[test]
+    fn rejects_malformed_rules() {
+        assert!(Rule::parse("redirect", "exact /a").is_err());
+        assert!(Rule::parse("redirect", "glob /a /b").is_err());
+        assert!(Rule::parse("rewrite", "exact /a /b 301").is_err());
+        assert!(Rule::parse("forward", "exact /a /b").is_err());
+        assert!(Rule::parse("rewrite", "regex ( /b").is_err());
+    }
+
+    #[test]
+    fn matches_exact_prefix_and_regex() {
+        let rules = rules(&[
+            ("redirect", "exact /old /new 301"),
+            ("rewrite", "prefix /static/ /assets/"),
+            ("rewrite", "regex ^/user/([0-9]+)$ /profile.html?id=$1")
+        ]);
+
+        assert!(matches!(evaluate(&rules, "/old"), RuleOutcome::Redirect(HttpCode::E301, location) if location == "/new"));
+        assert!(matches!(evaluate(&rules, "/old/x"), RuleOutcome::None));
+        assert!(matches!(evaluate(&rules, "/static/a.png"), RuleOutcome::Rewrite(target) if target == "/assets/a.png"));
+        assert!(matches!(evaluate(&rules, "/user/42"), RuleOutcome::Rewrite(target) if target == "/profile.html?id=42"));
+        assert!(matches!(evaluate(&rules, "/user/abc"), RuleOutcome::None));
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = rules(&[
+            ("rewrite", "prefix /a /first"),
+            ("redirect", "exact /a /second")
+        ]);
+
+        assert!(matches!(evaluate(&rules, "/a"), RuleOutcome::Rewrite(target) if target == "/first"));
+    }
+
+    #[test]
+    fn redirect_defaults_to_302() {
+        let rules = rules(&[("redirect", "exact /a /b")]);
+        assert!(matches!(evaluate(&rules, "/a"), RuleOutcome::Redirect(HttpCode::E302, _)));
+    }
+}
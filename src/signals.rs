@@ -0,0 +1,40 @@
+use std::{process, thread};
+use std::time::Duration;
+
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+use crate::admin;
+use crate::config;
+use crate::http_error::HttpError;
+use crate::transcript_writer;
+
+// SIGHUP reloads the config, SIGINT and SIGTERM flush queued transcript output and exit
+pub fn install_signal_handlers() -> Result<(), HttpError> {
+    let mut signals = Signals::new([SIGHUP, SIGINT, SIGTERM]).map_err(|e| HttpError::convert_from(e, Some("Failed to install signal handler")))?;
+
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            if signal == SIGHUP {
+                reload();
+                continue;
+            }
+
+            println!("Received signal {}, shutting down", signal);
+            admin::set_accepting(false);
+            transcript_writer::shutdown(Duration::from_secs(5));
+            process::exit(0);
+        }
+    });
+
+    Ok(())
+}
+
+// A broken config file keeps the server running on the previous one
+fn reload() {
+    match config::reload() {
+        Ok(restart) if restart.is_empty() => println!("Received SIGHUP, config reloaded"),
+        Ok(restart) => println!("Received SIGHUP, config reloaded. Changes to {} need a restart", restart.join(", ")),
+        Err(e) => eprintln!("Received SIGHUP, config not reloaded: {}", e.get_chain_msg())
+    }
+}
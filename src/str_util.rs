@@ -5,23 +5,23 @@ pub struct Builder {
 }
 
 impl Builder {
-    pub fn new(delimiter: &String) -> Self {
+    pub fn new(delimiter: &str) -> Self {
         Self {
             result: String::new(),
             delimiter: delimiter.to_owned()
         }
     }
 
-    pub fn prepend(&mut self, value: &String) -> &mut Self {
+    pub fn prepend(&mut self, value: &str) -> &mut Self {
         if !self.result.is_empty() {
             self.result.insert_str(0, self.delimiter.as_str());
         }
 
-        self.result.insert_str(0, value.as_str());
+        self.result.insert_str(0, value);
         self
     }
 
-    pub fn append(&mut self, value: &String) -> &mut Self {
+    pub fn append(&mut self, value: &str) -> &mut Self {
         if !self.result.is_empty() {
             self.result.push_str(&self.delimiter);
         }
@@ -49,4 +49,63 @@ impl Builder {
     pub fn is_empty(&self) -> bool {
         self.result.is_empty()
     }
-}
\ No newline at end of file
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut result = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                result.push(BASE64_ALPHABET[((n >> (18 - i * 6)) & 0x3f) as usize] as char);
+            } else {
+                result.push('=');
+            }
+        }
+    }
+
+    result
+}
+
+pub fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    let input = input.trim_end_matches('=');
+    let mut result = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for c in input.bytes() {
+        let value = BASE64_ALPHABET.iter().position(|&a| a == c)? as u32;
+        buffer = (buffer << 6) | value;
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            result.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    Some(result)
+}
+
+pub fn html_escape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&#39;"),
+            _ => result.push(c)
+        }
+    }
+
+    result
+}
@@ -1,8 +1,65 @@
-use std::{fs::{create_dir_all, File}, io::Write, net::TcpStream, path::PathBuf};
+use std::{cell::{Cell, OnceCell, RefCell}, fs::create_dir_all, path::PathBuf, sync::{Arc, Mutex}};
 
-use chrono::{DateTime, Duration, Utc};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
 
-use crate::{http_error::{http_errors, HttpError}, io_util, str_util::Builder, util::{get_time_str, get_time_str_from}};
+use crate::{config::TranscriptConfig, http_error::HttpError, json::JsonValue, log_rotation::RotatingFile, metrics, redaction::Redaction, rules::RuleMatch, transcript_sink::SinkKind, transcript_writer, str_util::Builder, util::{generate_id, get_time_str, get_time_str_from}};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TranscriptFormat {
+    Text,
+    // One JSON object per event (JSON Lines), for log pipelines
+    Json
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TranscriptLayout {
+    // <dir>/<peer>_<time>.log, one file per connection
+    PerConnection,
+    // <dir>/<yyyy-mm-dd>/<peer>_<time>.log
+    Daily,
+    // <dir>/transcript.log, shared by all connections and rotated by size or age
+    Shared
+}
+
+// Each level records everything the previous one does
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum TranscriptLevel {
+    Off,
+    // Request line, status, messages and the connection summary
+    Summary,
+    Headers,
+    Full
+}
+
+// Overrides the transcript level for matching request paths, the first match wins
+pub struct TranscriptRoute {
+    pub matcher: RuleMatch,
+    pub level: TranscriptLevel
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    Incoming,
+    Outgoing
+}
+
+pub enum BodyData<'a> {
+    Text(&'a str),
+    Binary(&'a [u8]),
+    // The start of a binary body that is never in memory as a whole, and the length of all of it
+    Prefix(&'a [u8], u64)
+}
+
+pub enum TranscriptEvent<'a> {
+    Open,
+    Message(&'a str),
+    RequestLine(&'a str),
+    Status(i32, &'a str),
+    Header(Direction, &'a str, &'a str),
+    HeaderEnd(Direction),
+    Body(Direction, BodyData<'a>),
+    Close(Duration)
+}
 
 struct TranscriptPrefix {
     pub prefix: String,
@@ -10,33 +67,274 @@ struct TranscriptPrefix {
 }
 
 pub struct Transcript {
-    file: File,
+    // Output is written by the background writer, see transcript_writer.
+    // Sinks only hear of a connection once something is written, so connections at level off leave no files behind.
+    handle: OnceCell<u64>,
+    dir: PathBuf,
+    file_name: String,
+    // Lines recorded before the request path picked a level, with the level each one needs
+    pending: RefCell<Option<Vec<(TranscriptLevel, String)>>>,
+    level: Cell<TranscriptLevel>,
+    default_level: TranscriptLevel,
+    max_body: usize,
+    redaction: Arc<Redaction>,
+    format: TranscriptFormat,
+    connection_id: String,
+    request_id: String,
+    peer: String,
     prefix: Option<Box<TranscriptPrefix>>,
-    start: DateTime<Utc>
+    start: DateTime<Utc>,
+    bytes_in: Cell<u64>,
+    bytes_out: Cell<u64>
+}
+
+impl TranscriptFormat {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "text" => Ok(TranscriptFormat::Text),
+            "json" => Ok(TranscriptFormat::Json),
+            _ => Err(format!("Unknown transcript format \"{}\", expected text or json", value))
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            TranscriptFormat::Text => "text",
+            TranscriptFormat::Json => "json"
+        }
+    }
+
+    pub fn get_extension(&self) -> &'static str {
+        match self {
+            TranscriptFormat::Text => "log",
+            TranscriptFormat::Json => "jsonl"
+        }
+    }
+}
+
+impl TranscriptLevel {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "off" => Ok(TranscriptLevel::Off),
+            "summary" => Ok(TranscriptLevel::Summary),
+            "headers" => Ok(TranscriptLevel::Headers),
+            "full" => Ok(TranscriptLevel::Full),
+            _ => Err(format!("Unknown transcript level \"{}\", expected off, summary, headers or full", value))
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            TranscriptLevel::Off => "off",
+            TranscriptLevel::Summary => "summary",
+            TranscriptLevel::Headers => "headers",
+            TranscriptLevel::Full => "full"
+        }
+    }
+}
+
+impl TranscriptRoute {
+    // Parses `<exact|prefix|regex> <pattern> <level>`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = value.split_whitespace().collect();
+        if parts.len() != 3 {
+            return Err(format!("Transcript route \"{}\" did not match <exact|prefix|regex> <pattern> <level>", value));
+        }
+
+        Ok(Self {
+            matcher: RuleMatch::parse(parts[0], parts[1])?,
+            level: TranscriptLevel::parse(parts[2])?
+        })
+    }
+}
+
+impl TranscriptLayout {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "per_connection" => Ok(TranscriptLayout::PerConnection),
+            "daily" => Ok(TranscriptLayout::Daily),
+            "shared" => Ok(TranscriptLayout::Shared),
+            _ => Err(format!("Unknown transcript layout \"{}\", expected per_connection, daily or shared", value))
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            TranscriptLayout::PerConnection => "per_connection",
+            TranscriptLayout::Daily => "daily",
+            TranscriptLayout::Shared => "shared"
+        }
+    }
+}
+
+static SHARED_FILE: Mutex<Option<Arc<Mutex<RotatingFile>>>> = Mutex::new(None);
+
+// Opens the shared transcript file when the shared layout is configured for the file sink
+pub fn init(config: &TranscriptConfig) -> Result<(), HttpError> {
+    let shared = if config.layout == TranscriptLayout::Shared && config.sinks.contains(&SinkKind::File) {
+        create_dir_all(&config.dir).map_err(|e| HttpError::convert_from(e, Some("Failed to create logs directory")))?;
+        let path = config.dir.join(format!("transcript.{}", config.format.get_extension()));
+        Some(Arc::new(Mutex::new(RotatingFile::open(&path, config)?)))
+    } else {
+        None
+    };
+
+    *SHARED_FILE.lock().unwrap_or_else(|e| e.into_inner()) = shared;
+    Ok(())
+}
+
+// Transcript files of a connection start with its escaped peer name
+pub fn get_file_prefix(stream_name: &str) -> String {
+    stream_name.replace(".", "_").replace(":", "_")
+}
+
+pub fn get_shared_file() -> Option<Arc<Mutex<RotatingFile>>> {
+    SHARED_FILE.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+pub fn reopen_shared_file() -> Result<(), HttpError> {
+    match get_shared_file() {
+        Some(shared) => shared.lock().unwrap_or_else(|e| e.into_inner()).reopen()
+            .map_err(|e| HttpError::convert_from(e, Some("Failed to reopen shared transcript file"))),
+        None => Ok(())
+    }
+}
+
+// Applies reloaded rotation limits to the shared file, its path stays until a restart
+pub fn update_shared_file(config: &TranscriptConfig) {
+    if let Some(shared) = get_shared_file() {
+        shared.lock().unwrap_or_else(|e| e.into_inner()).set_limits(config);
+    }
 }
 
+pub fn get_partition_name(time: &DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d").to_string()
+}
+
+impl Direction {
+    pub fn get_prefix(&self) -> &'static str {
+        match self {
+            Direction::Incoming => "-->",
+            Direction::Outgoing => "<--"
+        }
+    }
+}
+
+impl TranscriptEvent<'_> {
+    pub fn get_name(&self) -> &str {
+        match self {
+            TranscriptEvent::Open => "open",
+            TranscriptEvent::Message(_) => "message",
+            TranscriptEvent::RequestLine(_) => "request_line",
+            TranscriptEvent::Status(_, _) => "status",
+            TranscriptEvent::Header(_, _, _) => "header",
+            TranscriptEvent::HeaderEnd(_) => "header_end",
+            TranscriptEvent::Body(_, _) => "body",
+            TranscriptEvent::Close(_) => "close"
+        }
+    }
+
+    pub fn get_direction(&self) -> Option<Direction> {
+        match self {
+            TranscriptEvent::RequestLine(_) => Some(Direction::Incoming),
+            TranscriptEvent::Status(_, _) => Some(Direction::Outgoing),
+            TranscriptEvent::Header(direction, _, _) | TranscriptEvent::HeaderEnd(direction) | TranscriptEvent::Body(direction, _) => Some(*direction),
+            _ => None
+        }
+    }
+
+    // The lowest level that records this event
+    fn get_level(&self) -> TranscriptLevel {
+        match self {
+            TranscriptEvent::Header(_, _, _) | TranscriptEvent::HeaderEnd(_) => TranscriptLevel::Headers,
+            TranscriptEvent::Body(_, _) => TranscriptLevel::Full,
+            _ => TranscriptLevel::Summary
+        }
+    }
+
+    // Bytes this event puts on the wire, head lines include their CRLF
+    fn get_wire_size(&self) -> u64 {
+        match self {
+            TranscriptEvent::RequestLine(line) | TranscriptEvent::Status(_, line) => line.len() as u64 + 2,
+            TranscriptEvent::Header(_, name, value) => (name.len() + value.len()) as u64 + 4,
+            TranscriptEvent::HeaderEnd(_) => 2,
+            TranscriptEvent::Body(_, BodyData::Text(text)) => text.len() as u64,
+            TranscriptEvent::Body(_, BodyData::Binary(data)) => data.len() as u64,
+            TranscriptEvent::Body(_, BodyData::Prefix(_, len)) => *len,
+            _ => 0
+        }
+    }
+}
+
+#[allow(unused)]
 impl Transcript {
-    pub fn new(stream: &TcpStream) -> Result<Self, HttpError> {
-        let stream_name = io_util::get_stream_name(stream);
-        // Escape name
-        let stream_file_name = stream_name.replace(".", "_").replace(":", "_");
+    pub fn new(stream_name: &str, request_id: &str, config: &TranscriptConfig, redaction: Arc<Redaction>) -> Result<Self, HttpError> {
+        let stream_file_name = get_file_prefix(stream_name);
         
         let current_time = Utc::now();
-        let current_time_int = current_time.timestamp() as i32;
+        let dir = match config.layout {
+            TranscriptLayout::Daily => config.dir.join(get_partition_name(&current_time)),
+            _ => config.dir.clone()
+        };
+
+        // Routes may raise the level once the request path is known, so hold lines back until then
+        let pending = (!config.routes.is_empty()).then(Vec::new);
+        let level = if pending.is_some() { TranscriptLevel::Full } else { config.level };
 
         let transcript = Self {
-            file: Self::try_get_file_name(&stream_file_name, current_time_int)?,
+            handle: OnceCell::new(),
+            dir,
+            file_name: stream_file_name,
+            pending: RefCell::new(pending),
+            level: Cell::new(level),
+            default_level: config.level,
+            max_body: config.max_body,
+            redaction,
+            format: config.format,
+            connection_id: generate_id(),
+            request_id: request_id.to_string(),
+            peer: stream_name.to_owned(),
             prefix: Some(Box::new(TranscriptPrefix { prefix: stream_name.to_owned(), prev: None })),
-            start: current_time
+            start: current_time,
+            bytes_in: Cell::new(0),
+            bytes_out: Cell::new(0)
         };
 
-        transcript.push("New transcript for HTTP connection")?;
-        transcript.push(format!(" at {} UTC", get_time_str_from(&transcript.start, true, true)).as_str())?;
-        transcript.push(format!(" by {}", stream_name).as_str())?;
+        transcript.record(TranscriptEvent::Open)?;
 
         Ok(transcript)
     }
 
+    // Picks the level for this request from the configured routes, then writes what was held back
+    pub fn resolve_level(&self, config: &TranscriptConfig, path: &str) -> Result<(), HttpError> {
+        let level = config.routes.iter()
+            .find(|route| route.matcher.matches(path))
+            .map(|route| route.level)
+            .unwrap_or(config.level);
+
+        self.set_level(level)
+    }
+
+    fn set_level(&self, level: TranscriptLevel) -> Result<(), HttpError> {
+        self.level.set(level);
+
+        let Some(pending) = self.pending.borrow_mut().take() else {
+            return Ok(());
+        };
+
+        for (required, line) in pending {
+            if required <= level {
+                self.write_out(line)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn set_request_id(&mut self, request_id: &str) {
+        self.request_id = request_id.to_string();
+    }
+
     pub fn with_prefix<F>(&mut self, prefix: &str, mut func: F) -> Result<(), HttpError> where F: FnMut(&Transcript) -> Result<(), HttpError> {
         self.add_prefix(prefix);
         let result = func(self);
@@ -63,6 +361,11 @@ impl Transcript {
         }
     }
 
+    // Bytes of each body that are recorded, 0 for all of them
+    pub fn get_max_body(&self) -> usize {
+        self.max_body
+    }
+
     pub fn get_prefix(&self) -> Option<String> {
         let mut builder = Builder::new(&String::from(" "));
         
@@ -82,90 +385,331 @@ impl Transcript {
         }
     }
 
-    fn try_get_file_name(name: &String, time_int: i32) -> Result<File, HttpError> {
-        let mut counter = 0;
-        let base_path = PathBuf::from("./logs");
-        if !base_path.exists() {
-            create_dir_all(&base_path).map_err(|_| http_errors::msg::internal_server_error("Failed to create logs directory"))?;
+    fn get_handle(&self) -> Result<u64, HttpError> {
+        if let Some(handle) = self.handle.get() {
+            return Ok(*handle);
         }
 
-        loop {
-            let path = if counter > 0 {
-                PathBuf::from(format!("{}_{}_{}.log", name, time_int, counter))
-            } else {
-                PathBuf::from(format!("{}_{}.log", name, time_int))
-            };
+        let name = format!("{}_{}", self.file_name, self.start.timestamp());
+        let handle = transcript_writer::open(self.dir.clone(), name)?;
+        Ok(*self.handle.get_or_init(|| handle))
+    }
 
-            let mut full_path = PathBuf::new();
-            full_path.push(&base_path);
-            full_path.push(path);
-            
-            if !full_path.exists() {
-                return File::create(&full_path).map_err(|e| HttpError::convert_from(e, Some("Failed to create transcript file")));
-            }
+    pub fn push(&self, line: &str) -> Result<(), HttpError> {
+        self.record(TranscriptEvent::Message(line))
+    }
 
-            counter += 1;
+    pub fn record(&self, event: TranscriptEvent) -> Result<(), HttpError> {
+        match event.get_direction() {
+            Some(Direction::Incoming) => self.bytes_in.set(self.bytes_in.get() + event.get_wire_size()),
+            Some(Direction::Outgoing) => self.bytes_out.set(self.bytes_out.get() + event.get_wire_size()),
+            None => {}
+        }
+
+        let required = event.get_level();
+        if required > self.level.get() {
+            return Ok(());
+        }
+
+        // Redacted before formatting, so neither format ever sees the raw values
+        let redaction = &self.redaction;
+        let (line, value, body);
+        let event = match event {
+            TranscriptEvent::Message(text) => {
+                line = redaction.redact_query(text);
+                TranscriptEvent::Message(&line)
+            },
+            TranscriptEvent::RequestLine(text) => {
+                line = redaction.redact_query(text);
+                TranscriptEvent::RequestLine(&line)
+            },
+            TranscriptEvent::Header(direction, name, text) => {
+                value = redaction.redact_header(name, text);
+                TranscriptEvent::Header(direction, name, &value)
+            },
+            TranscriptEvent::Body(direction, BodyData::Text(text)) => {
+                body = redaction.redact_body(text);
+                TranscriptEvent::Body(direction, BodyData::Text(&body))
+            },
+            event => event
+        };
+
+        match self.format {
+            TranscriptFormat::Text => self.record_text(&event, required),
+            TranscriptFormat::Json => self.record_json(&event, required)
         }
     }
 
-    pub fn push(&self, line: &str) -> Result<(), HttpError> {
+    fn record_text(&self, event: &TranscriptEvent, required: TranscriptLevel) -> Result<(), HttpError> {
         let now = get_time_str(false, true);
+        let direction = event.get_direction();
 
-        if !line.is_empty() {
-            for split_line in line.lines() {
-                self.push_int(&now, split_line)?;
+        let text = match event {
+            TranscriptEvent::Open => format!("New transcript for HTTP connection\n at {} UTC\n by {}", get_time_str_from(&self.start, true, true), self.peer),
+            TranscriptEvent::Message(line) | TranscriptEvent::RequestLine(line) | TranscriptEvent::Status(_, line) => line.to_string(),
+            TranscriptEvent::Header(_, name, value) => format!("{}: {}", name, value),
+            TranscriptEvent::HeaderEnd(_) => String::new(),
+            TranscriptEvent::Body(_, BodyData::Text(text)) => {
+                let (captured, truncated) = self.capture_text(text);
+                if truncated > 0 {
+                    format!("{}\n<{} more bytes not captured>", captured, truncated)
+                } else {
+                    captured.to_string()
+                }
+            },
+            TranscriptEvent::Body(_, BodyData::Binary(_) | BodyData::Prefix(_, _)) => {
+                let (captured, truncated) = self.capture_binary(event);
+                let mut text = format!("<binary data, {} bytes>\n{}", captured.len() + truncated, hex_dump(captured));
+                if truncated > 0 {
+                    text.push_str(&format!("\n<{} more bytes not captured>", truncated));
+                }
+
+                text
+            },
+            TranscriptEvent::Close(duration) => format!("\n\n\nTranscript ended after {}", format_duration(duration))
+        };
+
+        if !text.is_empty() {
+            for split_line in text.lines() {
+                self.push_int(&now, direction, split_line, required)?;
             }
         } else {
-            self.push_int(&now, "")?;
+            self.push_int(&now, direction, "", required)?;
         }
 
         Ok(())
     }
 
-    fn push_int(&self, time: &String, line: &str) -> Result<(), HttpError> {
-        let data = if let Some(prefix) = self.get_prefix() {
-            format!("[{}] {} {}", time, prefix, line)
+    fn record_json(&self, event: &TranscriptEvent, required: TranscriptLevel) -> Result<(), HttpError> {
+        // Blank messages only space out the text format
+        if matches!(event, TranscriptEvent::Message(line) if line.trim().is_empty()) {
+            return Ok(());
+        }
+
+        let mut object = JsonValue::object()
+            .with("timestamp", Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true))
+            .with("connection_id", self.connection_id.as_str())
+            .with("request_id", self.request_id.as_str())
+            .with("peer", self.peer.as_str())
+            .with("event", event.get_name())
+            .with("direction", event.get_direction().map(|direction| direction.get_prefix()));
+
+        object = match event {
+            TranscriptEvent::Open => object,
+            TranscriptEvent::Message(line) => object.with("message", *line),
+            TranscriptEvent::RequestLine(line) => object.with("line", *line),
+            TranscriptEvent::Status(code, line) => object.with("status", *code).with("line", *line),
+            TranscriptEvent::Header(_, name, value) => object.with("name", *name).with("value", *value),
+            TranscriptEvent::HeaderEnd(_) => object,
+            TranscriptEvent::Body(_, BodyData::Text(text)) => {
+                let (captured, truncated) = self.capture_text(text);
+                object.with("bytes", text.len() as u64).with("body", captured).with("truncated", truncated > 0)
+            },
+            TranscriptEvent::Body(_, BodyData::Binary(_) | BodyData::Prefix(_, _)) => {
+                let (captured, truncated) = self.capture_binary(event);
+                let hex: String = captured.iter().map(|byte| format!("{:02x}", byte)).collect();
+                object.with("bytes", (captured.len() + truncated) as u64).with("binary", true).with("hex", hex).with("truncated", truncated > 0)
+            },
+            TranscriptEvent::Close(duration) => object
+                .with("duration_us", duration.num_microseconds().unwrap_or(i64::MAX) as f64)
+                .with("bytes_in", self.bytes_in.get())
+                .with("bytes_out", self.bytes_out.get())
+        };
+
+        self.write_int(&object.to_string(), required)
+    }
+
+    // Cuts a text body down to the configured capture size, on a character boundary
+    fn capture_text<'b>(&self, text: &'b str) -> (&'b str, usize) {
+        if self.max_body == 0 || text.len() <= self.max_body {
+            return (text, 0);
+        }
+
+        let mut end = self.max_body;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        (&text[..end], text.len() - end)
+    }
+
+    // Cuts a binary body or prefix down to the configured capture size, returns what is left of the whole body
+    fn capture_binary<'b>(&self, event: &TranscriptEvent<'b>) -> (&'b [u8], usize) {
+        let (data, len) = match event {
+            TranscriptEvent::Body(_, BodyData::Binary(data)) => (*data, data.len()),
+            TranscriptEvent::Body(_, BodyData::Prefix(data, len)) => (*data, *len as usize),
+            _ => return (&[], 0)
+        };
+
+        let end = if self.max_body == 0 { data.len() } else { data.len().min(self.max_body) };
+        (&data[..end], len - end)
+    }
+
+    fn push_int(&self, time: &String, direction: Option<Direction>, line: &str, required: TranscriptLevel) -> Result<(), HttpError> {
+        let mut prefix = Builder::new(" ");
+        prefix.try_append(&self.get_prefix());
+        if let Some(direction) = direction {
+            prefix.append(direction.get_prefix());
+        }
+
+        let data = if let Some(prefix) = prefix.get(true) {
+            format!("[{}] [{}] {} {}", time, self.request_id, prefix, line)
         } else {
-            format!("[{}] {}", time, line)
+            format!("[{}] [{}] {}", time, self.request_id, line)
         };
 
-        println!("[TS] {}", data);
-        write!(&self.file, "{}\r\n", data).map_err(|e| HttpError::convert_from(e, Some("Failed to write to transcript file")))
+        self.write_int(&data, required)
     }
 
-    pub fn flush(&mut self) -> Result<(), HttpError> {
-        self.file.flush().map_err(|e| HttpError::convert_from(e, Some("Failed to flush transcript file")))
+    fn write_int(&self, data: &str, required: TranscriptLevel) -> Result<(), HttpError> {
+        if let Some(pending) = self.pending.borrow_mut().as_mut() {
+            pending.push((required, data.to_string()));
+            return Ok(());
+        }
+
+        self.write_out(data.to_string())
+    }
+
+    fn write_out(&self, data: String) -> Result<(), HttpError> {
+        transcript_writer::write_line(self.get_handle()?, data)
     }
 }
 
 impl Drop for Transcript {
     fn drop(&mut self) {
         let duration: Duration = Utc::now().signed_duration_since(self.start);
-        
-        let mut builder = Builder::new(&String::from(" "));
-        let minutes = duration.num_minutes();
-        if minutes > 0 {
-            builder.append(&format!("{}m", minutes));
+
+        self.record(TranscriptEvent::Close(duration)).ok();
+
+        // Requests that never got far enough to pick a route use the default level
+        if self.pending.borrow().is_some() {
+            self.set_level(self.default_level).ok();
         }
 
-        let seconds = duration.num_seconds() % 60;
-        if seconds > 0 {
-            builder.append(&format!("{}s", seconds));
+        if let Some(handle) = self.handle.get() {
+            transcript_writer::close(*handle).ok();
         }
 
-        let nanos = duration.subsec_nanos() as u64;
-        
-        let (small_str, small_unit) = if nanos < 100_000 {
-            (format!("{}ns", nanos), nanos)
-        } else {
-            (format!("{}\u{00B5}s", nanos / 1_000), nanos / 1_000)
-        };
+        metrics::add_bytes(self.bytes_in.get(), self.bytes_out.get());
+    }
+}
 
-        if small_unit > 0 || builder.is_empty() {
-            builder.append(&small_str);
-        }
+// 16 bytes per line: offset, hex bytes, printable ASCII
+fn hex_dump(data: &[u8]) -> String {
+    let mut lines = Vec::new();
+
+    for (index, chunk) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let ascii: String = chunk.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }).collect();
+        lines.push(format!("{:08x}  {:<47}  |{}|", index * 16, hex.join(" "), ascii));
+    }
+
+    lines.join("\n")
+}
+
+fn format_duration(duration: &Duration) -> String {
+    let mut builder = Builder::new(" ");
+    let minutes = duration.num_minutes();
+    if minutes > 0 {
+        builder.append(&format!("{}m", minutes));
+    }
+
+    let seconds = duration.num_seconds() % 60;
+    if seconds > 0 {
+        builder.append(&format!("{}s", seconds));
+    }
+
+    let nanos = duration.subsec_nanos() as u64;
+    
+    let (small_str, small_unit) = if nanos < 100_000 {
+        (format!("{}ns", nanos), nanos)
+    } else {
+        (format!("{}\u{00B5}s", nanos / 1_000), nanos / 1_000)
+    };
+
+    if small_unit > 0 || builder.is_empty() {
+        builder.append(&small_str);
+    }
+
+    builder.result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::read_line;
+
+    fn transcript(max_body: usize) -> Transcript {
+        let config = TranscriptConfig { level: TranscriptLevel::Off, max_body, ..TranscriptConfig::default() };
+        Transcript::new("127.0.0.1:1", "test", &config, Arc::new(Redaction::default())).unwrap()
+    }
+
+    #[test]
+    fn prefix_counts_the_whole_body() {
+        let prefix = [1u8; 16];
+        let event = TranscriptEvent::Body(Direction::Outgoing, BodyData::Prefix(&prefix, 1 << 30));
+        assert_eq!(event.get_wire_size(), 1 << 30);
+
+        let (captured, truncated) = transcript(4).capture_binary(&event);
+        assert_eq!(captured.len(), 4);
+        assert_eq!(truncated, (1 << 30) - 4);
+    }
+
+    #[test]
+    fn text_is_cut_on_a_character_boundary() {
+        let (captured, truncated) = transcript(2).capture_text("äb");
+        assert_eq!(captured, "ä");
+        assert_eq!(truncated, 1);
+    }
+
+    // A route holds every line back until the level is resolved, so tests can read what would be written
+    fn held(format: TranscriptFormat) -> Transcript {
+        let routes = vec![TranscriptRoute { matcher: RuleMatch::parse("prefix", "/").unwrap(), level: TranscriptLevel::Full }];
+        let config = TranscriptConfig { level: TranscriptLevel::Off, format, routes, ..TranscriptConfig::default() };
+        Transcript::new("127.0.0.1:1", "test", &config, Arc::new(Redaction::default())).unwrap()
+    }
 
-        self.push(format!("\n\n\nTranscript ended after {}", builder.result).as_str()).ok();
-        self.flush().ok();
+    fn held_lines(transcript: &Transcript) -> Vec<String> {
+        transcript.pending.borrow().iter().flatten().map(|(_, line)| line.clone()).collect()
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn json_writes_one_object_per_event() {
+        let transcript = held(TranscriptFormat::Json);
+        transcript.push("").unwrap();
+        transcript.record(TranscriptEvent::RequestLine("GET / HTTP/1.1")).unwrap();
+        transcript.record(TranscriptEvent::Header(Direction::Incoming, "Host", "a \"b\"")).unwrap();
+        transcript.record(TranscriptEvent::Body(Direction::Outgoing, BodyData::Text("line\nbreak"))).unwrap();
+
+        let lines = held_lines(&transcript);
+        assert_eq!(lines.len(), 4, "blank messages are skipped: {:?}", lines);
+        assert!(lines.iter().all(|line| line.starts_with('{') && line.ends_with('}') && !line.contains('\n')));
+
+        assert!(lines[0].contains("\"event\":\"open\""));
+        assert!(lines[1].contains("\"event\":\"request_line\"") && lines[1].contains("\"direction\":\"-->\""));
+        assert!(lines[2].contains("\"value\":\"a \\\"b\\\"\""));
+        assert!(lines[3].contains("\"body\":\"line\\nbreak\"") && lines[3].contains("\"request_id\":\"test\""));
+    }
+
+    #[test]
+    fn text_prefixes_lines_with_the_request_id() {
+        let transcript = held(TranscriptFormat::Text);
+        transcript.record(TranscriptEvent::Header(Direction::Outgoing, "Server", "myhttp")).unwrap();
+
+        let lines = held_lines(&transcript);
+        assert!(lines.last().unwrap().ends_with("[test] 127.0.0.1:1 <-- Server: myhttp"), "{:?}", lines);
+    }
+
+    #[test]
+    fn malformed_header_lines_are_not_recorded() {
+        let transcript = held(TranscriptFormat::Text);
+        read_line(&transcript, "Authorization : Basic c2VjcmV0", false).unwrap();
+        read_line(&transcript, "Authorization Basic c2VjcmV0", false).unwrap();
+        read_line(&transcript, "Cookie: id=1", false).unwrap();
+
+        let lines = held_lines(&transcript);
+        assert!(lines.iter().all(|line| !line.contains("c2VjcmV0") && !line.contains("id=1")), "{:?}", lines);
+        assert!(lines[lines.len() - 3].ends_with("<malformed header line, 30 bytes>"));
+        assert!(lines.last().unwrap().ends_with("--> Cookie: [REDACTED]"));
+    }
+}
@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Days, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use regex::Regex;
+
+use crate::json::JsonValue;
+
+// Reads transcripts back into request/response exchanges, for exports and replay.
+// Only what the transcript level captured is available, e.g. no headers at level summary.
+
+pub struct RecordedBody {
+    pub text: Option<String>,
+    pub binary: Option<Vec<u8>>,
+    // Size on the wire, which may be more than what was captured
+    pub size: u64,
+    pub truncated: bool
+}
+
+pub struct RecordedExchange {
+    pub request_id: String,
+    pub connection_id: Option<String>,
+    pub peer: String,
+    pub opened: Option<DateTime<Utc>>,
+    pub request_line: Option<String>,
+    pub request_time: Option<DateTime<Utc>>,
+    pub request_headers: Vec<(String, String)>,
+    // When the incoming header section ended
+    pub request_end: Option<DateTime<Utc>>,
+    pub status: Option<i32>,
+    pub status_line: Option<String>,
+    pub response_time: Option<DateTime<Utc>>,
+    pub response_headers: Vec<(String, String)>,
+    pub response_body: Option<RecordedBody>,
+    pub last_time: Option<DateTime<Utc>>
+}
+
+#[derive(PartialEq)]
+enum Section {
+    Head,
+    Body
+}
+
+struct ExchangeState {
+    exchange: RecordedExchange,
+    incoming: Section,
+    outgoing: Section,
+    date: Option<NaiveDate>
+}
+
+#[allow(unused)]
+impl RecordedExchange {
+    fn new(request_id: &str, peer: &str) -> Self {
+        Self {
+            request_id: request_id.to_string(),
+            connection_id: None,
+            peer: peer.to_string(),
+            opened: None,
+            request_line: None,
+            request_time: None,
+            request_headers: Vec::new(),
+            request_end: None,
+            status: None,
+            status_line: None,
+            response_time: None,
+            response_headers: Vec::new(),
+            response_body: None,
+            last_time: None
+        }
+    }
+
+    pub fn get_request_header(&self, name: &str) -> Option<&str> {
+        self.request_headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_response_header(&self, name: &str) -> Option<&str> {
+        self.response_headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    // <method> <target> <version> from the request line
+    pub fn get_request_parts(&self) -> Option<(&str, &str, &str)> {
+        let mut parts = self.request_line.as_deref()?.split(' ');
+        Some((parts.next()?, parts.next()?, parts.next()?))
+    }
+}
+
+impl ExchangeState {
+    fn new(request_id: &str, peer: &str) -> Self {
+        Self {
+            exchange: RecordedExchange::new(request_id, peer),
+            incoming: Section::Head,
+            outgoing: Section::Head,
+            date: None
+        }
+    }
+
+    fn touch(&mut self, time: DateTime<Utc>) {
+        self.exchange.last_time = Some(time);
+    }
+
+    fn incoming_line(&mut self, time: DateTime<Utc>, line: &str) {
+        if self.exchange.request_line.is_none() {
+            self.exchange.request_line = Some(line.to_string());
+            self.exchange.request_time = Some(time);
+        } else if self.incoming == Section::Head {
+            if line.is_empty() {
+                self.incoming = Section::Body;
+                self.exchange.request_end = Some(time);
+            } else if let Some((name, value)) = line.split_once(':') {
+                self.exchange.request_headers.push((name.to_string(), value.trim().to_string()));
+            }
+        }
+    }
+
+    fn outgoing_line(&mut self, time: DateTime<Utc>, line: &str) {
+        if self.exchange.status_line.is_none() {
+            self.exchange.status = line.split(' ').nth(1).and_then(|code| code.parse().ok());
+            self.exchange.status_line = Some(line.to_string());
+            self.exchange.response_time = Some(time);
+        } else if self.outgoing == Section::Head {
+            if line.is_empty() {
+                self.outgoing = Section::Body;
+            } else if let Some((name, value)) = line.split_once(':') {
+                self.exchange.response_headers.push((name.to_string(), value.trim().to_string()));
+            }
+        } else {
+            self.body_line(line);
+        }
+    }
+
+    // Text transcripts spread bodies over lines, binary ones as a hex dump
+    fn body_line(&mut self, line: &str) {
+        if let Some(rest) = line.strip_prefix('<').and_then(|rest| rest.strip_suffix(" more bytes not captured>")) {
+            if let Some(body) = &mut self.exchange.response_body {
+                body.truncated = true;
+                // Binary bodies announce their full size up front
+                if body.binary.is_none() {
+                    body.size += rest.parse::<u64>().unwrap_or(0);
+                }
+            }
+
+            return;
+        }
+
+        if let Some(size) = line.strip_prefix("<binary data, ").and_then(|rest| rest.strip_suffix(" bytes>")) {
+            self.exchange.response_body = Some(RecordedBody {
+                text: None,
+                binary: Some(Vec::new()),
+                size: size.parse().unwrap_or(0),
+                truncated: false
+            });
+
+            return;
+        }
+
+        let body = self.exchange.response_body.get_or_insert(RecordedBody {
+            text: Some(String::new()),
+            binary: None,
+            size: 0,
+            truncated: false
+        });
+
+        if let Some(binary) = &mut body.binary {
+            binary.extend(parse_hex_dump_line(line));
+        } else if let Some(text) = &mut body.text {
+            if body.size > 0 {
+                text.push('\n');
+                body.size += 1;
+            }
+
+            text.push_str(line);
+            body.size += line.len() as u64;
+        }
+    }
+}
+
+// Detects the format from the first line, JSON Lines transcripts start with an object
+pub fn read_transcript(contents: &str, fallback_date: NaiveDate) -> Vec<RecordedExchange> {
+    match contents.lines().find(|line| !line.trim().is_empty()) {
+        Some(line) if line.trim_start().starts_with('{') => read_json(contents),
+        Some(_) => read_text(contents, fallback_date),
+        None => Vec::new()
+    }
+}
+
+fn read_text(contents: &str, fallback_date: NaiveDate) -> Vec<RecordedExchange> {
+    let line_regex = Regex::new(r"^\[(\d{2}:\d{2}:\d{2}\.\d{3})\] \[([^\]]*)\] (\S+) ?(.*)$").expect("valid transcript line regex");
+    let alias_regex = Regex::new(r"^Request id (\S+) replaced by X-Request-Id (\S+)$").expect("valid alias regex");
+
+    let mut order = Vec::new();
+    let mut states: HashMap<String, ExchangeState> = HashMap::new();
+    let mut aliases: HashMap<String, String> = HashMap::new();
+
+    for line in contents.lines() {
+        let line = line.trim_end_matches('\r');
+        let Some(captures) = line_regex.captures(line) else {
+            continue;
+        };
+
+        let Ok(time_of_day) = NaiveTime::parse_from_str(&captures[1], "%H:%M:%S%.3f") else {
+            continue;
+        };
+
+        let id = captures[2].to_string();
+        let key = aliases.get(&id).cloned().unwrap_or_else(|| id.clone());
+        let rest = &captures[4];
+
+        let state = states.entry(key.clone()).or_insert_with(|| {
+            order.push(key.clone());
+            ExchangeState::new(&id, &captures[3])
+        });
+
+        // The open event carries the date, every later line only the time of day
+        if let Some(opened) = rest.strip_prefix(" at ").and_then(|rest| rest.strip_suffix(" UTC")) {
+            if let Ok(opened) = NaiveDateTime::parse_from_str(opened, "%Y-%m-%d %H:%M:%S%.3f") {
+                state.date = Some(opened.date());
+                state.exchange.opened = Some(opened.and_utc());
+                // Lines before this one were dated with the fallback, they must not count as a day rollover
+                state.exchange.last_time = None;
+            }
+        }
+
+        let mut date = state.date.unwrap_or(fallback_date);
+        let mut time = date.and_time(time_of_day).and_utc();
+        if state.exchange.last_time.is_some_and(|last| time < last) {
+            date = date.checked_add_days(Days::new(1)).unwrap_or(date);
+            time = date.and_time(time_of_day).and_utc();
+        }
+
+        state.date = Some(date);
+        state.touch(time);
+        state.exchange.request_id = id.clone();
+
+        if let Some(line) = rest.strip_prefix("-->") {
+            state.incoming_line(time, line.strip_prefix(' ').unwrap_or(line));
+        } else if let Some(line) = rest.strip_prefix("<--") {
+            state.outgoing_line(time, line.strip_prefix(' ').unwrap_or(line));
+        } else if let Some(alias) = alias_regex.captures(rest) {
+            aliases.insert(alias[2].to_string(), key.clone());
+        }
+    }
+
+    order.into_iter().filter_map(|key| states.remove(&key)).map(|state| state.exchange).collect()
+}
+
+fn read_json(contents: &str) -> Vec<RecordedExchange> {
+    let mut order = Vec::new();
+    let mut states: HashMap<String, ExchangeState> = HashMap::new();
+
+    for line in contents.lines() {
+        let Ok(event) = JsonValue::parse(line) else {
+            continue;
+        };
+
+        let field = |name: &str| event.get(name).and_then(JsonValue::as_str).unwrap_or("");
+        let Ok(time) = DateTime::parse_from_rfc3339(field("timestamp")).map(|time| time.with_timezone(&Utc)) else {
+            continue;
+        };
+
+        // Connection ids stay stable when the request id is replaced by X-Request-Id
+        let key = field("connection_id").to_string();
+        let state = states.entry(key.clone()).or_insert_with(|| {
+            order.push(key.clone());
+            let mut state = ExchangeState::new(field("request_id"), field("peer"));
+            state.exchange.connection_id = Some(key.clone());
+            state
+        });
+
+        state.touch(time);
+        state.exchange.request_id = field("request_id").to_string();
+
+        match field("event") {
+            "open" => state.exchange.opened = Some(time),
+            "request_line" => state.incoming_line(time, field("line")),
+            "status" => state.outgoing_line(time, field("line")),
+            "header" if field("direction") == "-->" => state.incoming_line(time, &format!("{}: {}", field("name"), field("value"))),
+            "header" => state.outgoing_line(time, &format!("{}: {}", field("name"), field("value"))),
+            "header_end" if field("direction") == "-->" => state.incoming_line(time, ""),
+            "header_end" => state.outgoing_line(time, ""),
+            "body" if field("direction") == "<--" => {
+                let size = event.get("bytes").and_then(JsonValue::as_f64).unwrap_or(0.0) as u64;
+                let truncated = event.get("truncated").and_then(JsonValue::as_bool).unwrap_or(false);
+                let binary = event.get("hex").and_then(JsonValue::as_str).map(parse_hex);
+
+                state.exchange.response_body = Some(RecordedBody {
+                    text: event.get("body").and_then(JsonValue::as_str).map(str::to_string),
+                    binary,
+                    size,
+                    truncated
+                });
+            },
+            _ => {}
+        }
+    }
+
+    order.into_iter().filter_map(|key| states.remove(&key)).map(|state| state.exchange).collect()
+}
+
+fn parse_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len() / 2).filter_map(|index| u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).ok()).collect()
+}
+
+// `00000010  00 0d 49 48 ...  |..IH|`, only the hex columns matter
+fn parse_hex_dump_line(line: &str) -> Vec<u8> {
+    let columns = line.get(10..).unwrap_or("");
+    let columns = columns.split("  |").next().unwrap_or("");
+    columns.split_whitespace().filter_map(|byte| u8::from_str_radix(byte, 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fallback() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 10, 19).unwrap()
+    }
+
+    #[test]
+    fn reads_binary_bodies_from_hex_dumps() {
+        let text = "[10:00:00.000] [a] 127.0.0.1:1 <-- HTTP/1.1 200 OK\n\
+                    [10:00:00.000] [a] 127.0.0.1:1 <-- \n\
+                    [10:00:00.000] [a] 127.0.0.1:1 <-- <binary data, 40 bytes>\n\
+                    [10:00:00.000] [a] 127.0.0.1:1 <-- 00000000  00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f  |................|\n\
+                    [10:00:00.000] [a] 127.0.0.1:1 <-- 00000010  10 11                                            |..|\n\
+                    [10:00:00.000] [a] 127.0.0.1:1 <-- <22 more bytes not captured>\n";
+
+        let exchanges = read_transcript(text, fallback());
+        let body = exchanges[0].response_body.as_ref().unwrap();
+        assert_eq!(body.binary.as_deref(), Some(&(0..18).collect::<Vec<u8>>()[..]));
+        assert_eq!(body.size, 40);
+        assert!(body.truncated);
+
+        let json = r#"{"timestamp":"2026-10-19T10:00:00Z","connection_id":"c","request_id":"a","peer":"p","event":"body","direction":"<--","bytes":40,"binary":true,"hex":"00010203","truncated":true}"#;
+        let exchanges = read_transcript(json, fallback());
+        let body = exchanges[0].response_body.as_ref().unwrap();
+        assert_eq!(body.binary.as_deref(), Some(&[0u8, 1, 2, 3][..]));
+        assert_eq!((body.size, body.truncated), (40, true));
+    }
+
+    #[test]
+    fn text_times_roll_over_midnight() {
+        let text = "[23:59:59.900] [a] 127.0.0.1:1 New transcript for HTTP connection\n\
+                    [23:59:59.900] [a] 127.0.0.1:1  at 2026-10-18 23:59:59.900 UTC\n\
+                    [23:59:59.950] [a] 127.0.0.1:1 --> GET / HTTP/1.1\n\
+                    [00:00:00.050] [a] 127.0.0.1:1 <-- HTTP/1.1 404 Not Found\n";
+
+        let exchanges = read_transcript(text, fallback());
+        let exchange = &exchanges[0];
+        assert_eq!(exchange.status, Some(404));
+        assert_eq!(exchange.request_time.unwrap().to_rfc3339(), "2026-10-18T23:59:59.950+00:00");
+        assert_eq!(exchange.response_time.unwrap().to_rfc3339(), "2026-10-19T00:00:00.050+00:00");
+    }
+
+    #[test]
+    fn keeps_connections_apart() {
+        let text = "[10:00:00.000] [a] 127.0.0.1:1 --> GET /a HTTP/1.1\n\
+                    [10:00:00.000] [b] 127.0.0.1:2 --> GET /b HTTP/1.1\n\
+                    [10:00:00.000] [a] 127.0.0.1:1 --> Host: x\n\
+                    not a transcript line\n\
+                    [10:00:00.001] [b] 127.0.0.1:2 <-- HTTP/1.1 200 OK\n";
+
+        let exchanges = read_transcript(text, fallback());
+        assert_eq!(exchanges.len(), 2);
+        assert_eq!(exchanges[0].get_request_parts(), Some(("GET", "/a", "HTTP/1.1")));
+        assert_eq!(exchanges[0].get_request_header("host"), Some("x"));
+        assert_eq!((exchanges[1].peer.as_str(), exchanges[1].status), ("127.0.0.1:2", Some(200)));
+        assert!(read_transcript("\n\n", fallback()).is_empty());
+    }
+}
@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+use std::fs::{create_dir_all, File};
+use std::io::{self, BufWriter, Stdout, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use chrono::Utc;
+
+use crate::config::TranscriptConfig;
+use crate::http_error::{http_errors, HttpError};
+use crate::log_rotation::{gzip_file, RotatingFile};
+use crate::transcript::{self, TranscriptLayout};
+
+// Destinations for transcript lines. Sinks live on the writer thread, see transcript_writer,
+// and are only ever called from there.
+pub trait TranscriptSink: Send {
+    // A connection wrote its first line, sinks writing a single stream can ignore this
+    fn open(&mut self, _connection: &SinkConnection) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_line(&mut self, connection: u64, line: &str) -> io::Result<()>;
+
+    fn close(&mut self, _connection: u64) -> io::Result<()> {
+        Ok(())
+    }
+
+    // Called whenever the writer queue runs dry
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SinkKind {
+    // Files in the configured layout, the shared layout writes to the rotating file
+    File,
+    // Lines prefixed with [TS]
+    Stdout,
+    // RFC 3164 messages over a Unix datagram socket
+    Syslog
+}
+
+pub struct SinkConnection {
+    pub handle: u64,
+    // Includes the daily partition
+    pub dir: PathBuf,
+    // <peer>_<timestamp>, without extension
+    pub name: String
+}
+
+// One file per connection, for the per_connection and daily layouts
+pub struct FileSink {
+    extension: &'static str,
+    compress: bool,
+    files: HashMap<u64, OpenFile>
+}
+
+// The shared layout's transcript file, rotated by size and age
+pub struct RotatingFileSink {
+    file: Arc<Mutex<RotatingFile>>
+}
+
+pub struct StdoutSink {
+    out: BufWriter<Stdout>
+}
+
+pub struct SyslogSink {
+    socket: UnixDatagram,
+    path: PathBuf,
+    facility: u8,
+    pid: u32
+}
+
+// Keeps every line in memory, e.g. to inspect transcripts from tests
+#[allow(unused)]
+pub struct MemorySink {
+    lines: Arc<Mutex<Vec<(u64, String)>>>
+}
+
+// Passes everything on to each sink, a failing sink does not stop the others
+pub struct FanOutSink {
+    sinks: Vec<Box<dyn TranscriptSink>>
+}
+
+struct OpenFile {
+    writer: BufWriter<File>,
+    path: PathBuf
+}
+
+impl SinkKind {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "file" => Ok(SinkKind::File),
+            "stdout" => Ok(SinkKind::Stdout),
+            "syslog" => Ok(SinkKind::Syslog),
+            _ => Err(format!("Unknown transcript sink \"{}\", expected file, stdout or syslog", value))
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            SinkKind::File => "file",
+            SinkKind::Stdout => "stdout",
+            SinkKind::Syslog => "syslog"
+        }
+    }
+
+    // Comma separated, an empty value disables every sink
+    pub fn parse_list(value: &str) -> Result<Vec<Self>, String> {
+        value.split(',').map(str::trim).filter(|kind| !kind.is_empty()).map(Self::parse).collect()
+    }
+}
+
+// Syslog facility codes, only the ones that make sense for a server
+pub fn parse_facility(value: &str) -> Result<u8, String> {
+    match value {
+        "user" => Ok(1),
+        "daemon" => Ok(3),
+        _ => match value.strip_prefix("local").and_then(|number| number.parse::<u8>().ok()) {
+            Some(number) if number <= 7 => Ok(16 + number),
+            _ => Err(format!("Unknown syslog facility \"{}\", expected user, daemon or local0-local7", value))
+        }
+    }
+}
+
+// Builds the configured sinks, failing if one can not be set up
+pub fn build_sinks(config: &TranscriptConfig) -> Result<FanOutSink, HttpError> {
+    let mut sinks = FanOutSink::new();
+
+    for kind in &config.sinks {
+        match kind {
+            SinkKind::File if config.layout == TranscriptLayout::Shared => {
+                let file = transcript::get_shared_file().ok_or_else(|| http_errors::msg::internal_server_error("Shared transcript file was not opened"))?;
+                sinks.add(Box::new(RotatingFileSink::new(file)));
+            },
+            SinkKind::File => sinks.add(Box::new(FileSink::new(config))),
+            SinkKind::Stdout => sinks.add(Box::new(StdoutSink::new())),
+            SinkKind::Syslog => {
+                let sink = SyslogSink::connect(&config.syslog_socket, config.syslog_facility)
+                    .map_err(|e| HttpError::convert_from(e, Some("Failed to connect to syslog socket")))?;
+                sinks.add(Box::new(sink));
+            }
+        }
+    }
+
+    Ok(sinks)
+}
+
+impl FileSink {
+    pub fn new(config: &TranscriptConfig) -> Self {
+        Self {
+            extension: config.format.get_extension(),
+            compress: config.compress,
+            files: HashMap::new()
+        }
+    }
+
+    // Adds a counter when several connections from the same peer start within a second
+    fn create_file(dir: &Path, name: &str, extension: &str) -> io::Result<(File, PathBuf)> {
+        create_dir_all(dir)?;
+
+        let mut counter = 0;
+        loop {
+            let path = if counter > 0 {
+                dir.join(format!("{}_{}.{}", name, counter, extension))
+            } else {
+                dir.join(format!("{}.{}", name, extension))
+            };
+
+            match File::options().write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok((file, path)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => counter += 1,
+                Err(e) => return Err(e)
+            }
+        }
+    }
+}
+
+impl TranscriptSink for FileSink {
+    fn open(&mut self, connection: &SinkConnection) -> io::Result<()> {
+        let (file, path) = Self::create_file(&connection.dir, &connection.name, self.extension)?;
+        self.files.insert(connection.handle, OpenFile { writer: BufWriter::new(file), path });
+        Ok(())
+    }
+
+    fn write_line(&mut self, connection: u64, line: &str) -> io::Result<()> {
+        match self.files.get_mut(&connection) {
+            Some(file) => write!(file.writer, "{}\r\n", line),
+            None => Ok(())
+        }
+    }
+
+    fn close(&mut self, connection: u64) -> io::Result<()> {
+        let Some(mut file) = self.files.remove(&connection) else {
+            return Ok(());
+        };
+
+        file.writer.flush()?;
+        drop(file.writer);
+
+        // Per connection files are complete once their connection ends
+        if self.compress {
+            gzip_file(&file.path)?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        for file in self.files.values_mut() {
+            file.writer.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl RotatingFileSink {
+    pub fn new(file: Arc<Mutex<RotatingFile>>) -> Self {
+        Self { file }
+    }
+}
+
+impl TranscriptSink for RotatingFileSink {
+    fn write_line(&mut self, _connection: u64, line: &str) -> io::Result<()> {
+        self.file.lock().unwrap_or_else(|e| e.into_inner()).write_line(line)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.lock().unwrap_or_else(|e| e.into_inner()).flush()
+    }
+}
+
+impl StdoutSink {
+    pub fn new() -> Self {
+        Self { out: BufWriter::new(io::stdout()) }
+    }
+}
+
+impl TranscriptSink for StdoutSink {
+    fn write_line(&mut self, _connection: u64, line: &str) -> io::Result<()> {
+        writeln!(self.out, "[TS] {}", line)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+impl SyslogSink {
+    pub fn connect(path: &Path, facility: u8) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+
+        Ok(Self {
+            socket,
+            path: path.to_path_buf(),
+            facility,
+            pid: process::id()
+        })
+    }
+
+    fn send(&self, message: &str) -> io::Result<()> {
+        if self.socket.send(message.as_bytes()).is_ok() {
+            return Ok(());
+        }
+
+        // The syslog daemon may have restarted, which leaves a stale socket behind
+        self.socket.connect(&self.path)?;
+        self.socket.send(message.as_bytes()).map(|_| ())
+    }
+}
+
+impl TranscriptSink for SyslogSink {
+    fn write_line(&mut self, _connection: u64, line: &str) -> io::Result<()> {
+        // Severity informational
+        let priority = self.facility * 8 + 6;
+        let timestamp = Utc::now().format("%b %e %H:%M:%S");
+
+        // Hex dumps and text bodies span several lines, syslog takes one per message
+        for line in line.split('\n') {
+            self.send(&format!("<{}>{} myhttp[{}]: {}", priority, timestamp, self.pid, line.trim_end_matches('\r')))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[allow(unused)]
+impl MemorySink {
+    pub fn new() -> Self {
+        Self { lines: Arc::new(Mutex::new(Vec::new())) }
+    }
+
+    // Shares the recorded lines, so they can still be read once the sink moved to the writer
+    pub fn get_lines(&self) -> Arc<Mutex<Vec<(u64, String)>>> {
+        Arc::clone(&self.lines)
+    }
+}
+
+impl TranscriptSink for MemorySink {
+    fn write_line(&mut self, connection: u64, line: &str) -> io::Result<()> {
+        self.lines.lock().unwrap_or_else(|e| e.into_inner()).push((connection, line.to_string()));
+        Ok(())
+    }
+}
+
+#[allow(unused)]
+impl FanOutSink {
+    pub fn new() -> Self {
+        Self { sinks: Vec::new() }
+    }
+
+    pub fn add(&mut self, sink: Box<dyn TranscriptSink>) {
+        self.sinks.push(sink);
+    }
+
+    // Runs `func` on every sink and returns the first error
+    fn each<F>(&mut self, mut func: F) -> io::Result<()> where F: FnMut(&mut Box<dyn TranscriptSink>) -> io::Result<()> {
+        let mut result = Ok(());
+
+        for sink in &mut self.sinks {
+            if let Err(e) = func(sink) {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+
+        result
+    }
+}
+
+impl TranscriptSink for FanOutSink {
+    fn open(&mut self, connection: &SinkConnection) -> io::Result<()> {
+        self.each(|sink| sink.open(connection))
+    }
+
+    fn write_line(&mut self, connection: u64, line: &str) -> io::Result<()> {
+        self.each(|sink| sink.write_line(connection, line))
+    }
+
+    fn close(&mut self, connection: u64) -> io::Result<()> {
+        self.each(|sink| sink.close(connection))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.each(|sink| sink.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FailingSink;
+
+    impl TranscriptSink for FailingSink {
+        fn write_line(&mut self, _connection: u64, _line: &str) -> io::Result<()> {
+            Err(io::Error::other("sink failed"))
+        }
+    }
+
+    #[test]
+    fn fan_out_writes_to_every_sink() {
+        let (first, second) = (MemorySink::new(), MemorySink::new());
+        let (first_lines, second_lines) = (first.get_lines(), second.get_lines());
+
+        let mut sinks = FanOutSink::new();
+        sinks.add(Box::new(first));
+        sinks.add(Box::new(second));
+
+        sinks.write_line(1, "GET / HTTP/1.1").unwrap();
+        sinks.write_line(2, "Host: x").unwrap();
+        sinks.flush().unwrap();
+
+        let expected = vec![(1, String::from("GET / HTTP/1.1")), (2, String::from("Host: x"))];
+        assert_eq!(*first_lines.lock().unwrap(), expected);
+        assert_eq!(*second_lines.lock().unwrap(), expected);
+    }
+
+    #[test]
+    fn failing_sink_does_not_stop_the_others() {
+        let memory = MemorySink::new();
+        let lines = memory.get_lines();
+
+        let mut sinks = FanOutSink::new();
+        sinks.add(Box::new(FailingSink));
+        sinks.add(Box::new(memory));
+
+        assert!(sinks.write_line(7, "line").is_err());
+        assert_eq!(*lines.lock().unwrap(), vec![(7, String::from("line"))]);
+    }
+}
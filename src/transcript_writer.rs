@@ -0,0 +1,262 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::OnceLock;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::config::TranscriptConfig;
+use crate::http_error::{HttpError, http_errors};
+use crate::metrics;
+use crate::transcript_sink::{build_sinks, FanOutSink, SinkConnection, TranscriptSink};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum QueuePolicy {
+    // Wait for room, transcripts stay complete but requests slow down
+    Block,
+    // Discard lines and count them
+    Drop,
+    // Keep every nth line while the queue is full, discard and count the rest
+    Sample(u64)
+}
+
+enum WriterMessage {
+    Open(SinkConnection),
+    Line(u64, String),
+    Close(u64),
+    Shutdown(SyncSender<()>)
+}
+
+struct TranscriptWriter {
+    sender: SyncSender<WriterMessage>,
+    policy: QueuePolicy
+}
+
+static WRITER: OnceLock<TranscriptWriter> = OnceLock::new();
+static NEXT_HANDLE: AtomicU64 = AtomicU64::new(1);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+static SAMPLED: AtomicU64 = AtomicU64::new(0);
+// Messages sent but not yet taken off the queue, may briefly go negative as both sides race
+static QUEUED: AtomicI64 = AtomicI64::new(0);
+static THREAD: OnceLock<JoinHandle<()>> = OnceLock::new();
+
+impl QueuePolicy {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "block" => Ok(QueuePolicy::Block),
+            "drop" => Ok(QueuePolicy::Drop),
+            _ => {
+                let rate = value.strip_prefix("sample:").ok_or_else(|| {
+                    format!("Unknown queue policy \"{}\", expected block, drop or sample:<n>", value)
+                })?;
+
+                match rate.parse::<u64>() {
+                    Ok(rate) if rate > 0 => Ok(QueuePolicy::Sample(rate)),
+                    _ => Err(format!("\"{}\" is not a valid sample rate", rate))
+                }
+            }
+        }
+    }
+
+    pub fn get_name(&self) -> String {
+        match self {
+            QueuePolicy::Block => String::from("block"),
+            QueuePolicy::Drop => String::from("drop"),
+            QueuePolicy::Sample(rate) => format!("sample:{}", rate)
+        }
+    }
+}
+
+// Starts the background thread that owns all transcript sinks
+pub fn start(config: &TranscriptConfig) -> Result<(), HttpError> {
+    let sinks = build_sinks(config)?;
+    let (sender, receiver) = sync_channel(config.queue_size);
+    let writer = TranscriptWriter {
+        sender,
+        policy: config.queue_policy
+    };
+
+    if WRITER.set(writer).is_ok() {
+        THREAD.set(thread::spawn(move || run(receiver, sinks))).ok();
+    }
+
+    Ok(())
+}
+
+// Announces a connection's transcript to the sinks, returns the handle its lines are written with
+pub fn open(dir: PathBuf, name: String) -> Result<u64, HttpError> {
+    let handle = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+    send_control(WriterMessage::Open(SinkConnection { handle, dir, name }))?;
+    Ok(handle)
+}
+
+pub fn write_line(handle: u64, line: String) -> Result<(), HttpError> {
+    let writer = get_writer()?;
+    let message = WriterMessage::Line(handle, line);
+
+    let message = match writer.policy {
+        QueuePolicy::Block => message,
+        policy => match writer.sender.try_send(message) {
+            Ok(()) => {
+                QUEUED.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            },
+            Err(TrySendError::Disconnected(_)) => return Err(disconnected()),
+            Err(TrySendError::Full(message)) => {
+                let keep = match policy {
+                    QueuePolicy::Sample(rate) => SAMPLED.fetch_add(1, Ordering::Relaxed).is_multiple_of(rate),
+                    _ => false
+                };
+
+                if !keep {
+                    DROPPED.fetch_add(1, Ordering::Relaxed);
+                    return Ok(());
+                }
+
+                message
+            }
+        }
+    };
+
+    send(writer, message)
+}
+
+pub fn close(handle: u64) -> Result<(), HttpError> {
+    send_control(WriterMessage::Close(handle))
+}
+
+// Blocks until everything queued so far is written and flushed
+pub fn shutdown(timeout: Duration) {
+    let (ack_sender, ack_receiver) = sync_channel(1);
+    if send_control(WriterMessage::Shutdown(ack_sender)).is_ok() {
+        ack_receiver.recv_timeout(timeout).ok();
+    }
+}
+
+pub fn get_dropped_count() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+// False before start and once the thread died, e.g. from a panicking sink
+pub fn is_running() -> bool {
+    THREAD.get().is_some_and(|thread| !thread.is_finished())
+}
+
+pub fn get_queue_depth() -> i64 {
+    QUEUED.load(Ordering::Relaxed).max(0)
+}
+
+fn get_writer() -> Result<&'static TranscriptWriter, HttpError> {
+    WRITER.get().ok_or_else(|| http_errors::msg::internal_server_error("Transcript writer was not started"))
+}
+
+// Opening and closing files is never dropped, whatever the policy
+fn send_control(message: WriterMessage) -> Result<(), HttpError> {
+    send(get_writer()?, message)
+}
+
+fn send(writer: &TranscriptWriter, message: WriterMessage) -> Result<(), HttpError> {
+    writer.sender.send(message).map_err(|_| disconnected())?;
+    QUEUED.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+
+fn disconnected() -> HttpError {
+    http_errors::msg::internal_server_error("Transcript writer has stopped")
+}
+
+// Writes everything that is queued, then flushes once the queue runs dry
+fn run(receiver: Receiver<WriterMessage>, mut sinks: FanOutSink) {
+    let mut reported = 0;
+
+    while let Ok(message) = receiver.recv() {
+        let mut pending = Some(message);
+        let mut acks = Vec::new();
+
+        while let Some(message) = pending.take().or_else(|| receiver.try_recv().ok()) {
+            QUEUED.fetch_sub(1, Ordering::Relaxed);
+
+            match message {
+                WriterMessage::Open(connection) => {
+                    if let Err(e) = sinks.open(&connection) {
+                        eprintln!("Failed to open transcript {}: {}", connection.name, e);
+                        metrics::add_transcript_write_failure();
+                    }
+                },
+                WriterMessage::Line(handle, line) => {
+                    if let Err(e) = sinks.write_line(handle, &line) {
+                        eprintln!("Failed to write transcript line: {}", e);
+                        metrics::add_transcript_write_failure();
+                    }
+                },
+                WriterMessage::Close(handle) => {
+                    if let Err(e) = sinks.close(handle) {
+                        eprintln!("Failed to close transcript: {}", e);
+                        metrics::add_transcript_write_failure();
+                    }
+                },
+                WriterMessage::Shutdown(ack) => acks.push(ack)
+            }
+        }
+
+        if let Err(e) = sinks.flush() {
+            eprintln!("Failed to flush transcripts: {}", e);
+            metrics::add_transcript_write_failure();
+        }
+
+        let dropped = DROPPED.load(Ordering::Relaxed);
+        if dropped != reported {
+            eprintln!("Transcript queue full, dropped {} lines ({} total)", dropped - reported, dropped);
+            reported = dropped;
+        }
+
+        for ack in acks {
+            ack.send(()).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcript_sink::MemorySink;
+
+    #[test]
+    fn parses_queue_policies() {
+        assert!(QueuePolicy::parse("block").unwrap() == QueuePolicy::Block);
+        assert!(QueuePolicy::parse("drop").unwrap() == QueuePolicy::Drop);
+        assert!(QueuePolicy::parse("sample:10").unwrap() == QueuePolicy::Sample(10));
+        assert_eq!(QueuePolicy::Sample(10).get_name(), "sample:10");
+
+        for value in ["sample:0", "sample:x", "sample", "wait"] {
+            assert!(QueuePolicy::parse(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn shutdown_waits_for_queued_lines() {
+        let memory = MemorySink::new();
+        let lines = memory.get_lines();
+        let mut sinks = FanOutSink::new();
+        sinks.add(Box::new(memory));
+
+        let (sender, receiver) = sync_channel(4);
+        let thread = thread::spawn(move || run(receiver, sinks));
+
+        sender.send(WriterMessage::Open(SinkConnection { handle: 7, dir: PathBuf::new(), name: String::from("test") })).unwrap();
+        for index in 0..10 {
+            sender.send(WriterMessage::Line(7, index.to_string())).unwrap();
+        }
+        sender.send(WriterMessage::Close(7)).unwrap();
+
+        let (ack_sender, ack_receiver) = sync_channel(1);
+        sender.send(WriterMessage::Shutdown(ack_sender)).unwrap();
+        ack_receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        let written: Vec<String> = lines.lock().unwrap().iter().map(|(handle, line)| format!("{}:{}", handle, line)).collect();
+        assert_eq!(written, (0..10).map(|index| format!("7:{}", index)).collect::<Vec<_>>());
+
+        drop(sender);
+        thread.join().unwrap();
+    }
+}
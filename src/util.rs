@@ -1,8 +1,11 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use chrono::{DateTime, TimeZone, Utc};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
 
-use crate::{str_util::Builder, transcript::Transcript, http_error::HttpError};
+use crate::{str_util::Builder, transcript::{Direction, Transcript, TranscriptEvent}, http_error::HttpError, headers::is_token};
 
 pub fn log_empty(ts: &Transcript, n: i32) -> Result<(), HttpError> {
     for _ in 0..n {
@@ -17,8 +20,16 @@ pub fn log_title(ts: &Transcript, title: &str) -> Result<(), HttpError> {
     ts.push(title)
 }
 
-pub fn read_line(ts: &mut Transcript, line: &str) -> Result<(), HttpError> {
-    ts.with_prefix("-->", |ts| ts.push(line))
+pub fn read_line(ts: &Transcript, line: &str, is_request_line: bool) -> Result<(), HttpError> {
+    if is_request_line {
+        return ts.record(TranscriptEvent::RequestLine(line));
+    }
+
+    // A line that does not parse is rejected right after, and redaction cannot tell what it holds, so only its size is kept
+    match line.split_once(':') {
+        Some((name, value)) if is_token(name) => ts.record(TranscriptEvent::Header(Direction::Incoming, name, value.trim())),
+        _ => ts.push(format!("<malformed header line, {} bytes>", line.len()).as_str())
+    }
 }
 
 #[allow(unused)]
@@ -50,5 +61,35 @@ pub fn get_time_str_from<T: TimeZone>(date_time: &DateTime<T>, date: bool, time:
         result.append(&formatted);
     }
 
-    return result.result;
+    result.result
+}
+
+// IMF-fixdate, the preferred format from RFC 9110 5.6.7
+#[allow(unused)]
+pub fn get_http_date(date_time: &DateTime<Utc>) -> String {
+    date_time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+pub fn parse_http_date(input: &str) -> Option<DateTime<Utc>> {
+    let input = input.trim();
+
+    // Recipients must also accept the obsolete RFC 850 and asctime formats
+    ["%a, %d %b %Y %H:%M:%S GMT", "%A, %d-%b-%y %H:%M:%S GMT", "%a %b %e %H:%M:%S %Y"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(input, format).ok())
+        .map(|naive| Utc.from_utc_datetime(&naive))
+}
+
+static ID_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// 16 hex digits, practically unique (hashed from time, a counter and the pid) and unpredictable across processes
+pub fn generate_id() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(nanos);
+    hasher.write_u64(ID_COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.write_u32(std::process::id());
+
+    format!("{:016x}", hasher.finish())
 }